@@ -0,0 +1,107 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A balking policy decides how likely an arriving customer is to join, given how many customers they see.
+///
+/// Customers that don't join balk: they leave straight away and never enter the system.
+pub trait BalkingPolicy {
+    /// The probability, `b_n` in *Fundamentals*, that a customer arriving to `in_system` customers joins.
+    fn join_probability(&self, in_system: u64) -> f64;
+}
+
+impl<F: Fn(u64) -> f64> BalkingPolicy for F {
+    fn join_probability(&self, in_system: u64) -> f64 {
+        self(in_system)
+    }
+}
+
+/// The balking policies that ship with the crate, so they can be chosen on the command line and recorded in the
+/// header of an event file.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Balking {
+    /// Discouraged arrivals, `b_n = 1 / (n + 1)`.
+    Discouraged,
+    /// `b_n = exp(-alpha * n)`.
+    Exponential { alpha: f64 },
+    /// `b_n = 1 - n / limit`, so no one joins once there are `limit` customers.
+    Linear { limit: u64 },
+    /// Everyone joins while there are fewer than `limit` customers, and no one does after that.
+    Threshold { limit: u64 },
+}
+
+#[derive(Debug, Error)]
+#[error("Couldn't parse balking policy '{0}', expected discouraged, exp:alpha, linear:limit or threshold:limit")]
+pub struct BalkingParsing(String);
+
+impl BalkingPolicy for Balking {
+    fn join_probability(&self, in_system: u64) -> f64 {
+        match self {
+            Balking::Discouraged => 1. / (in_system + 1) as f64,
+            Balking::Exponential { alpha } => (-alpha * in_system as f64).exp(),
+            Balking::Linear { limit } => (1. - in_system as f64 / *limit as f64).max(0.),
+            Balking::Threshold { limit } => if in_system < *limit { 1. } else { 0. },
+        }
+    }
+}
+
+impl fmt::Display for Balking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Balking::Discouraged => write!(f, "discouraged"),
+            Balking::Exponential { alpha } => write!(f, "exp:{}", alpha),
+            Balking::Linear { limit } => write!(f, "linear:{}", limit),
+            Balking::Threshold { limit } => write!(f, "threshold:{}", limit),
+        }
+    }
+}
+
+impl FromStr for Balking {
+    type Err = BalkingParsing;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || BalkingParsing(s.to_string());
+        let (name, parameter) = s.split_once(':').unwrap_or((s, ""));
+        match name.trim() {
+            "discouraged" if parameter.is_empty() => Ok(Balking::Discouraged),
+            "exp" => {
+                let alpha = parameter.parse::<f64>().map_err(|_| error())?;
+                if alpha >= 0. { Ok(Balking::Exponential { alpha }) } else { Err(error()) }
+            }
+            "linear" => {
+                let limit = parameter.parse::<u64>().map_err(|_| error())?;
+                if limit > 0 { Ok(Balking::Linear { limit }) } else { Err(error()) }
+            }
+            "threshold" => Ok(Balking::Threshold { limit: parameter.parse().map_err(|_| error())? }),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::balking::*;
+
+    #[test]
+    fn join_probabilities() {
+        assert_eq!(1., Balking::Discouraged.join_probability(0));
+        assert_eq!(0.25, Balking::Discouraged.join_probability(3));
+        assert_eq!(0.5, Balking::Linear { limit: 4 }.join_probability(2));
+        assert_eq!(0., Balking::Linear { limit: 4 }.join_probability(5));
+        assert_eq!(1., Balking::Threshold { limit: 2 }.join_probability(1));
+        assert_eq!(0., Balking::Threshold { limit: 2 }.join_probability(2));
+        assert_eq!((-1.5f64).exp(), Balking::Exponential { alpha: 0.5 }.join_probability(3));
+    }
+
+    #[test]
+    fn parse_round_trips_through_display() {
+        for s in ["discouraged", "exp:0.5", "linear:4", "threshold:2"] {
+            assert_eq!(s, s.parse::<Balking>().unwrap().to_string());
+        }
+        assert!("linear:0".parse::<Balking>().is_err());
+        assert!("always".parse::<Balking>().is_err());
+    }
+}
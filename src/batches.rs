@@ -0,0 +1,158 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use rand_distr::{Distribution, Geometric};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The distributions of the number of customers arriving together, the X in M^X/M/1.
+///
+/// Like `TimeDistribution`, every variant can be written to (and read back from) the header of an event file.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchSize {
+    /// Every batch has exactly `size` customers.
+    Fixed { size: u32 },
+    /// `P(X = k) = (1 - p)^(k - 1) p` for k from 1, so the mean is `1 / p`.
+    Geometric { p: f64 },
+    /// Every size from `min` to `max` inclusive is equally likely.
+    Uniform { min: u32, max: u32 },
+}
+
+#[derive(Debug, Error)]
+#[error("Couldn't parse batch size '{0}', expected fixed:size, geom:p or uniform:min,max")]
+pub struct BatchSizeParsing(String);
+
+impl BatchSize {
+    /// The mean batch size, E[X].
+    pub fn mean(&self) -> f64 {
+        match self {
+            BatchSize::Fixed { size } => *size as f64,
+            BatchSize::Geometric { p } => 1. / p,
+            BatchSize::Uniform { min, max } => (min + max) as f64 / 2.,
+        }
+    }
+
+    /// The second moment of the batch size, E[X^2].
+    pub fn second_moment(&self) -> f64 {
+        match self {
+            BatchSize::Fixed { size } => (size * size) as f64,
+            BatchSize::Geometric { p } => (2. - p) / (p * p),
+            BatchSize::Uniform { min, max } => {
+                (*min..=*max).map(|k| (k * k) as f64).sum::<f64>() / (max - min + 1) as f64
+            }
+        }
+    }
+
+    /// The probabilities of the sizes, `P(X = k)` at index `k - 1`. The geometric distribution is cut off where what
+    /// is left is below 1e-15.
+    pub fn probabilities(&self) -> Vec<f64> {
+        match self {
+            BatchSize::Fixed { size } => {
+                let mut probabilities = vec![0.; *size as usize];
+                probabilities[*size as usize - 1] = 1.;
+                probabilities
+            }
+            BatchSize::Geometric { p } => {
+                let mut probabilities = Vec::new();
+                let mut tail = 1.;
+                while tail > 1.0e-15 {
+                    probabilities.push(tail * p);
+                    tail *= 1. - p;
+                }
+                probabilities
+            }
+            BatchSize::Uniform { min, max } => {
+                let p = 1. / (max - min + 1) as f64;
+                (1..=*max).map(|k| if k >= *min { p } else { 0. }).collect()
+            }
+        }
+    }
+}
+
+impl Distribution<u32> for BatchSize {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u32 {
+        // The parameters are validated when parsed, so constructing the geometric distribution can't fail.
+        match self {
+            BatchSize::Fixed { size } => *size,
+            // rand_distr counts the failures before the first success, from 0.
+            BatchSize::Geometric { p } => Geometric::new(*p).unwrap().sample(rng) as u32 + 1,
+            BatchSize::Uniform { min, max } => rng.gen_range(*min..=*max),
+        }
+    }
+}
+
+impl fmt::Display for BatchSize {
+    /// Writes the batch size in the same format that `from_str` parses.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchSize::Fixed { size } => write!(f, "fixed:{}", size),
+            BatchSize::Geometric { p } => write!(f, "geom:{}", p),
+            BatchSize::Uniform { min, max } => write!(f, "uniform:{},{}", min, max),
+        }
+    }
+}
+
+impl FromStr for BatchSize {
+    type Err = BatchSizeParsing;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || BatchSizeParsing(s.to_string());
+        let (name, parameters) = s.split_once(':').unwrap_or((s, ""));
+        let parameters: Vec<&str> = parameters.split(',').map(str::trim).collect();
+        match (name.trim(), parameters.as_slice()) {
+            ("fixed", [size]) => {
+                let size = size.parse::<u32>().map_err(|_| error())?;
+                if size > 0 { Ok(BatchSize::Fixed { size }) } else { Err(error()) }
+            }
+            ("geom", [p]) => {
+                let p = p.parse::<f64>().map_err(|_| error())?;
+                if p > 0. && p <= 1. { Ok(BatchSize::Geometric { p }) } else { Err(error()) }
+            }
+            ("uniform", [min, max]) => {
+                let min = min.parse::<u32>().map_err(|_| error())?;
+                let max = max.parse::<u32>().map_err(|_| error())?;
+                if min > 0 && min <= max { Ok(BatchSize::Uniform { min, max }) } else { Err(error()) }
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batches::*;
+    use assert_approx_eq::assert_approx_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parse_round_trips_through_display() {
+        for s in ["fixed:3", "geom:0.25", "uniform:2,5"] {
+            let batch_size: BatchSize = s.parse().unwrap();
+            assert_eq!(s, batch_size.to_string());
+        }
+        assert!("fixed:0".parse::<BatchSize>().is_err());
+        assert!("geom:1.5".parse::<BatchSize>().is_err());
+        assert!("uniform:5,2".parse::<BatchSize>().is_err());
+    }
+
+    #[test]
+    fn moments_match_samples_and_probabilities() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for s in ["fixed:3", "geom:0.25", "uniform:2,5"] {
+            let batch_size: BatchSize = s.parse().unwrap();
+            let probabilities = batch_size.probabilities();
+            assert_approx_eq!(1., probabilities.iter().sum::<f64>(), 1.0e-12);
+            let mean: f64 = probabilities.iter().enumerate().map(|(i, p)| (i + 1) as f64 * p).sum();
+            let second_moment: f64 = probabilities.iter().enumerate().map(|(i, p)| ((i + 1) * (i + 1)) as f64 * p).sum();
+            assert_approx_eq!(batch_size.mean(), mean, 1.0e-12);
+            assert_approx_eq!(batch_size.second_moment(), second_moment, 1.0e-9);
+
+            let n = 100_000;
+            let sum: u64 = (0..n).map(|_| batch_size.sample(&mut rng) as u64).sum();
+            assert_approx_eq!(batch_size.mean(), sum as f64 / n as f64, 0.05);
+        }
+    }
+}
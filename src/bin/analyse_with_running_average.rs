@@ -2,7 +2,7 @@ use structopt::StructOpt;
 use std::fs::File;
 use std::io::{BufReader, BufRead};
 use std::convert::TryFrom;
-use queues::queues::{QueueEvent};
+use queues::queues::{EventType, QueueEvent};
 use queues::errors::ApplicationError;
 
 #[derive(StructOpt)]
@@ -45,6 +45,9 @@ fn main() -> Result<(), ApplicationError> {
     for line in reader.lines() {
         let line = line?;
         let counts = QueueEvent::try_from(line.clone()).map_err(|e| ApplicationError::LineFormatError(e, line))?;
+        if counts.event_type() != EventType::Departure {
+            continue;
+        }
         if let Some(customer) = counts.served_customer() {
             if customer.time_of_departure() > beginning_of_window + cli.window {
                 if n_of_waits_in_window > 0 {
@@ -1,9 +1,18 @@
-use std::io::{Error, Write, stdout};
+use std::io::{BufReader, Error, ErrorKind, Write, stdout};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use structopt::StructOpt;
 
-use queues::queues::{Queue, QueueEvent};
+use queues::balking::Balking;
+use queues::batches::BatchSize;
+use queues::customer::CustomerClass;
+use queues::disciplines::{Discipline, Preemption, QueueDiscipline, Sharing};
+use queues::profiles::{Interpolation, ProfileError, RateProfile};
+use queues::distributions::{DistributionError, TimeDistribution};
+use queues::interruptions::{Interruption, VacationPolicy};
+use queues::queues::{Parameters, Queue, WarmUp};
+use queues::servers::IdleServerPolicy;
+use queues::staffing::{ShiftEnd, StaffingError, StaffingSchedule};
 use std::fs::File;
 
 const MINUTE: f64 = 60.;
@@ -25,8 +34,134 @@ struct Cli {
     customer_service_time_in_minutes: f64,
     /// The number of servers.
     #[structopt(short, long, default_value = "1")]
-    servers: u8,
-    /// Empties the queue after the number of samples is finished. Therefore, all arrivals are served.
+    servers: u32,
+    /// The distribution of interarrival times in seconds, e.g. "exp:0.001" or "erlang:3,0.001".
+    /// Overrides customers-per-hour, which otherwise gives exponential interarrival times.
+    #[structopt(long)]
+    arrival_distribution: Option<TimeDistribution>,
+    /// The distribution of service times in seconds, e.g. "det:600", "lognormal:6,0.5" or "hyperexp:0.9,0.01,0.1,0.001".
+    /// Overrides customer-service-time-in-minutes, which otherwise gives exponential service times.
+    #[structopt(long)]
+    service_distribution: Option<TimeDistribution>,
+    /// A file of the arrival rate over time, one "time rate" point per line in seconds and customers per second,
+    /// the first at time 0. Overrides customers-per-hour and arrival-distribution: arrivals are a Poisson process
+    /// whose rate follows the profile.
+    #[structopt(long, parse(from_os_str))]
+    arrival_profile: Option<std::path::PathBuf>,
+    /// How the rate of the arrival profile goes between its points: step (piecewise constant) or linear.
+    #[structopt(long, default_value = "step")]
+    profile_interpolation: Interpolation,
+    /// Repeats the arrival profile every this many seconds, e.g. 86400 for a daily curve.
+    #[structopt(long)]
+    profile_period: Option<f64>,
+    /// A file of the number of servers on duty over time, one "time servers" point per line in seconds, the first at
+    /// time 0. Overrides servers, which becomes the most on duty at once.
+    #[structopt(long, parse(from_os_str))]
+    staffing: Option<std::path::PathBuf>,
+    /// Repeats the staffing schedule every this many seconds, e.g. 86400 for a daily rota.
+    #[structopt(long)]
+    staffing_period: Option<f64>,
+    /// What a server going off duty does with its customer: finish serving them, or requeue them at the front of the
+    /// queue to resume with another server.
+    #[structopt(long, default_value = "finish")]
+    shift_end: ShiftEnd,
+    /// The distribution of service times in seconds at one server, when the servers aren't identical, e.g.
+    /// "exp:0.002". Give it once for each server, in order. A customer's service time then depends on their server,
+    /// and the service distributions of the classes are ignored. Not with the sjf, ljf or srpt disciplines.
+    #[structopt(long = "server-service-distribution")]
+    server_service_distributions: Vec<TimeDistribution>,
+    /// Another class of customers sharing the servers, as "arrival_distribution/service_distribution" in seconds,
+    /// e.g. "exp:0.0005/det:300". Can be given several times. The class from the options above is class 0.
+    #[structopt(long = "class")]
+    classes: Vec<CustomerClass<TimeDistribution, TimeDistribution>>,
+    /// The seed of the random number generator. The same seed and parameters reproduce the same events.
+    /// When not given, a random seed is chosen and recorded in the header.
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// The queue discipline, which decides who is served next: fcfs, lcfs, siro, sjf, ljf, srpt or priority. Under
+    /// priority, lower numbered classes are served first. Under srpt, shortest remaining processing time first, an
+    /// arriving customer preempts the customer in service with the most service left, if they need less.
+    #[structopt(long, default_value = "fcfs")]
+    discipline: Discipline,
+    /// Whether arriving customers interrupt the service of customers of lower priority: none, resume or repeat.
+    /// Requires the priority discipline. Under srpt it is always resume.
+    #[structopt(long, default_value = "none")]
+    preemption: Preemption,
+    /// How the servers divide their time between customers: "ps" for processor sharing, where a single server serves
+    /// everyone in the system at once, or "rr:quantum" for round robin, where customers are served for at most the
+    /// quantum in seconds at a time. Each server serves one customer until they are done when not given.
+    #[structopt(long)]
+    sharing: Option<Sharing>,
+    /// Which idle server serves the next customer when several are: lowest (numbered), random, longest-idle,
+    /// fastest (by mean service time) or round-robin.
+    #[structopt(long, default_value = "lowest")]
+    server_selection: IdleServerPolicy,
+    /// The number of customers, N, when the population is finite, like machines queueing for repair. Each customer
+    /// outside the system arrives after an interarrival time, so customers-per-hour is then per customer.
+    #[structopt(short = "N", long)]
+    population: Option<u32>,
+    /// The number of customers arriving together, when they arrive in batches: "fixed:size", "geom:p" or
+    /// "uniform:min,max". The interarrival times are then between batches.
+    #[structopt(long)]
+    batch_size: Option<BatchSize>,
+    /// The most waiting customers a server takes at once, serving them together in one service time.
+    #[structopt(long)]
+    bulk_service: Option<u32>,
+    /// The maximum number of customers in the system, K. Arrivals finding it full are blocked.
+    #[structopt(short = "k", long)]
+    capacity: Option<u32>,
+    /// The distribution of how long customers will wait in seconds before abandoning the queue, e.g. "exp:0.001".
+    /// Customers never abandon when not given.
+    #[structopt(long)]
+    patience_distribution: Option<TimeDistribution>,
+    /// How likely arriving customers are to join given the number in the system: "discouraged" (1 / (n + 1)),
+    /// "exp:alpha", "linear:limit" or "threshold:limit". Everyone joins when not given.
+    #[structopt(long)]
+    balking: Option<Balking>,
+    /// The distribution of the time in seconds between the attempts of a customer who found every server busy, e.g.
+    /// "exp:0.02". They join an orbit instead of the queue and retry until they find a server free. Customers wait in
+    /// the queue when not given.
+    #[structopt(long)]
+    retrial_distribution: Option<TimeDistribution>,
+    /// The probability that a served customer fails and rejoins the back of the queue for rework, with a new service
+    /// time, instead of leaving. Give it once for every class, or once for each class in order, class 0 first.
+    #[structopt(long = "feedback-probability")]
+    feedback_probabilities: Vec<f64>,
+    /// The distribution of the vacations in seconds a server takes when it finishes and finds no one waiting, e.g.
+    /// "exp:0.01". Servers never take vacations when not given.
+    #[structopt(long)]
+    vacation_distribution: Option<TimeDistribution>,
+    /// What a server does after a vacation if there is still no one waiting: single (waits idle) or multiple (takes
+    /// another vacation).
+    #[structopt(long, default_value = "single")]
+    vacation_policy: VacationPolicy,
+    /// The distribution of the time in seconds a server serves before it breaks down, e.g. "exp:0.0001". Servers
+    /// never break down when not given.
+    #[structopt(long, requires = "repair-distribution")]
+    time_to_failure_distribution: Option<TimeDistribution>,
+    /// The distribution of the repair times in seconds of a server that broke down, e.g. "lognormal:6,0.5".
+    #[structopt(long, requires = "time-to-failure-distribution")]
+    repair_distribution: Option<TimeDistribution>,
+    /// What happens to the service of the customers of a server that breaks down: resume (where it left off) or
+    /// restart (from the beginning) after the repair.
+    #[structopt(long, default_value = "resume")]
+    interruption: Interruption,
+    /// The time in seconds after which no one is admitted, like a service desk closing. The simulation ends when
+    /// everyone admitted has been served, if that is before the number of samples.
+    #[structopt(long)]
+    closing_time: Option<f64>,
+    /// The simulated time in seconds to stop at, e.g. 28800 for 8 hours, if that is before the number of samples.
+    #[structopt(long)]
+    horizon: Option<f64>,
+    /// Leaves out the events up to this time in seconds, so that the statistics aren't biased by the queue starting
+    /// empty. The end of the warm-up is recorded in the header.
+    #[structopt(long, conflicts_with = "warm-up-events")]
+    warm_up: Option<f64>,
+    /// Leaves out this many events at the start, like warm-up but counting events rather than time.
+    #[structopt(long)]
+    warm_up_events: Option<u64>,
+    /// Empties the queue after the number of samples is finished: no one else is admitted and everyone in the
+    /// system is served. Therefore, all arrivals are served.
     #[structopt(short, long)]
     empty: bool,
 }
@@ -40,44 +175,287 @@ impl Cli {
         1. / (self.customer_service_time_in_minutes * MINUTE)
     }
 
+    /// The interarrival distribution, exponential at the rate of customers per hour unless one was given. The rate
+    /// isn't parsed as a distribution, so it is only validated here.
+    pub fn arrival_distribution(&self) -> Result<TimeDistribution, DistributionError> {
+        self.arrival_distribution.clone().unwrap_or(TimeDistribution::Exponential { rate: self.lambda() }).validate()
+    }
+
+    /// The service distribution, exponential with the mean service time unless one was given.
+    pub fn service_distribution(&self) -> Result<TimeDistribution, DistributionError> {
+        self.service_distribution.clone().unwrap_or(TimeDistribution::Exponential { rate: self.mu() }).validate()
+    }
+
+    /// The arrival profile from its file, if one was given.
+    pub fn arrival_profile(&self) -> Result<Option<RateProfile>, ProfileError> {
+        let path = match &self.arrival_profile {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let profile = RateProfile::read(BufReader::new(File::open(path)?), self.profile_interpolation)?;
+        match self.profile_period {
+            Some(period) => profile.with_period(period).map(Some),
+            None => Ok(Some(profile)),
+        }
+    }
+
+    /// The staffing schedule from its file, if one was given.
+    pub fn staffing(&self) -> Result<Option<StaffingSchedule>, StaffingError> {
+        let path = match &self.staffing {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let staffing = StaffingSchedule::read(BufReader::new(File::open(path)?))?.with_shift_end(self.shift_end);
+        match self.staffing_period {
+            Some(period) => staffing.with_period(period).map(Some),
+            None => Ok(Some(staffing)),
+        }
+    }
+
+    /// The mean service time at each server, for choosing the fastest.
+    pub fn mean_service_times(&self, service_distribution: &TimeDistribution) -> Vec<f64> {
+        if self.server_service_distributions.is_empty() {
+            vec![service_distribution.mean(); self.servers as usize]
+        } else {
+            self.server_service_distributions.iter().map(TimeDistribution::mean).collect()
+        }
+    }
+}
+
+/// Rejects a combination of options, unless `condition` holds.
+fn require(condition: bool, message: &str) -> Result<(), Error> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::InvalidInput, message))
+    }
 }
 
 fn main() -> Result<(), Error> {
     let terminate = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&terminate))?;
 
-    let cli: Cli = Cli::from_args();
+    let mut cli: Cli = Cli::from_args();
+    let arrival_distribution = cli.arrival_distribution().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let service_distribution = cli.service_distribution().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let staffing = cli.staffing().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    if let Some(staffing) = &staffing {
+        cli.servers = staffing.max_servers();
+    }
+    require(cli.preemption == Preemption::None || cli.discipline == Discipline::Priority,
+            "Preemption is by priority, so it requires the priority discipline.")?;
+    require(!cli.discipline.by_service_time() || cli.server_service_distributions.is_empty(),
+            "A discipline by service time needs the customers' service times, not the servers'.")?;
+    if cli.discipline == Discipline::Srpt {
+        cli.preemption = Preemption::Resume;
+    }
+    require(cli.population.is_none() || cli.classes.is_empty(), "A finite population only has one class of customers.")?;
+    require(cli.population.is_none() || cli.batch_size.is_none(), "The customers of a finite population arrive one at a time.")?;
+    require(cli.bulk_service.is_none() || cli.preemption == Preemption::None, "Customers served in bulk can't be preempted.")?;
+    require(cli.retrial_distribution.is_none() || cli.preemption == Preemption::None, "Customers who retry can't preempt.")?;
+    require(cli.retrial_distribution.is_none() || cli.bulk_service.is_none(), "Customers who retry are served one at a time.")?;
+    require(cli.capacity.is_none_or(|capacity| capacity >= cli.servers),
+            "The capacity must leave room for every server to be busy.")?;
+    require(cli.bulk_service != Some(0), "A server takes at least one customer at a time.")?;
+    require(cli.feedback_probabilities.iter().all(|p| (0. ..1.).contains(p)),
+            "Customers must leave eventually, so feedback probabilities are at least zero and below one.")?;
+    require(cli.feedback_probabilities.len() <= 1 || cli.feedback_probabilities.len() == cli.classes.len() + 1,
+            "Give a feedback probability for every class, or one for all of them.")?;
+    require(cli.server_service_distributions.is_empty() || cli.server_service_distributions.len() == cli.servers as usize,
+            "Give a server service distribution for every server, or none.")?;
+    require(cli.sharing.is_none() || cli.preemption == Preemption::None, "Servers that share their time can't be preempted.")?;
+    require(cli.sharing.is_none() || cli.retrial_distribution.is_none(), "Customers who retry don't share servers.")?;
+    require(cli.sharing.is_none() || cli.bulk_service.is_none(), "Servers that share their time serve customers one at a time.")?;
+    require(cli.sharing.is_none() || cli.time_to_failure_distribution.is_none(), "Servers that share their time don't break down.")?;
+    if cli.sharing == Some(Sharing::ProcessorSharing) {
+        require(cli.servers == 1 && cli.staffing.is_none(), "Processor sharing is for a single server.")?;
+        require(cli.vacation_distribution.is_none(), "A shared processor is always there to serve.")?;
+        require(cli.server_service_distributions.is_empty(), "A shared processor takes the service times of the classes.")?;
+    }
+    let interrupted = cli.vacation_distribution.is_some() || cli.time_to_failure_distribution.is_some();
+    require(!interrupted || cli.staffing.is_none(), "Servers on a staffing schedule don't take vacations or break down.")?;
+    require(!interrupted || cli.preemption == Preemption::None, "Servers that take vacations or break down can't be preempted.")?;
 
-    let queue = Queue::new_exp_exp(cli.lambda(), cli.mu(), cli.servers);
+    require(cli.population.is_none() || cli.arrival_profile.is_none(),
+            "The customers of a finite population arrive after their interarrival times.")?;
+    let arrival_profile = cli.arrival_profile().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+    let seed = cli.seed.unwrap_or_else(rand::random);
+    let mut queue = Queue::seeded(arrival_distribution.clone(), service_distribution.clone(), cli.servers, seed)
+        .with_discipline(cli.discipline)
+        .with_preemption(cli.preemption)
+        .with_server_selection(cli.server_selection.selection(&cli.mean_service_times(&service_distribution)));
+    if !cli.server_service_distributions.is_empty() {
+        queue = queue.with_server_service_distributions(cli.server_service_distributions.clone());
+    }
+    if let Some(staffing) = &staffing {
+        queue = queue.with_staffing(staffing.clone());
+    }
+    if let Some(vacation_distribution) = &cli.vacation_distribution {
+        queue = queue.with_vacations(cli.vacation_policy, vacation_distribution.clone());
+    }
+    if let (Some(time_to_failure), Some(repair)) = (&cli.time_to_failure_distribution, &cli.repair_distribution) {
+        queue = queue.with_breakdowns(time_to_failure.clone(), repair.clone(), cli.interruption);
+    }
+    for class in &cli.classes {
+        queue = queue.with_class(class.arrival_distribution.clone(), class.service_distribution.clone());
+    }
+    if let Some(population) = cli.population {
+        queue = queue.with_population(population);
+    }
+    if let Some(batch_size) = cli.batch_size {
+        queue = queue.with_batch_arrivals(batch_size);
+    }
+    if let Some(arrival_profile) = &arrival_profile {
+        queue = queue.with_arrival_profile(arrival_profile.clone());
+    }
+    if let Some(bulk_service) = cli.bulk_service {
+        queue = queue.with_bulk_service(bulk_service);
+    }
+    if let Some(capacity) = cli.capacity {
+        queue = queue.with_capacity(capacity);
+    }
+    if let Some(patience_distribution) = &cli.patience_distribution {
+        queue = queue.with_patience(patience_distribution.clone());
+    }
+    if let Some(balking) = cli.balking {
+        queue = queue.with_balking(balking);
+    }
+    if let Some(retrial_distribution) = &cli.retrial_distribution {
+        queue = queue.with_retrials(retrial_distribution.clone());
+    }
+    if !cli.feedback_probabilities.is_empty() {
+        queue = queue.with_feedback(cli.feedback_probabilities.clone());
+    }
+    if let Some(sharing) = cli.sharing {
+        queue = queue.with_sharing(sharing);
+    }
+    if cli.discipline == Discipline::Srpt {
+        queue = queue.with_srpt();
+    }
+    if let Some(closing_time) = cli.closing_time {
+        queue = queue.with_closing_time(closing_time);
+    }
 
     if let Some(path) = &cli.path {
         let mut file = File::create(path).unwrap();
-        simulate(&mut file, terminate, cli, queue)?;
+        simulate(&mut file, terminate, cli, seed, arrival_profile, staffing, queue)?;
     } else {
         let stdout = stdout();
         let mut stdout = stdout.lock();
-        simulate(&mut stdout, terminate, cli, queue)?;
+        simulate(&mut stdout, terminate, cli, seed, arrival_profile, staffing, queue)?;
     }
 
     Ok(())
 }
 
-fn simulate<OUT: Write>(out: &mut OUT, terminate: Arc<AtomicBool>, cli: Cli, mut queue: Queue) -> Result<(), Error>{
+fn simulate<OUT: Write>(
+    out: &mut OUT,
+    terminate: Arc<AtomicBool>,
+    cli: Cli,
+    seed: u64,
+    arrival_profile: Option<RateProfile>,
+    staffing: Option<StaffingSchedule>,
+    mut queue: Queue<TimeDistribution, TimeDistribution>,
+) -> Result<(), Error> {
     let mut samples = 0;
-    writeln!(out, "# {{\"lambda\":{}, \"mu\":{}}}", cli.lambda(), cli.mu())?;
-    QueueEvent::dump_line_header(out).unwrap();
-    while !terminate.load(Ordering::Relaxed) && samples < cli.samples {
+    let arrival_distribution = cli.arrival_distribution().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let service_distribution = cli.service_distribution().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut parameters = Parameters::new(arrival_distribution, service_distribution, cli.servers)
+        .with_seed(seed)
+        .with_discipline(cli.discipline)
+        .with_preemption(cli.preemption)
+        .with_server_selection(cli.server_selection);
+    if !cli.server_service_distributions.is_empty() {
+        parameters = parameters.with_server_service_distributions(cli.server_service_distributions.clone());
+    }
+    if let Some(staffing) = staffing {
+        parameters = parameters.with_staffing(staffing);
+    }
+    if let Some(vacation_distribution) = &cli.vacation_distribution {
+        parameters = parameters.with_vacations(cli.vacation_policy, vacation_distribution.clone());
+    }
+    if let (Some(time_to_failure), Some(repair)) = (&cli.time_to_failure_distribution, &cli.repair_distribution) {
+        parameters = parameters.with_breakdowns(time_to_failure.clone(), repair.clone(), cli.interruption);
+    }
+    for class in &cli.classes {
+        parameters = parameters.with_class(class.clone());
+    }
+    if let Some(population) = cli.population {
+        parameters = parameters.with_population(population);
+    }
+    if let Some(batch_size) = cli.batch_size {
+        parameters = parameters.with_batch_size(batch_size);
+    }
+    if let Some(arrival_profile) = arrival_profile {
+        parameters = parameters.with_arrival_profile(arrival_profile);
+    }
+    if let Some(bulk_service) = cli.bulk_service {
+        parameters = parameters.with_bulk_service(bulk_service);
+    }
+    if let Some(capacity) = cli.capacity {
+        parameters = parameters.with_capacity(capacity);
+    }
+    if let Some(patience_distribution) = &cli.patience_distribution {
+        parameters = parameters.with_patience(patience_distribution.clone());
+    }
+    if let Some(balking) = cli.balking {
+        parameters = parameters.with_balking(balking);
+    }
+    if let Some(retrial_distribution) = &cli.retrial_distribution {
+        parameters = parameters.with_retrials(retrial_distribution.clone());
+    }
+    if !cli.feedback_probabilities.is_empty() {
+        parameters = parameters.with_feedback(cli.feedback_probabilities.clone());
+    }
+    if let Some(sharing) = cli.sharing {
+        parameters = parameters.with_sharing(sharing);
+    }
+    if let Some(closing_time) = cli.closing_time {
+        parameters = parameters.with_closing_time(closing_time);
+    }
+    if let Some(horizon) = cli.horizon {
+        parameters = parameters.with_horizon(horizon);
+    }
+    if let Some(warm_up) = warm_up(&cli, &mut queue) {
+        parameters = parameters.with_warm_up(warm_up);
+    }
+    parameters.dump_header(out)?;
+    let horizon = cli.horizon.unwrap_or(f64::INFINITY);
+    while !terminate.load(Ordering::Relaxed)
+        && samples < cli.samples
+        && queue.peek_next_event_time().is_some_and(|time| time <= horizon) {
         samples += 1;
 
         let event = queue.next_event();
         event.dump_line(out).unwrap();
     }
     if cli.empty {
-        let emptied = queue.empty();
-        for event in emptied {
+        for event in queue.drain() {
             event.dump_line(out).unwrap();
         }
     }
 
     Ok(())
+}
+
+/// Simulates the warm-up without writing its events, and returns where it ended.
+fn warm_up(cli: &Cli, queue: &mut Queue<TimeDistribution, TimeDistribution>) -> Option<WarmUp> {
+    let mut events = 0;
+    let time = if let Some(warm_up) = cli.warm_up {
+        while queue.peek_next_event_time().is_some_and(|time| time <= warm_up) {
+            queue.next_event();
+            events += 1;
+        }
+        warm_up
+    } else {
+        let mut time = 0.;
+        while events < cli.warm_up_events? && !queue.is_finished() {
+            time = queue.next_event().time();
+            events += 1;
+        }
+        time
+    };
+
+    Some(WarmUp { time, events, in_system: queue.in_system() as u64, in_orbit: queue.in_orbit() as u64 })
 }
\ No newline at end of file
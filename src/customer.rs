@@ -1,14 +1,79 @@
+use std::str::FromStr;
+
 use rand::Rng;
 use rand_distr::Distribution;
+use serde::{Deserialize, Serialize};
+
+use crate::distributions::{DistributionError, TimeDistribution};
+
+/// A type of customer, with its own stream of arrivals and its own service times. The streams of all the classes
+/// are superposed to make the arrivals of a queue.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CustomerClass<DA, DS> {
+    pub arrival_distribution: DA,
+    pub service_distribution: DS,
+}
+
+impl<DA, DS> CustomerClass<DA, DS> {
+    pub fn new(arrival_distribution: DA, service_distribution: DS) -> CustomerClass<DA, DS> {
+        CustomerClass { arrival_distribution, service_distribution }
+    }
+}
+
+impl FromStr for CustomerClass<TimeDistribution, TimeDistribution> {
+    type Err = DistributionError;
+
+    /// Parses `arrival/service`, where both are distributions as parsed by `TimeDistribution`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (arrival, service) = s.split_once('/')
+            .ok_or_else(|| DistributionError::ParameterParsing(s.to_string()))?;
+        Ok(CustomerClass::new(arrival.parse()?, service.parse()?))
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct ArrivingCustomer {
+    /// The sequential ID the queue gives customers as they arrive, so their events can be matched up.
+    id: u64,
+    /// The ID of the batch the customer arrived in, shared by the customers arriving together.
+    batch: u64,
+    /// The index of the customer's class, which is zero unless there are several.
+    class: usize,
     interarrival_time: f64,
     time_of_arrival: f64,
     service_time: f64,
+    /// When the customer gives up waiting and abandons the queue, infinite when they are patient.
+    time_of_abandonment: f64,
+    /// The service still owed to the customer, which is less than `service_time` if their service was preempted.
+    remaining_service: f64,
+    /// When the customer first started service, if they have been preempted since.
+    time_of_first_service_start: Option<f64>,
+    /// How many times the customer has tried to get a server, counting their arrival as the first.
+    attempts: u32,
+    /// Which pass through the queue this is, more than one when the customer was fed back for rework.
+    visit: u32,
+    /// When the customer first arrived, before any passes they were fed back for.
+    time_of_entry: f64,
 }
 
 impl ArrivingCustomer {
+    fn arriving(class: usize, interarrival_time: f64, time_of_arrival: f64, service_time: f64) -> ArrivingCustomer {
+        ArrivingCustomer {
+            id: 0,
+            batch: 0,
+            class,
+            interarrival_time,
+            time_of_arrival,
+            service_time,
+            time_of_abandonment: f64::INFINITY,
+            remaining_service: service_time,
+            time_of_first_service_start: None,
+            attempts: 1,
+            visit: 1,
+            time_of_entry: time_of_arrival,
+        }
+    }
+
     /// Generate the first customer in a simulation. This is not really dependent on anything
     /// but the service distribution since an empty queue means they will be served right away.
     ///
@@ -23,20 +88,58 @@ impl ArrivingCustomer {
         let a_0 = 0.;
         let s_0 = service_time_distribution.sample(rng);
 
-        ArrivingCustomer {
-            interarrival_time: t_0,
-            time_of_arrival: a_0,
-            service_time: s_0,
-        }
+        ArrivingCustomer::arriving(0, t_0, a_0, s_0)
+    }
+
+    /// Generate the first customer of a class that starts arriving at t=0. Unlike `first`, they don't arrive right
+    /// away, since the stream of another class has already supplied the customer arriving at t=0.
+    ///
+    /// # Arguments
+    /// * `rng` The random number generator to sample with. Obviously mutable.
+    /// * `interarrival_time_distribution` The distribution of the interarrival times of the class.
+    /// * `service_time_distribution` The distribution of the service times of the class.
+    /// * `class` The index of the class.
+    pub fn first_of_class<R: Rng + ?Sized, DA: Distribution<f64>, DS: Distribution<f64>>(
+        rng: &mut R,
+        interarrival_time_distribution: &DA,
+        service_time_distribution: &DS,
+        class: usize,
+    ) -> ArrivingCustomer {
+        let t_0 = interarrival_time_distribution.sample(rng);
+        let s_0 = service_time_distribution.sample(rng);
+
+        ArrivingCustomer::arriving(class, t_0, t_0, s_0)
+    }
+
+    /// Generate a customer of a class arriving one interarrival time after `time`, as when a machine that was
+    /// repaired at `time` breaks down again after running for a while.
+    ///
+    /// # Arguments
+    /// * `rng` The random number generator to sample with. Obviously mutable.
+    /// * `interarrival_time_distribution` The distribution of the times until the customer arrives.
+    /// * `service_time_distribution` The distribution of the service times of the class.
+    /// * `class` The index of the class.
+    /// * `time` The time from which the interarrival time runs.
+    pub fn after<R: Rng + ?Sized, DA: Distribution<f64>, DS: Distribution<f64>>(
+        rng: &mut R,
+        interarrival_time_distribution: &DA,
+        service_time_distribution: &DS,
+        class: usize,
+        time: f64,
+    ) -> ArrivingCustomer {
+        let t_n = interarrival_time_distribution.sample(rng);
+        let s_n = service_time_distribution.sample(rng);
+
+        ArrivingCustomer::arriving(class, t_n, time + t_n, s_n)
     }
 
-    /// Generates a following customer when the queue discipline is first come first served
+    /// Generates a following customer, of the same class as the previous one, when the queue discipline is first come first served
     /// and there is a single server.
     ///
     /// # Arguments
     /// * `rng` The random number generator to sample with. Obviously mutable.
     /// * `interarrival_time_distribution` The distribution of the interarrival times, the times
-    /// between arrivals.
+    ///   between arrivals.
     /// * `service_time_distribution` The distribution of the service times.
     pub fn next_1_fcfs<R: Rng + ?Sized, DA: Distribution<f64>, DS: Distribution<f64>>(
         rng: &mut R,
@@ -48,20 +151,59 @@ impl ArrivingCustomer {
         let a_n = previous.a() + t_n;
         let s_n = service_time_distribution.sample(rng);
 
-        ArrivingCustomer {
-            interarrival_time: t_n,
-            time_of_arrival: a_n,
-            service_time: s_n,
-        }
+        ArrivingCustomer::arriving(previous.class, t_n, a_n, s_n)
+    }
+
+    /// Generates the next customer of the same batch as the previous one, arriving at the same time.
+    ///
+    /// # Arguments
+    /// * `rng` The random number generator to sample with. Obviously mutable.
+    /// * `service_time_distribution` The distribution of the service times.
+    pub fn next_in_batch<R: Rng + ?Sized, DS: Distribution<f64>>(
+        rng: &mut R,
+        service_time_distribution: &DS,
+        previous: ArrivingCustomer,
+    ) -> ArrivingCustomer {
+        let s_n = service_time_distribution.sample(rng);
+
+        ArrivingCustomer { batch: previous.batch, ..ArrivingCustomer::arriving(previous.class, 0., previous.a(), s_n) }
     }
 
     /// The customer that never arrives, but if they do, they will never finish being served.
     pub fn never() -> ArrivingCustomer {
-        ArrivingCustomer {
-            interarrival_time: f64::INFINITY,
-            time_of_arrival: f64::INFINITY,
-            service_time: f64::INFINITY,
-        }
+        ArrivingCustomer::arriving(0, f64::INFINITY, f64::INFINITY, f64::INFINITY)
+    }
+
+    /// The same customer, with the ID the queue gives them when they arrive.
+    pub fn with_id(self, id: u64) -> ArrivingCustomer {
+        ArrivingCustomer { id, ..self }
+    }
+
+    /// The same customer, in the batch of customers arriving together that the queue gives them.
+    pub fn with_batch(self, batch: u64) -> ArrivingCustomer {
+        ArrivingCustomer { batch, ..self }
+    }
+
+    /// The same customer, but they will abandon the queue if they are still waiting `patience` after `time`, when
+    /// they join (or rejoin) it. Customers are infinitely patient unless given a patience.
+    pub fn with_patience(self, time: f64, patience: f64) -> ArrivingCustomer {
+        ArrivingCustomer { time_of_abandonment: time + patience, ..self }
+    }
+
+    /// The same customer, but with a different service time, as when it depends on the server rather than the
+    /// customer. Only for customers who haven't started service.
+    pub fn with_service_time(self, service_time: f64) -> ArrivingCustomer {
+        ArrivingCustomer { service_time, remaining_service: service_time, ..self }
+    }
+
+    /// The same customer, trying again for a server after finding them all busy.
+    pub fn retry(self) -> ArrivingCustomer {
+        ArrivingCustomer { attempts: self.attempts + 1, ..self }
+    }
+
+    /// Whether the customer has been served before, and is waiting to resume (or repeat) after being preempted.
+    pub fn was_preempted(&self) -> bool {
+        self.time_of_first_service_start.is_some()
     }
 
     /// Returns `A_n` the arrival time of this customer.
@@ -72,13 +214,55 @@ impl ArrivingCustomer {
     pub fn arrival_time(&self) -> f64 {
         self.time_of_arrival
     }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    pub fn class(&self) -> usize {
+        self.class
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn visit(&self) -> u32 {
+        self.visit
+    }
+
+    /// Returns `s_n` the time it will take to serve this customer.
+    pub fn service_time(&self) -> f64 {
+        self.service_time
+    }
+
+    /// The service still owed to this customer. The same as the service time unless they were preempted.
+    pub fn remaining_service(&self) -> f64 {
+        self.remaining_service
+    }
+
+    /// The time at which this customer gives up waiting, if they haven't started service by then.
+    pub fn abandonment_time(&self) -> f64 {
+        self.time_of_abandonment
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct Customer {
+    pub(crate) id: u64,
+    pub(crate) batch: u64,
+    pub(crate) class: usize,
     pub(crate) interarrival_time: f64,
     pub(crate) time_of_arrival: f64,
     pub(crate) service_time: f64,
+    pub(crate) attempts: u32,
+    pub(crate) visit: u32,
+    /// When the customer first arrived, so their sojourn counts every pass through the queue.
+    pub(crate) time_of_entry: f64,
 
     pub(crate) time_of_service_start: f64,
     pub(crate) time_of_departure: f64,
@@ -88,18 +272,148 @@ pub struct Customer {
 }
 
 impl Customer {
+    /// The customer starts (or resumes) service at `time_of_service_start` and will depart once they have received
+    /// their remaining service, unless they are preempted first.
+    ///
+    /// The wait in queue is all the time in the system not spent being served, so for a preempted customer it
+    /// includes the time spent waiting to resume, and under preemptive-repeat the service that was lost.
     pub fn start_service(arriving_customer: ArrivingCustomer, time_of_service_start: f64) -> Customer {
+        let time_of_departure = time_of_service_start + arriving_customer.remaining_service;
+        let wait_in_system = time_of_departure - arriving_customer.time_of_arrival;
         Customer {
+            id: arriving_customer.id,
+            batch: arriving_customer.batch,
+            class: arriving_customer.class,
             interarrival_time: arriving_customer.interarrival_time,
             time_of_arrival: arriving_customer.time_of_arrival,
             service_time: arriving_customer.service_time,
-            time_of_service_start,
-            time_of_departure: time_of_service_start + arriving_customer.service_time,
-            wait_in_queue: time_of_service_start - arriving_customer.time_of_arrival,
-            wait_in_system: time_of_service_start - arriving_customer.time_of_arrival + arriving_customer.service_time,
+            attempts: arriving_customer.attempts,
+            visit: arriving_customer.visit,
+            time_of_entry: arriving_customer.time_of_entry,
+            time_of_service_start: arriving_customer.time_of_first_service_start.unwrap_or(time_of_service_start),
+            time_of_departure,
+            wait_in_queue: wait_in_system - arriving_customer.service_time,
+            wait_in_system,
+        }
+    }
+
+    /// The customer is interrupted at `time` by a customer of higher priority and goes back to waiting.
+    ///
+    /// # Arguments
+    /// * `time` The time the customer is preempted.
+    /// * `repeat` When true the service done so far is lost and will be repeated (preemptive-repeat), otherwise
+    ///   it will resume where it left off (preemptive-resume).
+    pub fn preempt(&self, time: f64, repeat: bool) -> ArrivingCustomer {
+        ArrivingCustomer {
+            id: self.id,
+            batch: self.batch,
+            remaining_service: if repeat { self.service_time } else { self.time_of_departure - time },
+            time_of_first_service_start: Some(self.time_of_service_start),
+            attempts: self.attempts,
+            visit: self.visit,
+            time_of_entry: self.time_of_entry,
+            ..ArrivingCustomer::arriving(self.class, self.interarrival_time, self.time_of_arrival, self.service_time)
         }
     }
 
+    /// The customer's server broke down at `time` and takes `repair_time` to repair, while they wait at it.
+    ///
+    /// # Arguments
+    /// * `time` The time the server breaks down.
+    /// * `repair_time` How long the server takes to repair.
+    /// * `repeat` When true the service done so far is lost and starts over after the repair, otherwise it resumes
+    ///   where it left off.
+    pub fn interrupt(self, time: f64, repair_time: f64, repeat: bool) -> Customer {
+        let time_of_departure = if repeat {
+            time + repair_time + self.service_time
+        } else {
+            self.time_of_departure + repair_time
+        };
+        let wait_in_system = time_of_departure - self.time_of_arrival;
+        Customer {
+            time_of_departure,
+            wait_in_queue: wait_in_system - self.service_time,
+            wait_in_system,
+            ..self
+        }
+    }
+
+    /// The number of customers sharing the customer's server goes from `before` to `after` at `time`, so what is left
+    /// of their service goes at `before / after` of the rate it did, and they depart that much later (or sooner).
+    pub fn share(self, time: f64, before: usize, after: usize) -> Customer {
+        let time_of_departure = time + (self.time_of_departure - time) * after as f64 / before as f64;
+        let wait_in_system = time_of_departure - self.time_of_arrival;
+        Customer {
+            time_of_departure,
+            wait_in_queue: wait_in_system - self.service_time,
+            wait_in_system,
+            ..self
+        }
+    }
+
+    /// The customer failed after service at `time` and goes round again, as a new pass through the queue arriving
+    /// then with `service_time`. Their ID and time of entry stay the same.
+    pub fn feed_back(&self, time: f64, service_time: f64) -> ArrivingCustomer {
+        ArrivingCustomer {
+            id: self.id,
+            batch: self.batch,
+            attempts: self.attempts,
+            visit: self.visit + 1,
+            time_of_entry: self.time_of_entry,
+            ..ArrivingCustomer::arriving(self.class, self.interarrival_time, time, service_time)
+        }
+    }
+
+    /// The service still owed to the customer at `time`.
+    pub fn remaining_service(&self, time: f64) -> f64 {
+        self.time_of_departure - time
+    }
+
+    /// The customer gave up waiting and left the queue at `time_of_abandonment` without being served. They are
+    /// recorded as departing at that time with no service.
+    pub fn abandon(arriving_customer: ArrivingCustomer, time_of_abandonment: f64) -> Customer {
+        Customer {
+            id: arriving_customer.id,
+            batch: arriving_customer.batch,
+            class: arriving_customer.class,
+            interarrival_time: arriving_customer.interarrival_time,
+            time_of_arrival: arriving_customer.time_of_arrival,
+            service_time: 0.,
+            attempts: arriving_customer.attempts,
+            visit: arriving_customer.visit,
+            time_of_entry: arriving_customer.time_of_entry,
+            time_of_service_start: time_of_abandonment,
+            time_of_departure: time_of_abandonment,
+            wait_in_queue: time_of_abandonment - arriving_customer.time_of_arrival,
+            wait_in_system: time_of_abandonment - arriving_customer.time_of_arrival,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    pub fn class(&self) -> usize {
+        self.class
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn visit(&self) -> u32 {
+        self.visit
+    }
+
+    /// The time from the customer's first arrival to their departure, over every pass through the queue.
+    pub fn sojourn_time(&self) -> f64 {
+        self.time_of_departure - self.time_of_entry
+    }
+
     pub fn time_of_departure(&self) -> f64 {
         self.time_of_departure
     }
@@ -107,4 +421,8 @@ impl Customer {
     pub fn wait_in_system(&self) -> f64 {
         self.wait_in_system
     }
+
+    pub fn wait_in_queue(&self) -> f64 {
+        self.wait_in_queue
+    }
 }
\ No newline at end of file
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::customer::ArrivingCustomer;
+
+/// A queue discipline decides which waiting customer is served when a server frees up.
+///
+/// The waiting line itself is kept by the `Queue` in order of arrival, the discipline only picks from it. That
+/// way every discipline sees the same arrival stream and only the order of service differs.
+pub trait QueueDiscipline {
+    /// Returns the index into `waiting` of the customer to serve next.
+    ///
+    /// # Arguments
+    /// * `waiting` The customers waiting for service, in order of arrival. Never empty.
+    /// * `rng` The random number generator of the queue, for disciplines that need one.
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, rng: &mut dyn RngCore) -> usize;
+
+    /// Whether the discipline orders customers by their service time, which then has to be known as they arrive
+    /// rather than sampled at the server that takes them.
+    fn by_service_time(&self) -> bool {
+        false
+    }
+}
+
+/// First come first served: the customer that has waited longest.
+pub struct FirstComeFirstServed;
+
+impl QueueDiscipline for FirstComeFirstServed {
+    fn select(&mut self, _waiting: &VecDeque<ArrivingCustomer>, _rng: &mut dyn RngCore) -> usize {
+        0
+    }
+}
+
+/// Last come first served: the customer that arrived most recently.
+pub struct LastComeFirstServed;
+
+impl QueueDiscipline for LastComeFirstServed {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, _rng: &mut dyn RngCore) -> usize {
+        waiting.len() - 1
+    }
+}
+
+/// Service in random order: every waiting customer is equally likely to be next.
+pub struct ServiceInRandomOrder;
+
+impl QueueDiscipline for ServiceInRandomOrder {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, rng: &mut dyn RngCore) -> usize {
+        rng.gen_range(0..waiting.len())
+    }
+}
+
+/// Shortest job first: the customer with the smallest service time, ties going to the earliest arrival.
+pub struct ShortestJobFirst;
+
+impl QueueDiscipline for ShortestJobFirst {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, _rng: &mut dyn RngCore) -> usize {
+        let mut index = 0;
+        for (i, customer) in waiting.iter().enumerate() {
+            if customer.service_time() < waiting[index].service_time() {
+                index = i;
+            }
+        }
+        index
+    }
+
+    fn by_service_time(&self) -> bool {
+        true
+    }
+}
+
+/// Longest job first: the customer with the largest service time, ties going to the earliest arrival.
+pub struct LongestJobFirst;
+
+impl QueueDiscipline for LongestJobFirst {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, _rng: &mut dyn RngCore) -> usize {
+        let mut index = 0;
+        for (i, customer) in waiting.iter().enumerate() {
+            if customer.service_time() > waiting[index].service_time() {
+                index = i;
+            }
+        }
+        index
+    }
+
+    fn by_service_time(&self) -> bool {
+        true
+    }
+}
+
+/// Shortest remaining processing time first: the customer with the least service left, which for a customer who was
+/// preempted is less than their service time. Ties go to the earliest arrival. On its own this doesn't preempt anyone,
+/// `Queue::with_srpt` also lets arriving customers preempt the customer in service with the most left.
+pub struct ShortestRemainingProcessingTime;
+
+impl QueueDiscipline for ShortestRemainingProcessingTime {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, _rng: &mut dyn RngCore) -> usize {
+        let mut index = 0;
+        for (i, customer) in waiting.iter().enumerate() {
+            let best = &waiting[index];
+            if (customer.remaining_service(), customer.arrival_time()) < (best.remaining_service(), best.arrival_time()) {
+                index = i;
+            }
+        }
+        index
+    }
+
+    fn by_service_time(&self) -> bool {
+        true
+    }
+}
+
+/// Priority by class: the customer of the lowest numbered class, so class 0 has the highest priority, and first
+/// come first served within a class.
+///
+/// Preempted customers go back to the end of the waiting line, so ties are broken by arrival time rather than by
+/// position in the line.
+pub struct HighestPriorityFirst;
+
+impl QueueDiscipline for HighestPriorityFirst {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, _rng: &mut dyn RngCore) -> usize {
+        let mut index = 0;
+        for (i, customer) in waiting.iter().enumerate() {
+            let best = &waiting[index];
+            if (customer.class(), customer.arrival_time()) < (best.class(), best.arrival_time()) {
+                index = i;
+            }
+        }
+        index
+    }
+}
+
+/// What happens when a customer arrives to find every server busy with a customer of lower priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preemption {
+    /// The arriving customer waits their turn like everyone else.
+    #[default]
+    None,
+    /// The lower priority customer is sent back to wait, and later resumes service where it left off.
+    Resume,
+    /// The lower priority customer is sent back to wait, and later repeats their whole service.
+    Repeat,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown preemption '{0}', expected one of none, resume, repeat")]
+pub struct UnknownPreemption(String);
+
+impl fmt::Display for Preemption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Preemption::None => "none",
+            Preemption::Resume => "resume",
+            Preemption::Repeat => "repeat",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Preemption {
+    type Err = UnknownPreemption;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(Preemption::None),
+            "resume" => Ok(Preemption::Resume),
+            "repeat" => Ok(Preemption::Repeat),
+            _ => Err(UnknownPreemption(s.to_string())),
+        }
+    }
+}
+
+/// How the servers divide their time between customers, when they don't serve one at a time to the end.
+///
+/// Both are preemptive, and neither looks at service times, so the mean number in the system of an M/M/c queue is the
+/// same under them as under first come first served.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sharing {
+    /// Egalitarian processor sharing: a single server serves every customer in the system at once, each at the rate
+    /// of one over the number of them. No one waits in the queue.
+    ProcessorSharing,
+    /// Round robin: a customer is served for at most `quantum` at a time, then goes to the back of the queue to wait
+    /// for another turn if their service isn't done.
+    RoundRobin { quantum: f64 },
+}
+
+#[derive(Debug, Error)]
+#[error("Couldn't parse sharing '{0}', expected ps or rr:quantum")]
+pub struct SharingParsing(String);
+
+impl fmt::Display for Sharing {
+    /// Writes the sharing in the same format that `from_str` parses.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sharing::ProcessorSharing => write!(f, "ps"),
+            Sharing::RoundRobin { quantum } => write!(f, "rr:{}", quantum),
+        }
+    }
+}
+
+impl FromStr for Sharing {
+    type Err = SharingParsing;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || SharingParsing(s.to_string());
+        match s.split_once(':') {
+            None if s.trim().eq_ignore_ascii_case("ps") => Ok(Sharing::ProcessorSharing),
+            Some((name, quantum)) if name.trim().eq_ignore_ascii_case("rr") => {
+                let quantum = quantum.trim().parse::<f64>().map_err(|_| error())?;
+                if quantum > 0. && quantum.is_finite() { Ok(Sharing::RoundRobin { quantum }) } else { Err(error()) }
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+/// The disciplines that ship with the crate, so they can be chosen on the command line and recorded in the
+/// header of an event file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Discipline {
+    #[default]
+    Fcfs,
+    Lcfs,
+    Siro,
+    Sjf,
+    Ljf,
+    Priority,
+    Srpt,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown queue discipline '{0}', expected one of fcfs, lcfs, siro, sjf, ljf, priority, srpt")]
+pub struct UnknownDiscipline(String);
+
+impl QueueDiscipline for Discipline {
+    fn select(&mut self, waiting: &VecDeque<ArrivingCustomer>, rng: &mut dyn RngCore) -> usize {
+        match self {
+            Discipline::Fcfs => FirstComeFirstServed.select(waiting, rng),
+            Discipline::Lcfs => LastComeFirstServed.select(waiting, rng),
+            Discipline::Siro => ServiceInRandomOrder.select(waiting, rng),
+            Discipline::Sjf => ShortestJobFirst.select(waiting, rng),
+            Discipline::Ljf => LongestJobFirst.select(waiting, rng),
+            Discipline::Priority => HighestPriorityFirst.select(waiting, rng),
+            Discipline::Srpt => ShortestRemainingProcessingTime.select(waiting, rng),
+        }
+    }
+
+    fn by_service_time(&self) -> bool {
+        matches!(self, Discipline::Sjf | Discipline::Ljf | Discipline::Srpt)
+    }
+}
+
+impl fmt::Display for Discipline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Discipline::Fcfs => "fcfs",
+            Discipline::Lcfs => "lcfs",
+            Discipline::Siro => "siro",
+            Discipline::Sjf => "sjf",
+            Discipline::Ljf => "ljf",
+            Discipline::Priority => "priority",
+            Discipline::Srpt => "srpt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Discipline {
+    type Err = UnknownDiscipline;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "fcfs" => Ok(Discipline::Fcfs),
+            "lcfs" => Ok(Discipline::Lcfs),
+            "siro" => Ok(Discipline::Siro),
+            "sjf" => Ok(Discipline::Sjf),
+            "ljf" => Ok(Discipline::Ljf),
+            "priority" => Ok(Discipline::Priority),
+            "srpt" => Ok(Discipline::Srpt),
+            _ => Err(UnknownDiscipline(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disciplines::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand_distr::Distribution;
+
+    struct Fixed(f64);
+
+    impl Distribution<f64> for Fixed {
+        fn sample<R: Rng + ?Sized>(&self, _rng: &mut R) -> f64 {
+            self.0
+        }
+    }
+
+    fn waiting(service_times: &[f64]) -> VecDeque<ArrivingCustomer> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut waiting = VecDeque::new();
+        let mut previous = ArrivingCustomer::first(&mut rng, &Fixed(service_times[0]));
+        waiting.push_back(previous);
+        for service_time in &service_times[1..] {
+            previous = ArrivingCustomer::next_1_fcfs(&mut rng, &Fixed(1.), &Fixed(*service_time), previous);
+            waiting.push_back(previous);
+        }
+        waiting
+    }
+
+    #[test]
+    fn disciplines_select_expected_customer() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let waiting = waiting(&[3., 1., 4., 1., 5.]);
+
+        assert_eq!(0, Discipline::Fcfs.select(&waiting, &mut rng));
+        assert_eq!(4, Discipline::Lcfs.select(&waiting, &mut rng));
+        assert_eq!(1, Discipline::Sjf.select(&waiting, &mut rng));
+        assert_eq!(4, Discipline::Ljf.select(&waiting, &mut rng));
+        assert_eq!(1, Discipline::Srpt.select(&waiting, &mut rng));
+        for _ in 0..100 {
+            assert!(Discipline::Siro.select(&waiting, &mut rng) < waiting.len());
+        }
+    }
+
+    #[test]
+    fn priority_prefers_lower_classes_then_earlier_arrivals() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let waiting: VecDeque<ArrivingCustomer> = [(2, 1.), (1, 3.), (1, 2.), (3, 0.5)].iter()
+            .map(|(class, arrival)| ArrivingCustomer::first_of_class(&mut rng, &Fixed(*arrival), &Fixed(1.), *class))
+            .collect();
+
+        assert_eq!(2, Discipline::Priority.select(&waiting, &mut rng));
+    }
+
+    #[test]
+    fn parse_round_trips_through_display() {
+        for discipline in [Discipline::Fcfs, Discipline::Lcfs, Discipline::Siro, Discipline::Sjf, Discipline::Ljf, Discipline::Priority,
+                           Discipline::Srpt] {
+            assert_eq!(discipline, discipline.to_string().parse().unwrap());
+        }
+        assert!("random".parse::<Discipline>().is_err());
+    }
+
+    #[test]
+    fn sharing_parses_round_trip_through_display() {
+        for s in ["ps", "rr:0.5"] {
+            let sharing: Sharing = s.parse().unwrap();
+            assert_eq!(s, sharing.to_string());
+        }
+        assert!("rr".parse::<Sharing>().is_err());
+        assert!("rr:0".parse::<Sharing>().is_err());
+        assert!("ps:1".parse::<Sharing>().is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::{Rng, RngCore};
+use rand_distr::{Distribution, Exp, Gamma, LogNormal};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The distributions of interarrival and service times the simulator knows how to describe.
+///
+/// Every variant is parameterized so that the distribution can be written to (and read back from) the
+/// header of an event file, which is how the analysis knows what was simulated. All times are in seconds.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimeDistribution {
+    /// Every sample is exactly `value`. The D in M/D/1.
+    Deterministic { value: f64 },
+    /// The memoryless distribution, with mean `1 / rate`. The M in M/M/1.
+    Exponential { rate: f64 },
+    /// The sum of `k` exponential phases, each with rate `k * rate`, so the mean is `1 / rate` just like
+    /// the exponential. This is the convention used by *Fundamentals*.
+    Erlang { k: u32, rate: f64 },
+    /// `exp(X)` where `X` is normal with mean `mu` and standard deviation `sigma`.
+    LogNormal { mu: f64, sigma: f64 },
+    /// The gamma distribution with the given shape and scale, the mean is `shape * scale`.
+    Gamma { shape: f64, scale: f64 },
+    /// With probability `probabilities[i]` the sample is exponential with rate `rates[i]`.
+    HyperExponential { probabilities: Vec<f64>, rates: Vec<f64> },
+}
+
+/// An object safe `Distribution<f64>`, for the optional distributions of a `Queue` that would each need a type
+/// parameter otherwise.
+pub trait DynDistribution {
+    fn sample_dyn(&self, rng: &mut dyn RngCore) -> f64;
+}
+
+impl<D: Distribution<f64>> DynDistribution for D {
+    fn sample_dyn(&self, rng: &mut dyn RngCore) -> f64 {
+        self.sample(rng)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DistributionError {
+    #[error("Unknown distribution '{0}', expected one of det, exp, erlang, lognormal, gamma, hyperexp")]
+    UnknownDistribution(String),
+    #[error("Couldn't parse the parameters of '{0}'")]
+    ParameterParsing(String),
+    #[error("Invalid parameters: {0}")]
+    InvalidParameters(String),
+}
+
+impl TimeDistribution {
+    /// The expected value of a sample.
+    pub fn mean(&self) -> f64 {
+        match self {
+            TimeDistribution::Deterministic { value } => *value,
+            TimeDistribution::Exponential { rate } => 1. / rate,
+            TimeDistribution::Erlang { rate, .. } => 1. / rate,
+            TimeDistribution::LogNormal { mu, sigma } => (mu + sigma * sigma / 2.).exp(),
+            TimeDistribution::Gamma { shape, scale } => shape * scale,
+            TimeDistribution::HyperExponential { probabilities, rates } => {
+                probabilities.iter().zip(rates).map(|(p, rate)| p / rate).sum()
+            }
+        }
+    }
+
+    /// The variance of a sample.
+    pub fn variance(&self) -> f64 {
+        match self {
+            TimeDistribution::Deterministic { .. } => 0.,
+            TimeDistribution::Exponential { rate } => 1. / (rate * rate),
+            TimeDistribution::Erlang { k, rate } => 1. / (*k as f64 * rate * rate),
+            TimeDistribution::LogNormal { mu, sigma } => {
+                let sigma_squared = sigma * sigma;
+                (sigma_squared.exp() - 1.) * (2. * mu + sigma_squared).exp()
+            }
+            TimeDistribution::Gamma { shape, scale } => shape * scale * scale,
+            TimeDistribution::HyperExponential { probabilities, rates } => {
+                let second_moment: f64 = probabilities.iter().zip(rates).map(|(p, rate)| 2. * p / (rate * rate)).sum();
+                second_moment - self.mean() * self.mean()
+            }
+        }
+    }
+
+    /// The second moment of a sample, E[X^2].
+    pub fn second_moment(&self) -> f64 {
+        self.variance() + self.mean() * self.mean()
+    }
+
+    /// The Laplace-Stieltjes transform at `s`, E[exp(-s X)], which at an arrival rate is the probability that no
+    /// one arrives during a sample. The lognormal distribution has no closed form for it.
+    pub fn laplace_transform(&self, s: f64) -> Option<f64> {
+        match self {
+            TimeDistribution::Deterministic { value } => Some((-s * value).exp()),
+            TimeDistribution::Exponential { rate } => Some(rate / (rate + s)),
+            TimeDistribution::Erlang { k, rate } => {
+                let phase_rate = *k as f64 * rate;
+                Some((phase_rate / (phase_rate + s)).powi(*k as i32))
+            }
+            TimeDistribution::LogNormal { .. } => None,
+            TimeDistribution::Gamma { shape, scale } => Some((1. + s * scale).powf(-shape)),
+            TimeDistribution::HyperExponential { probabilities, rates } => {
+                Some(probabilities.iter().zip(rates).map(|(p, rate)| p * rate / (rate + s)).sum())
+            }
+        }
+    }
+
+    /// The rate of events if consecutive events are separated by samples of this distribution.
+    pub fn rate(&self) -> f64 {
+        1. / self.mean()
+    }
+
+    /// The same distribution if its parameters are valid, so that it can be sampled. Parsing validates, but the
+    /// variants can also be built directly.
+    pub fn validate(self) -> Result<TimeDistribution, DistributionError> {
+        let valid = match &self {
+            TimeDistribution::Deterministic { value } => *value >= 0.,
+            TimeDistribution::Exponential { rate } => *rate > 0.,
+            TimeDistribution::Erlang { k, rate } => *k > 0 && *rate > 0.,
+            TimeDistribution::LogNormal { sigma, .. } => *sigma >= 0.,
+            TimeDistribution::Gamma { shape, scale } => *shape > 0. && *scale > 0.,
+            TimeDistribution::HyperExponential { probabilities, rates } => {
+                probabilities.len() == rates.len()
+                    && !rates.is_empty()
+                    && rates.iter().all(|rate| *rate > 0.)
+                    && probabilities.iter().all(|p| *p >= 0.)
+                    && (probabilities.iter().sum::<f64>() - 1.).abs() < 1.0e-9
+            }
+        };
+
+        if valid {
+            Ok(self)
+        } else {
+            Err(DistributionError::InvalidParameters(self.to_string()))
+        }
+    }
+}
+
+impl Distribution<f64> for TimeDistribution {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        // The parameters are validated when parsed (or built by the simulator), so constructing the rand_distr
+        // distributions can't fail.
+        match self {
+            TimeDistribution::Deterministic { value } => *value,
+            TimeDistribution::Exponential { rate } => Exp::new(*rate).unwrap().sample(rng),
+            TimeDistribution::Erlang { k, rate } => {
+                Gamma::new(*k as f64, 1. / (*k as f64 * rate)).unwrap().sample(rng)
+            }
+            TimeDistribution::LogNormal { mu, sigma } => LogNormal::new(*mu, *sigma).unwrap().sample(rng),
+            TimeDistribution::Gamma { shape, scale } => Gamma::new(*shape, *scale).unwrap().sample(rng),
+            TimeDistribution::HyperExponential { probabilities, rates } => {
+                let u: f64 = rng.gen();
+                let mut cumulative = 0.;
+                // Fall back on the last branch in case the probabilities sum to a hair under one.
+                let mut branch = rates.len() - 1;
+                for (i, p) in probabilities.iter().enumerate() {
+                    cumulative += p;
+                    if u < cumulative {
+                        branch = i;
+                        break;
+                    }
+                }
+                Exp::new(rates[branch]).unwrap().sample(rng)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TimeDistribution {
+    /// Writes the distribution in the same format that `from_str` parses.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeDistribution::Deterministic { value } => write!(f, "det:{}", value),
+            TimeDistribution::Exponential { rate } => write!(f, "exp:{}", rate),
+            TimeDistribution::Erlang { k, rate } => write!(f, "erlang:{},{}", k, rate),
+            TimeDistribution::LogNormal { mu, sigma } => write!(f, "lognormal:{},{}", mu, sigma),
+            TimeDistribution::Gamma { shape, scale } => write!(f, "gamma:{},{}", shape, scale),
+            TimeDistribution::HyperExponential { probabilities, rates } => {
+                let branches: Vec<String> = probabilities.iter().zip(rates)
+                    .map(|(p, rate)| format!("{},{}", p, rate))
+                    .collect();
+                write!(f, "hyperexp:{}", branches.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for TimeDistribution {
+    type Err = DistributionError;
+
+    /// Parses a distribution from `name:parameters`, where the parameters are comma separated:
+    ///
+    /// * `det:value`
+    /// * `exp:rate`
+    /// * `erlang:k,rate`
+    /// * `lognormal:mu,sigma`
+    /// * `gamma:shape,scale`
+    /// * `hyperexp:p_1,rate_1,p_2,rate_2,...`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameters) = s.split_once(':').unwrap_or((s, ""));
+        let values = parameters.split(',')
+            .map(|token| token.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| DistributionError::ParameterParsing(s.to_string()))?;
+
+        let distribution = match (name.trim(), values.as_slice()) {
+            ("det", [value]) => TimeDistribution::Deterministic { value: *value },
+            ("exp", [rate]) => TimeDistribution::Exponential { rate: *rate },
+            ("erlang", [k, rate]) if k.fract() == 0. && *k >= 1. => {
+                TimeDistribution::Erlang { k: *k as u32, rate: *rate }
+            }
+            ("lognormal", [mu, sigma]) => TimeDistribution::LogNormal { mu: *mu, sigma: *sigma },
+            ("gamma", [shape, scale]) => TimeDistribution::Gamma { shape: *shape, scale: *scale },
+            ("hyperexp", branches) if !branches.is_empty() && branches.len() % 2 == 0 => {
+                TimeDistribution::HyperExponential {
+                    probabilities: branches.iter().step_by(2).copied().collect(),
+                    rates: branches.iter().skip(1).step_by(2).copied().collect(),
+                }
+            }
+            ("det", _) | ("exp", _) | ("erlang", _) | ("lognormal", _) | ("gamma", _) | ("hyperexp", _) => {
+                return Err(DistributionError::ParameterParsing(s.to_string()));
+            }
+            (name, _) => return Err(DistributionError::UnknownDistribution(name.to_string())),
+        };
+
+        distribution.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::distributions::*;
+    use assert_approx_eq::assert_approx_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parse_round_trips_through_display() {
+        for s in ["det:3", "exp:0.5", "erlang:3,0.25", "lognormal:1,0.5", "gamma:2,1.5", "hyperexp:0.3,1,0.7,4"] {
+            let distribution: TimeDistribution = s.parse().unwrap();
+            assert_eq!(s, distribution.to_string());
+        }
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        assert!("normal:1,2".parse::<TimeDistribution>().is_err());
+        assert!("exp:1,2".parse::<TimeDistribution>().is_err());
+        assert!("exp:-1".parse::<TimeDistribution>().is_err());
+        assert!("erlang:2.5,1".parse::<TimeDistribution>().is_err());
+        assert!("hyperexp:0.3,1,0.3,4".parse::<TimeDistribution>().is_err());
+    }
+
+    #[test]
+    fn sample_means_match() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for s in ["det:3", "exp:0.5", "erlang:3,0.25", "lognormal:0,0.5", "gamma:2,1.5", "hyperexp:0.3,1,0.7,4"] {
+            let distribution: TimeDistribution = s.parse().unwrap();
+            let n = 200_000;
+            let sum: f64 = (0..n).map(|_| distribution.sample(&mut rng)).sum();
+            let tolerance = 5. * (distribution.variance() / n as f64).sqrt() + 1.0e-12;
+            assert_approx_eq!(distribution.mean(), sum / n as f64, tolerance);
+        }
+    }
+
+    #[test]
+    fn laplace_transforms_match_samples() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for s in ["det:3", "exp:0.5", "erlang:3,0.25", "gamma:2,1.5", "hyperexp:0.3,1,0.7,4"] {
+            let distribution: TimeDistribution = s.parse().unwrap();
+            let n = 200_000;
+            let sum: f64 = (0..n).map(|_| (-0.2 * distribution.sample(&mut rng)).exp()).sum();
+            assert_approx_eq!(distribution.laplace_transform(0.2).unwrap(), sum / n as f64, 5.0e-3);
+        }
+        assert!("lognormal:0,0.5".parse::<TimeDistribution>().unwrap().laplace_transform(0.2).is_none());
+    }
+}
@@ -1,5 +1,5 @@
 pub fn human_readable(seconds: f64) -> String {
-    return if seconds < 2. * 60. {
+    if seconds < 2. * 60. {
         // Less than two minutes, use seconds.
         format!("{:.2} sec", seconds)
     } else if seconds < 2. * 60. * 60. {
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// What a server does after a vacation if there is still no one waiting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VacationPolicy {
+    /// The server comes back and waits idle for the next customer.
+    #[default]
+    Single,
+    /// The server goes straight off on another vacation, until it comes back to find someone waiting.
+    Multiple,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown vacation policy '{0}', expected one of single, multiple")]
+pub struct UnknownVacationPolicy(String);
+
+impl fmt::Display for VacationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VacationPolicy::Single => write!(f, "single"),
+            VacationPolicy::Multiple => write!(f, "multiple"),
+        }
+    }
+}
+
+impl FromStr for VacationPolicy {
+    type Err = UnknownVacationPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "single" => Ok(VacationPolicy::Single),
+            "multiple" => Ok(VacationPolicy::Multiple),
+            _ => Err(UnknownVacationPolicy(s.to_string())),
+        }
+    }
+}
+
+/// What happens to the service of a customer whose server breaks down while serving them. Either way the customer
+/// stays at the server while it is repaired.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interruption {
+    /// The service carries on where it left off once the server is repaired.
+    #[default]
+    Resume,
+    /// The service done so far is lost, and starts over once the server is repaired. The service time is the same.
+    Restart,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown interruption '{0}', expected one of resume, restart")]
+pub struct UnknownInterruption(String);
+
+impl fmt::Display for Interruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interruption::Resume => write!(f, "resume"),
+            Interruption::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+impl FromStr for Interruption {
+    type Err = UnknownInterruption;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "resume" => Ok(Interruption::Resume),
+            "restart" => Ok(Interruption::Restart),
+            _ => Err(UnknownInterruption(s.to_string())),
+        }
+    }
+}
+
+/// Servers that finish serving and find no one waiting go on vacation, for a time sampled from `distribution`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Vacations<D> {
+    pub policy: VacationPolicy,
+    pub distribution: D,
+}
+
+/// Servers break down after serving for a time sampled from `time_to_failure`, counting only the time spent
+/// serving, and are repaired in a time sampled from `repair`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Breakdowns<D> {
+    pub time_to_failure: D,
+    pub repair: D,
+    #[serde(default)]
+    pub interruption: Interruption,
+}
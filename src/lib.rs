@@ -1,5 +1,15 @@
+pub mod balking;
+pub mod batches;
 pub mod customer;
+pub mod disciplines;
+pub mod distributions;
 pub mod formats;
+pub mod interruptions;
+pub mod network;
+pub mod profiles;
 pub mod queues;
+pub mod servers;
+pub mod staffing;
+pub mod statistics;
 pub mod theory;
 pub mod errors;
@@ -0,0 +1,553 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::Distribution;
+
+use crate::queues::EventType;
+use crate::statistics::WaitStatistics;
+
+/// A network of service stations, such as a pipeline of triage, treatment and checkout.
+///
+/// Each station is a first come first served G/G/c queue with its own servers and service distribution. Customers
+/// can arrive from outside at any station that has an `external_arrivals` stream. When a customer finishes service
+/// at station i they go on to station j with probability `routing[i][j]`, and leave the network with the probability
+/// that is left over. Going on to a station is an arrival there at the same time.
+///
+/// A closed network, where a fixed population circulates forever, has no external arrivals: the customers are
+/// placed at their first stations by `with_customers` and the routing probabilities from every station add up to one.
+/// A delay station, where no one waits, is a station with a server for every customer.
+///
+/// The external arrivals and the departures are kept in one event calendar. Events that follow from the one taken
+/// off the calendar at the same time (a service starting, an arrival downstream) are kept in `pending_events` and
+/// returned by the next calls, in the order they happened.
+///
+/// All randomness comes from `rng`, so a network built with `seeded` produces the same events every time.
+pub struct Network<DA: Distribution<f64>, DS: Distribution<f64>, R: Rng = StdRng> {
+    stations: Vec<Station<DS>>,
+    /// The distribution of the interarrival times from outside at each station, `None` for no external arrivals.
+    external_arrivals: Vec<Option<DA>>,
+    routing: Vec<Vec<f64>>,
+    rng: R,
+
+    calendar: BinaryHeap<ScheduledEvent>,
+    /// Breaks ties between events scheduled at the same time, so that they are taken in the order they were scheduled.
+    scheduled: u64,
+    time: f64,
+    in_network: u64,
+    /// The ID of the next customer to arrive from outside.
+    next_id: u64,
+    last_event: NetworkEvent,
+    pending_events: VecDeque<NetworkEvent>,
+}
+
+/// A station of the network: `servers` identical servers sharing one waiting line.
+struct Station<DS> {
+    servers: u32,
+    service_distribution: DS,
+    waiting: VecDeque<Visitor>,
+    busy: u32,
+}
+
+impl<DS> Station<DS> {
+    fn in_station(&self) -> u64 {
+        self.busy as u64 + self.waiting.len() as u64
+    }
+}
+
+/// A customer on their way through the network.
+#[derive(Clone, Copy, Debug)]
+struct Visitor {
+    id: u64,
+    /// When they arrived in the network, at their first station.
+    time_of_arrival: f64,
+    /// When they arrived at the station they are at.
+    time_of_arrival_at_station: f64,
+}
+
+impl<DA: Distribution<f64>, DS: Distribution<f64>> Network<DA, DS> {
+    /// A reproducible network with no stations yet: the same seed gives the same events.
+    pub fn seeded(seed: u64) -> Network<DA, DS> {
+        Network::with_rng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl<DA: Distribution<f64>, DS: Distribution<f64>, R: Rng> Network<DA, DS, R> {
+    /// A network with no stations yet, drawing from the given random number generator.
+    pub fn with_rng(rng: R) -> Network<DA, DS, R> {
+        Network {
+            stations: Vec::new(),
+            external_arrivals: Vec::new(),
+            routing: Vec::new(),
+            rng,
+
+            calendar: BinaryHeap::new(),
+            scheduled: 0,
+            time: 0.,
+            in_network: 0,
+            next_id: 0,
+            last_event: NetworkEvent::default(),
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Adds a station with `servers` servers. Stations are numbered from 0 in the order they are added, and at first
+    /// everyone leaves the network after being served there.
+    pub fn with_station(mut self, servers: u32, service_distribution: DS) -> Network<DA, DS, R> {
+        assert!(servers > 0, "A station needs at least one server.");
+        self.stations.push(Station { servers, service_distribution, waiting: VecDeque::new(), busy: 0 });
+        self.external_arrivals.push(None);
+        for row in &mut self.routing {
+            row.push(0.);
+        }
+        self.routing.push(vec![0.; self.stations.len()]);
+        self
+    }
+
+    /// Customers arrive from outside at `station`, with interarrival times from `arrival_distribution`. The first
+    /// arrives after one interarrival time.
+    pub fn with_external_arrivals(mut self, station: usize, arrival_distribution: DA) -> Network<DA, DS, R> {
+        assert!(self.external_arrivals[station].is_none(), "A station has at most one stream of external arrivals.");
+        let time = self.time + arrival_distribution.sample(&mut self.rng);
+        self.external_arrivals[station] = Some(arrival_distribution);
+        self.schedule(time, Scheduled::ExternalArrival { station });
+        self
+    }
+
+    /// Puts `customers` customers at `station` now, as the population of a closed network. Those beyond the servers
+    /// wait in line. Their arrivals are the first events.
+    pub fn with_customers(mut self, station: usize, customers: u32) -> Network<DA, DS, R> {
+        for _ in 0..customers {
+            let visitor = Visitor { id: self.next_id, time_of_arrival: self.time, time_of_arrival_at_station: self.time };
+            self.next_id += 1;
+            self.in_network += 1;
+            self.arrive(station, visitor);
+        }
+        self
+    }
+
+    /// Customers served at station `from` go on to station `to` with `probability`.
+    pub fn with_routing(mut self, from: usize, to: usize, probability: f64) -> Network<DA, DS, R> {
+        self.routing[from][to] = probability;
+        assert!(self.routing[from].iter().sum::<f64>() <= 1. + 1.0e-12, "The routing probabilities from a station add up to at most one.");
+        self
+    }
+
+    /// The number of stations.
+    pub fn stations(&self) -> usize {
+        self.stations.len()
+    }
+
+    /// The number of customers at a station, in service and waiting.
+    pub fn in_station(&self, station: usize) -> u64 {
+        self.stations[station].in_station()
+    }
+
+    /// The number of customers anywhere in the network.
+    pub fn in_network(&self) -> u64 {
+        self.in_network
+    }
+
+    /// The time of the last event.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Returns the time of the next event. There is always one as long as some station has external arrivals, or
+    /// there are customers in a closed network.
+    pub fn peek_next_event_time(&self) -> Option<f64> {
+        if !self.pending_events.is_empty() {
+            return Some(self.time);
+        }
+        self.calendar.peek().map(|scheduled| scheduled.time)
+    }
+
+    pub fn next_event(&mut self) -> &NetworkEvent {
+        if self.pending_events.is_empty() {
+            let scheduled = self.calendar.pop().expect("A network needs external arrivals or customers to have events.");
+            self.time = scheduled.time;
+            match scheduled.event {
+                Scheduled::ExternalArrival { station } => {
+                    let distribution = self.external_arrivals[station].as_ref().expect("Only stations with external arrivals schedule them.");
+                    let next_time = self.time + distribution.sample(&mut self.rng);
+                    self.schedule(next_time, Scheduled::ExternalArrival { station });
+
+                    let visitor = Visitor { id: self.next_id, time_of_arrival: self.time, time_of_arrival_at_station: self.time };
+                    self.next_id += 1;
+                    self.in_network += 1;
+                    self.arrive(station, visitor);
+                }
+                Scheduled::Departure { station, visitor } => self.depart(station, visitor),
+            }
+        }
+
+        self.last_event = self.pending_events.pop_front().expect("Every scheduled event causes at least one event.");
+        &self.last_event
+    }
+
+    /// The visitor joins the line at `station`, and starts service if a server is idle.
+    fn arrive(&mut self, station: usize, visitor: Visitor) {
+        let in_station = self.stations[station].in_station() + 1;
+        self.pending_events.push_back(NetworkEvent {
+            time: self.time,
+            event_type: EventType::Arrival,
+            station,
+            customer: visitor.id,
+            in_station,
+            in_network: self.in_network,
+            ..NetworkEvent::default()
+        });
+        if self.stations[station].busy < self.stations[station].servers {
+            self.start_service(station, visitor);
+        } else {
+            self.stations[station].waiting.push_back(visitor);
+        }
+    }
+
+    fn start_service(&mut self, station: usize, visitor: Visitor) {
+        let service_time = self.stations[station].service_distribution.sample(&mut self.rng);
+        self.stations[station].busy += 1;
+        self.schedule(self.time + service_time, Scheduled::Departure { station, visitor });
+        self.pending_events.push_back(NetworkEvent {
+            time: self.time,
+            event_type: EventType::ServiceStart,
+            station,
+            customer: visitor.id,
+            in_station: self.stations[station].in_station(),
+            in_network: self.in_network,
+            ..NetworkEvent::default()
+        });
+    }
+
+    /// The visitor finishes service at `station`, the next in line starts, and the visitor moves on or leaves.
+    fn depart(&mut self, station: usize, visitor: Visitor) {
+        self.stations[station].busy -= 1;
+        let next_station = self.route(station);
+        if next_station.is_none() {
+            self.in_network -= 1;
+        }
+        self.pending_events.push_back(NetworkEvent {
+            time: self.time,
+            event_type: EventType::Departure,
+            station,
+            customer: visitor.id,
+            next_station,
+            in_station: self.stations[station].in_station(),
+            in_network: self.in_network,
+            wait_in_station: self.time - visitor.time_of_arrival_at_station,
+            wait_in_network: if next_station.is_none() { Some(self.time - visitor.time_of_arrival) } else { None },
+        });
+        if let Some(waiting) = self.stations[station].waiting.pop_front() {
+            self.start_service(station, waiting);
+        }
+        if let Some(next_station) = next_station {
+            self.arrive(next_station, Visitor { time_of_arrival_at_station: self.time, ..visitor });
+        }
+    }
+
+    /// Where a customer served at `station` goes next, `None` when they leave the network.
+    fn route(&mut self, station: usize) -> Option<usize> {
+        let u: f64 = self.rng.gen();
+        let mut cumulative = 0.;
+        for (next_station, probability) in self.routing[station].iter().enumerate() {
+            cumulative += probability;
+            if u < cumulative {
+                return Some(next_station);
+            }
+        }
+        None
+    }
+
+    fn schedule(&mut self, time: f64, event: Scheduled) {
+        self.calendar.push(ScheduledEvent { time, order: self.scheduled, event });
+        self.scheduled += 1;
+    }
+}
+
+/// A network is an endless stream of events, as long as customers arrive from outside or circulate in it.
+impl<DA: Distribution<f64>, DS: Distribution<f64>, R: Rng> Iterator for Network<DA, DS, R> {
+    type Item = NetworkEvent;
+
+    fn next(&mut self) -> Option<NetworkEvent> {
+        self.peek_next_event_time()?;
+        Some(*self.next_event())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Scheduled {
+    ExternalArrival { station: usize },
+    Departure { station: usize, visitor: Visitor },
+}
+
+/// An entry in the event calendar of a `Network`, ordered so that the earliest is on top of the `BinaryHeap`, and
+/// between events at the same time the one scheduled first.
+#[derive(Clone, Copy, Debug)]
+struct ScheduledEvent {
+    time: f64,
+    order: u64,
+    event: Scheduled,
+}
+
+impl Ord for ScheduledEvent {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time).then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for ScheduledEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScheduledEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScheduledEvent {}
+
+/// Something that happened at a station of a `Network`: an arrival (from outside or from another station), a
+/// service starting, or a departure.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetworkEvent {
+    time: f64,
+    event_type: EventType,
+    station: usize,
+    customer: u64,
+    /// For departures, the station the customer goes on to, `None` when they leave the network.
+    next_station: Option<usize>,
+    /// The number of customers at the station after the event.
+    in_station: u64,
+    /// The number of customers in the whole network after the event.
+    in_network: u64,
+    /// For departures, the time since the customer arrived at the station.
+    wait_in_station: f64,
+    /// For departures from the network, the time since the customer arrived at their first station.
+    wait_in_network: Option<f64>,
+}
+
+impl Default for NetworkEvent {
+    fn default() -> Self {
+        NetworkEvent {
+            time: 0.,
+            event_type: EventType::Arrival,
+            station: 0,
+            customer: 0,
+            next_station: None,
+            in_station: 0,
+            in_network: 0,
+            wait_in_station: 0.,
+            wait_in_network: None,
+        }
+    }
+}
+
+impl NetworkEvent {
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn station(&self) -> usize {
+        self.station
+    }
+
+    pub fn customer(&self) -> u64 {
+        self.customer
+    }
+
+    pub fn next_station(&self) -> Option<usize> {
+        self.next_station
+    }
+
+    pub fn in_station(&self) -> u64 {
+        self.in_station
+    }
+
+    pub fn in_network(&self) -> u64 {
+        self.in_network
+    }
+
+    pub fn wait_in_station(&self) -> f64 {
+        self.wait_in_station
+    }
+
+    pub fn wait_in_network(&self) -> Option<f64> {
+        self.wait_in_network
+    }
+}
+
+/// Tallies the events of a `Network` into the measures the Jackson network theory predicts: the arrival rate, the
+/// number of customers and the wait at each station, and the time spent in the whole network.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkAnalyser {
+    start_time: f64,
+    time_of_last_event: f64,
+    stations: Vec<StationStatistics>,
+    /// The time spent in the network by the customers who left it.
+    network_waits: WaitStatistics,
+}
+
+#[derive(Clone, Debug, Default)]
+struct StationStatistics {
+    arrivals: u64,
+    /// The integral of the number of customers at the station over time, up to `last_change`.
+    customer_time: f64,
+    last_change: f64,
+    last_in_station: u64,
+    waits: WaitStatistics,
+}
+
+impl NetworkAnalyser {
+    /// An analyser for a network of `stations` stations, starting empty at `start_time`.
+    pub fn new(stations: usize, start_time: f64) -> NetworkAnalyser {
+        NetworkAnalyser {
+            start_time,
+            time_of_last_event: start_time,
+            stations: vec![StationStatistics { last_change: start_time, ..StationStatistics::default() }; stations],
+            network_waits: WaitStatistics::default(),
+        }
+    }
+
+    pub fn add_event(&mut self, event: &NetworkEvent) {
+        let station = &mut self.stations[event.station];
+        station.customer_time += (event.time - station.last_change) * station.last_in_station as f64;
+        station.last_change = event.time;
+        station.last_in_station = event.in_station;
+        match event.event_type {
+            EventType::Arrival => station.arrivals += 1,
+            EventType::Departure => {
+                station.waits.add(event.wait_in_station);
+                if let Some(wait) = event.wait_in_network {
+                    self.network_waits.add(wait);
+                }
+            }
+            _ => {}
+        }
+        self.time_of_last_event = event.time;
+    }
+
+    fn duration(&self) -> f64 {
+        self.time_of_last_event - self.start_time
+    }
+
+    /// The rate of arrivals at a station, from outside and from other stations.
+    pub fn arrival_rate(&self, station: usize) -> f64 {
+        self.stations[station].arrivals as f64 / self.duration()
+    }
+
+    /// The average number of customers at a station, in service and waiting.
+    pub fn number_in_station(&self, station: usize) -> f64 {
+        let statistics = &self.stations[station];
+        let customer_time = statistics.customer_time
+            + (self.time_of_last_event - statistics.last_change) * statistics.last_in_station as f64;
+        customer_time / self.duration()
+    }
+
+    /// The waits at a station, from arriving there to leaving it.
+    pub fn waits_in_station(&self, station: usize) -> &WaitStatistics {
+        &self.stations[station].waits
+    }
+
+    /// The times spent in the network by the customers who left it.
+    pub fn waits_in_network(&self) -> &WaitStatistics {
+        &self.network_waits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::*;
+    use crate::distributions::TimeDistribution;
+    use crate::theory::{JacksonNetwork, MeanValueAnalysis, QueueTheory};
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn departures_feed_downstream_arrivals() {
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut network = Network::seeded(0)
+            .with_station(1, deterministic(1.))
+            .with_station(1, deterministic(2.))
+            .with_external_arrivals(0, deterministic(10.))
+            .with_routing(0, 1, 1.);
+
+        let events: Vec<(f64, EventType, usize)> = (0..6)
+            .map(|_| *network.next_event())
+            .map(|event| (event.time(), event.event_type(), event.station()))
+            .collect();
+        assert_eq!(vec![
+            (10., EventType::Arrival, 0),
+            (10., EventType::ServiceStart, 0),
+            (11., EventType::Departure, 0),
+            (11., EventType::Arrival, 1),
+            (11., EventType::ServiceStart, 1),
+            (13., EventType::Departure, 1),
+        ], events);
+        assert_eq!(Some(3.), network.last_event.wait_in_network());
+        assert_eq!(0, network.in_network());
+    }
+
+    #[test]
+    fn simulation_agrees_with_jackson_network() {
+        // Two stations in tandem, with a quarter of the customers sent back from the second to the first, and some
+        // arriving straight at the second.
+        let exponential = |rate| TimeDistribution::Exponential { rate };
+        let network = Network::seeded(3)
+            .with_station(1, exponential(3.))
+            .with_station(2, exponential(1.5))
+            .with_external_arrivals(0, exponential(1.))
+            .with_external_arrivals(1, exponential(0.5))
+            .with_routing(0, 1, 1.)
+            .with_routing(1, 0, 0.25);
+        let theory = JacksonNetwork::new(vec![1., 0.5], vec![3., 1.5], vec![1, 2], vec![vec![0., 1.], vec![0.25, 0.]]);
+
+        let mut analyser = NetworkAnalyser::new(2, 0.);
+        for event in network.take(2_000_000) {
+            analyser.add_event(&event);
+        }
+        for station in 0..2 {
+            let expected = theory.station(station);
+            assert_approx_eq!(theory.arrival_rates[station], analyser.arrival_rate(station), 0.02 * theory.arrival_rates[station]);
+            assert_approx_eq!(expected.number_in_system(), analyser.number_in_station(station), 0.05 * expected.number_in_system());
+            assert_approx_eq!(expected.wait_in_system(), analyser.waits_in_station(station).mean(), 0.05 * expected.wait_in_system());
+        }
+        assert_approx_eq!(theory.wait_in_system(), analyser.waits_in_network().mean(), 0.05 * theory.wait_in_system());
+    }
+
+    #[test]
+    fn closed_network_agrees_with_mean_value_analysis() {
+        // Six jobs cycle from a CPU to one of two disks and back, and a think time, a delay station, after a quarter
+        // of the CPU bursts.
+        let exponential = |rate| TimeDistribution::Exponential { rate };
+        let network = Network::<TimeDistribution, _>::seeded(5)
+            .with_station(1, exponential(10.))
+            .with_station(1, exponential(4.))
+            .with_station(1, exponential(2.))
+            .with_station(6, exponential(0.5))
+            .with_routing(0, 1, 0.5)
+            .with_routing(0, 2, 0.25)
+            .with_routing(0, 3, 0.25)
+            .with_routing(1, 0, 1.)
+            .with_routing(2, 0, 1.)
+            .with_routing(3, 0, 1.)
+            .with_customers(3, 6);
+        // Relative to the think station, visited once per cycle.
+        let theory = MeanValueAnalysis::new(vec![4., 2., 1., 1.], vec![0.1, 0.25, 0.5, 2.], vec![false, false, false, true], 6);
+
+        let mut analyser = NetworkAnalyser::new(4, 0.);
+        for event in network.take(2_000_000) {
+            analyser.add_event(&event);
+        }
+        assert_approx_eq!(theory.throughput, analyser.arrival_rate(3), 0.02 * theory.throughput);
+        for station in 0..4 {
+            assert_approx_eq!(theory.queue_lengths[station], analyser.number_in_station(station), 0.05 * theory.queue_lengths[station]);
+        }
+    }
+}
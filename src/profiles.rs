@@ -0,0 +1,309 @@
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use rand::Rng;
+use rand_distr::{Distribution, Exp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// How the arrival rate goes from one point of a profile to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// The rate stays at the rate of the last point until the next point, piecewise constant.
+    #[default]
+    Step,
+    /// The rate changes linearly between the points, piecewise linear.
+    Linear,
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interpolation::Step => write!(f, "step"),
+            Interpolation::Linear => write!(f, "linear"),
+        }
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = ProfileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "step" => Ok(Interpolation::Step),
+            "linear" => Ok(Interpolation::Linear),
+            _ => Err(ProfileError::Parsing(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("Failed to read the rate profile")]
+    Reading(#[from] std::io::Error),
+    #[error("Couldn't parse '{0}', expected a time in seconds and a rate per second")]
+    Parsing(String),
+    #[error("Invalid rate profile: {0}")]
+    Invalid(&'static str),
+}
+
+/// An arrival rate that changes with time, lambda(t), like the daily curve of customers at a service desk.
+///
+/// The rate is given at points in time, the first at zero, and interpolated between them. When the profile has a
+/// period it repeats, and the segments between the points are the times of day the analysis is broken down by.
+/// Otherwise the rate stays at the last point's rate after it.
+///
+/// Arrivals are sampled by thinning: candidates arrive as a Poisson process at the peak rate, and a candidate at t is
+/// kept with probability lambda(t) / peak.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RateProfile {
+    #[serde(default)]
+    interpolation: Interpolation,
+    /// The times the rate is given at, in seconds from the start of the profile.
+    times: Vec<f64>,
+    /// The rates at those times, in customers per second.
+    rates: Vec<f64>,
+    /// The length of the profile in seconds when it repeats, like 86400 for a daily curve.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    period: Option<f64>,
+}
+
+impl RateProfile {
+    pub fn new(times: Vec<f64>, rates: Vec<f64>, interpolation: Interpolation) -> Result<RateProfile, ProfileError> {
+        if times.is_empty() || times.len() != rates.len() {
+            return Err(ProfileError::Invalid("there must be a rate for every time, and at least one"));
+        }
+        if times[0] != 0. {
+            return Err(ProfileError::Invalid("the first time must be zero"));
+        }
+        if times.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(ProfileError::Invalid("the times must be increasing"));
+        }
+        if rates.iter().any(|rate| !rate.is_finite() || *rate < 0.) {
+            return Err(ProfileError::Invalid("the rates must be finite and non-negative"));
+        }
+        Ok(RateProfile { interpolation, times, rates, period: None })
+    }
+
+    /// Repeats the profile every `period` seconds, which must be after the last point.
+    pub fn with_period(self, period: f64) -> Result<RateProfile, ProfileError> {
+        if !period.is_finite() || period <= *self.times.last().unwrap() {
+            return Err(ProfileError::Invalid("the period must end after the last time"));
+        }
+        Ok(RateProfile { period: Some(period), ..self })
+    }
+
+    /// Reads a profile of one point per line, a time in seconds and a rate per second separated by whitespace.
+    /// Blank lines and lines starting with # are skipped.
+    pub fn read<B: BufRead>(reader: B, interpolation: Interpolation) -> Result<RateProfile, ProfileError> {
+        let mut times = Vec::new();
+        let mut rates = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse = |token: Option<&str>| token.and_then(|token| token.parse::<f64>().ok())
+                .ok_or_else(|| ProfileError::Parsing(line.to_string()));
+            let mut tokens = line.split_ascii_whitespace();
+            times.push(parse(tokens.next())?);
+            rates.push(parse(tokens.next())?);
+            if tokens.next().is_some() {
+                return Err(ProfileError::Parsing(line.to_string()));
+            }
+        }
+        RateProfile::new(times, rates, interpolation)
+    }
+
+    pub fn period(&self) -> Option<f64> {
+        self.period
+    }
+
+    /// The number of segments, one starting at each point.
+    pub fn segments(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Where segment `i` starts and ends within the profile. The last segment ends with the period, or never.
+    pub fn segment_bounds(&self, i: usize) -> (f64, f64) {
+        let end = self.times.get(i + 1).copied().or(self.period).unwrap_or(f64::INFINITY);
+        (self.times[i], end)
+    }
+
+    /// The time within the profile, which is `time` itself unless the profile repeats.
+    fn phase(&self, time: f64) -> f64 {
+        self.period.map_or(time, |period| time.rem_euclid(period))
+    }
+
+    /// The segment the time within the profile falls in.
+    fn segment(&self, phase: f64) -> usize {
+        self.times.partition_point(|&start| start <= phase).saturating_sub(1)
+    }
+
+    /// The rate at the end of segment `i`, which linear interpolation heads towards. After the last point, that is
+    /// the first point again when the profile repeats.
+    fn rate_at_end(&self, i: usize) -> f64 {
+        match (self.interpolation, self.rates.get(i + 1), self.period) {
+            (Interpolation::Linear, Some(rate), _) => *rate,
+            (Interpolation::Linear, None, Some(_)) => self.rates[0],
+            _ => self.rates[i],
+        }
+    }
+
+    /// The arrival rate at `time`, lambda(t).
+    pub fn rate(&self, time: f64) -> f64 {
+        let phase = self.phase(time);
+        let i = self.segment(phase);
+        let (start, end) = self.segment_bounds(i);
+        if end.is_infinite() {
+            return self.rates[i];
+        }
+        self.rates[i] + (self.rate_at_end(i) - self.rates[i]) * (phase - start) / (end - start)
+    }
+
+    /// The highest rate, which thinning samples candidates at. Linear interpolation peaks at one of the points.
+    pub fn max_rate(&self) -> f64 {
+        self.rates.iter().copied().fold(0., f64::max)
+    }
+
+    /// The average rate over segment `i`.
+    pub fn segment_mean_rate(&self, i: usize) -> f64 {
+        (self.rates[i] + self.rate_at_end(i)) / 2.
+    }
+
+    /// The average rate over the profile: over a period when it repeats, otherwise up to the last point, or the only
+    /// rate when there is a single point.
+    pub fn mean_rate(&self) -> f64 {
+        let span = self.period.unwrap_or(*self.times.last().unwrap());
+        if span == 0. {
+            return self.rates[0];
+        }
+        (0..self.segments())
+            .map(|i| (i, self.segment_bounds(i)))
+            .filter(|(_, (_, end))| *end <= span)
+            .map(|(i, (start, end))| self.segment_mean_rate(i) * (end - start))
+            .sum::<f64>() / span
+    }
+
+    /// Calls `f` with each segment and the time spent in it between `from` and `to`, in order.
+    pub fn for_each_segment_between<F: FnMut(usize, f64)>(&self, from: f64, to: f64, mut f: F) {
+        let mut time = from;
+        while time < to {
+            let phase = self.phase(time);
+            let i = self.segment(phase);
+            let (_, end) = self.segment_bounds(i);
+            let until = (time + (end - phase)).min(to);
+            f(i, until - time);
+            time = until;
+        }
+    }
+
+    /// The segment that `time` falls in.
+    pub fn segment_at(&self, time: f64) -> usize {
+        self.segment(self.phase(time))
+    }
+
+    /// Samples the time of the next arrival after `time` by thinning, infinite if no one ever arrives again.
+    pub fn next_arrival<R: Rng + ?Sized>(&self, rng: &mut R, time: f64) -> f64 {
+        let max_rate = self.max_rate();
+        if max_rate == 0. {
+            return f64::INFINITY;
+        }
+        let candidates = Exp::new(max_rate).unwrap();
+        let last = self.segments() - 1;
+        let mut candidate = time;
+        loop {
+            // Without a period, the rate stays at the last point's after it, which may be no one at all.
+            if self.period.is_none() && candidate >= self.times[last] && self.rates[last] == 0. {
+                return f64::INFINITY;
+            }
+            candidate += candidates.sample(rng);
+            if rng.gen::<f64>() * max_rate < self.rate(candidate) {
+                return candidate;
+            }
+        }
+    }
+
+    /// The distribution of the time until the next arrival after `time`.
+    pub fn interarrivals_from(&self, time: f64) -> Interarrivals<'_> {
+        Interarrivals { profile: self, time }
+    }
+}
+
+/// The time until the next arrival of a rate profile after `time`, sampled by thinning.
+pub struct Interarrivals<'a> {
+    profile: &'a RateProfile,
+    time: f64,
+}
+
+impl Distribution<f64> for Interarrivals<'_> {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        self.profile.next_arrival(rng, self.time) - self.time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profiles::*;
+    use assert_approx_eq::assert_approx_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn rates_are_interpolated_and_repeat() {
+        let file = "# time rate\n0 1\n\n10 3\n20 0\n";
+        let step = RateProfile::read(file.as_bytes(), Interpolation::Step).unwrap().with_period(30.).unwrap();
+        let linear = RateProfile::read(file.as_bytes(), Interpolation::Linear).unwrap().with_period(30.).unwrap();
+        assert_eq!(3., step.rate(15.));
+        assert_eq!(1.5, linear.rate(15.));
+        // Linear heads back to the first rate at the end of the period.
+        assert_eq!(0.5, linear.rate(25.));
+        assert_eq!(linear.rate(5.), linear.rate(65.));
+        assert_approx_eq!(4. / 3., step.mean_rate(), 1.0e-15);
+        assert_approx_eq!((2. + 1.5 + 0.5) * 10. / 30., linear.mean_rate(), 1.0e-15);
+
+        // Without a period the last rate carries on.
+        let once = RateProfile::read(file.as_bytes(), Interpolation::Linear).unwrap();
+        assert_eq!(0., once.rate(100.));
+        assert_eq!(f64::INFINITY, once.next_arrival(&mut StdRng::seed_from_u64(0), 20.));
+
+        assert!(RateProfile::read("5 1\n".as_bytes(), Interpolation::Step).is_err());
+        assert!(RateProfile::read("0 1\n0 2\n".as_bytes(), Interpolation::Step).is_err());
+        assert!(RateProfile::read("0 one\n".as_bytes(), Interpolation::Step).is_err());
+        assert!(step.clone().with_period(20.).is_err());
+    }
+
+    #[test]
+    fn time_is_spread_over_the_segments() {
+        let profile = RateProfile::new(vec![0., 10., 20.], vec![1., 2., 3.], Interpolation::Step).unwrap()
+            .with_period(30.).unwrap();
+        let mut spent = vec![0.; 3];
+        profile.for_each_segment_between(5., 58., |i, duration| spent[i] += duration);
+        assert_eq!(vec![15., 20., 18.], spent);
+        assert_eq!(2, profile.segment_at(50.));
+    }
+
+    #[test]
+    fn thinning_arrives_at_the_profile_rate() {
+        let profile = RateProfile::new(vec![0., 10., 20.], vec![1., 3., 0.], Interpolation::Linear).unwrap()
+            .with_period(30.).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut arrivals = [0; 3];
+        let mut time = 0.;
+        let periods = 2000.;
+        loop {
+            time = profile.next_arrival(&mut rng, time);
+            if time >= 30. * periods {
+                break;
+            }
+            arrivals[profile.segment_at(time)] += 1;
+        }
+        for (i, arrivals) in arrivals.iter().enumerate() {
+            let expected = profile.segment_mean_rate(i) * 10. * periods;
+            assert_approx_eq!(expected, *arrivals as f64, 0.03 * expected);
+        }
+    }
+}
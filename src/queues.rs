@@ -1,440 +1,4169 @@
-use std::collections::HashMap;
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::io::{BufRead, BufReader, Read, Write};
 
-use rand::prelude::ThreadRng;
-use rand_distr::Exp;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Exp};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::customer::{ArrivingCustomer, Customer};
+use crate::balking::{Balking, BalkingPolicy};
+use crate::batches::BatchSize;
+use crate::profiles::RateProfile;
+use crate::customer::{ArrivingCustomer, Customer, CustomerClass};
+use crate::disciplines::{Discipline, FirstComeFirstServed, Preemption, QueueDiscipline, Sharing, ShortestRemainingProcessingTime};
+use crate::distributions::{DynDistribution, TimeDistribution};
+use crate::interruptions::{Breakdowns, Interruption, VacationPolicy, Vacations};
+use crate::servers::{IdleServerPolicy, ServerSelection};
+use crate::staffing::{ShiftEnd, StaffingSchedule};
+use crate::statistics::WaitStatistics;
 use crate::theory;
 use crate::theory::QueueTheory;
 
-// TODO: if I continue down this road, that Exp type will need to be parameterized, probably ThreadRng as well.
 /// So, here's the plan:
 ///
 /// We are going to simulate a queue by marching time forward. We will expose multiple abstractions
 /// and methods but just use this basic structure.
 ///
 /// 1. If the next customer is not known, generate a customer.
-/// 2.a. If we want the next customer, set time to the arrival time of that customer and fix up the queues (serve customers). Return the generated customer (copy).
-/// 2.b. If we want the next customer, find the minimum of the arrival time and the departure times of the customers being served. Set time to that, return that customer. Store the arrival if it is not being added to the queue.
+/// 2. a. If we want the next customer, set time to the arrival time of that customer and fix up the queues (serve customers). Return the generated customer (copy).
+///    b. If we want the next customer, find the minimum of the arrival time and the departure times of the customers being served. Set time to that, return that customer. Store the arrival if it is not being added to the queue.
 ///
 /// At any time we can switch between checking customer and checking events.
-pub struct Queue {
-    /// This one is really referred to as lambda in both distribution and markov model.
-    customer_arrival_lambda: f64,
-    /// This one is referred to as mu in the birth/death markov model but goes in as lambda in the exponential distribution.
-    customer_service_lambda: f64,
-    servers: u8,
+///
+/// The interarrival and service times can be any distribution, so this simulates G/G/c queues. The M/M/c queue
+/// is just the special case built by `new_exp_exp`.
+///
+/// Customers can come in several `classes`, each with their own arrival stream and service times, all sharing the
+/// same servers. The arrival streams are superposed, so `next_customers` holds the next arrival of each class.
+///
+/// With `batch_sizes`, customers arrive in batches that share one arrival time. The members of a batch arrive one
+/// after the other, as separate events at the same time, and `batches` keeps track of the batch each class is in the
+/// middle of. With bulk service (`bulk_size` above one) a server takes up to that many waiting customers at once and
+/// serves them together, so a batch still arriving is held back until its last member is in, when an idle server
+/// can take it whole.
+///
+/// When `capacity` is set, the system holds at most that many customers (in service and waiting). Arrivals that find
+/// the system full are blocked and never enter, which is the M/M/c/K queue.
+///
+/// With a finite `population` (the M/M/c//N machine repair model) there are only N customers, and those not in the
+/// system arrive independently, each after an interarrival time from when they last left. So the more customers are
+/// in the system, the fewer arrive. The customers outside are kept in `sources`, a heap by arrival time, instead of
+/// the arrival streams of the classes.
+///
+/// When a `balking` policy is given, arriving customers join with a probability that depends on how many customers
+/// they see in the system. The rest balk: they leave straight away.
+///
+/// When a `patience_distribution` is given, every customer that has to wait samples a patience from it, and samples
+/// another whenever they go back to waiting: when preempted, at the end of their turn, when handed back at the end of
+/// a shift or when fed back for rework. If they are still waiting when it runs out they abandon the queue (renege)
+/// without being served.
+///
+/// With a `retrial_distribution`, customers who find every server busy don't wait in the queue but join the `orbit`,
+/// from which they try again after a retrial time, and again, until they find a server free. They don't give up.
+///
+/// Servers aren't always there to serve. With `vacations`, a server that finishes and finds no one waiting goes away
+/// for a while. With `breakdowns`, a server fails after serving for a while and its customers wait at it while it
+/// is repaired. The vacations ending, breakdowns and repairs are kept in a calendar of their own, `server_events`,
+/// and each is an event of the server changing state.
+///
+/// With `feedback_probabilities`, customers who finish service fail with the probability of their class and need
+/// rework. They rejoin the back of the queue for another pass, with a new service time, rather than leaving, and keep
+/// their ID and time of entry so that their sojourn counts every pass.
+///
+/// With `sharing`, servers divide their time between customers. Under processor sharing the single server serves
+/// everyone in the system at once, so no one waits in the queue and every customer in service is in `in_service[0]`,
+/// in the order they will depart. Their departure times are recomputed whenever one arrives or leaves, and only the
+/// first is on the calendar. Under round robin a server serves a customer until the end of their turn, `turn_ends`,
+/// and if they aren't done by then they are preempted and go to the back of the queue.
+///
+/// After `closing_time` no one else is admitted, the arrival streams stop, but the customers already in the system
+/// are still served. `drain` closes the queue straight away and serves everyone left.
+///
+/// When a server frees up, the `discipline` chooses which waiting customer is served. The default is first come
+/// first served.
+///
+/// With `preemption`, the class of a customer is their priority (class 0 being the highest) and an arriving customer
+/// that finds every server busy interrupts the lowest priority customer in service, if it is lower than their own.
+/// This is meant to be used with the priority discipline. Under shortest remaining processing time first (`srpt`) it is
+/// the service customers have left that counts instead: an arriving customer interrupts the customer in service with
+/// the most left, if that is more than they need.
+///
+/// Customers are given sequential IDs as they arrive, and every event names the customer it happened to, so that a
+/// customer can be followed from arrival, through the start of their service, to their departure. Starting service
+/// happens at the same time as another event (an arrival or departure), so that event is returned first and the
+/// service start is kept in `pending_events` until the next call.
+///
+/// Each server serves at most one customer (or one bulk), `in_service` is indexed by server. When more than one server is idle, the
+/// `server_selection` policy chooses which serves the next customer, the default is the lowest numbered. Servers can
+/// be given their own service distributions, in which case a customer's service time depends on the server that
+/// serves them rather than on their class. The departures are kept in an event
+/// calendar, a binary heap of departure times, so finding and processing the next event is O(log c) however many
+/// servers there are.
+///
+/// All randomness comes from `rng`, so a queue built with `seeded` produces the same events every time it is
+/// run with the same seed and parameters (on the same version of `rand`).
+pub struct Queue<DA: Distribution<f64>, DS: Distribution<f64>, R: Rng = StdRng> {
+    servers: u32,
+    capacity: Option<u32>,
+    closing_time: f64,
+
+    classes: Vec<CustomerClass<DA, DS>>,
+    rng: R,
+    discipline: Box<dyn QueueDiscipline>,
+    preemption: Preemption,
+    /// Whether customers are preempted by the service they have left rather than by class.
+    srpt: bool,
+    patience_distribution: Option<Box<dyn DynDistribution>>,
+    balking: Option<Box<dyn BalkingPolicy>>,
+    batch_sizes: Option<BatchSize>,
+    /// The most customers a server takes at once, one unless the service is in bulk.
+    bulk_size: usize,
+    /// The time-varying arrival rate of class 0, when its arrivals don't come from its interarrival distribution.
+    arrival_profile: Option<RateProfile>,
 
-    customer_arrival_distribution: Exp<f64>,
-    customer_service_distribution: Exp<f64>,
-    rng: ThreadRng,
+    /// The customers outside the system, when the population is finite, by the time they arrive. `None` when the
+    /// population is infinite and customers come from the arrival streams of the classes.
+    sources: Option<BinaryHeap<Source>>,
 
+    /// The distribution of the time between the attempts of a customer in orbit, when blocked customers retry
+    /// instead of waiting.
+    retrial_distribution: Option<Box<dyn DynDistribution>>,
+    /// The customers who found every server busy and will try again, by the time they do.
+    orbit: BinaryHeap<Retrial>,
+    /// The probability that a served customer is fed back for another pass: one for every class, one per class, or
+    /// none when customers always leave.
+    feedback_probabilities: Vec<f64>,
+    /// How the servers divide their time between customers, `None` when each serves one customer until they are done.
+    sharing: Option<Sharing>,
+
+    /// The customers waiting for service, in order of arrival.
     queue: VecDeque<ArrivingCustomer>,
-    in_service: Vec<Customer>,
+    /// The calendar of abandonments, earliest on top. Entries that no longer match `abandonment_times` are of
+    /// customers who stopped waiting, and are discarded.
+    abandonments: BinaryHeap<ScheduledAbandonment>,
+    /// When each waiting customer who can abandon will, by their ID.
+    abandonment_times: HashMap<u64, f64>,
+    /// The customers each server is serving, empty when the server is idle. More than one only with bulk service.
+    in_service: Vec<Vec<Customer>>,
+    /// The number of busy servers, including those finishing a customer after going off duty.
+    busy: usize,
+    /// The number of idle servers on duty, which the server selection can choose from.
+    available: usize,
+    /// When the number of servers varies, the schedule of how many are on duty. Servers `0..staffed` are on duty,
+    /// the highest numbered go off duty first.
+    staffing: Option<StaffingSchedule>,
+    staffed: usize,
+    next_staffing_change: f64,
+    vacations: Option<Vacations<Box<dyn DynDistribution>>>,
+    breakdowns: Option<Breakdowns<Box<dyn DynDistribution>>>,
+    /// Whether each server is on vacation, and so neither serving nor available.
+    on_vacation: Vec<bool>,
+    /// The time each server has left to serve before it breaks down, counted down only while it serves.
+    uptime: Vec<f64>,
+    /// When each server breaks down if it keeps serving, infinite while it isn't serving or is being repaired.
+    failure_times: Vec<f64>,
+    /// When the turn of each server's customer ends under round robin, infinite otherwise.
+    turn_ends: Vec<f64>,
+    /// The calendar of vacations ending, breakdowns and repairs, earliest on top. Breakdowns that no longer match
+    /// `failure_times` are stale, and discarded.
+    server_events: BinaryHeap<ScheduledServerChange>,
+    /// The number of customers in service, which is more than the busy servers with bulk service.
+    serving: usize,
+    /// Keeps the idle servers, and chooses which serves the next customer.
+    server_selection: Box<dyn ServerSelection>,
+    /// The service distribution of each server, when they aren't identical. Empty when the service time is the
+    /// customer's, from their class.
+    server_service_distributions: Vec<DS>,
+    /// The event calendar: when each server is due to finish, earliest on top.
+    departures: BinaryHeap<ScheduledDeparture>,
     time: f64,
-    next_customer: ArrivingCustomer,
+    next_customers: Vec<ArrivingCustomer>,
+    /// The ID of the next customer to arrive.
+    next_id: u64,
+    /// The batch each class is arriving in, and how many more of it are still to arrive at the same time.
+    batches: Vec<(u64, u32)>,
+    /// The ID of the next batch to arrive.
+    next_batch: u64,
     last_event: QueueEvent,
+    /// Events that happened at the same time as `last_event`, to be returned next.
+    pending_events: VecDeque<QueueEvent>,
+}
+
+impl Queue<Exp<f64>, Exp<f64>> {
+    /// An M/M/c queue.
+    ///
+    /// # Arguments
+    /// * `customer_arrival_rate` The rate of arrivals, lambda.
+    /// * `customer_service_rate` The rate of service at each server, mu.
+    /// * `servers` The number of servers, c.
+    pub fn new_exp_exp(customer_arrival_rate: f64, customer_service_rate: f64, servers: u32) -> Queue<Exp<f64>, Exp<f64>> {
+        Queue::new(Exp::new(customer_arrival_rate).unwrap(), Exp::new(customer_service_rate).unwrap(), servers)
+    }
+}
+
+impl<DA: Distribution<f64>, DS: Distribution<f64>> Queue<DA, DS> {
+    /// A G/G/c queue seeded from system entropy, so it can't be reproduced. Use `seeded` for that.
+    ///
+    /// # Arguments
+    /// * `customer_arrival_distribution` The distribution of the interarrival times.
+    /// * `customer_service_distribution` The distribution of the service times.
+    /// * `servers` The number of servers, c.
+    pub fn new(customer_arrival_distribution: DA, customer_service_distribution: DS, servers: u32) -> Queue<DA, DS> {
+        Queue::with_rng(customer_arrival_distribution, customer_service_distribution, servers, StdRng::from_entropy())
+    }
+
+    /// A reproducible G/G/c queue: the same seed gives the same events.
+    ///
+    /// # Arguments
+    /// * `customer_arrival_distribution` The distribution of the interarrival times.
+    /// * `customer_service_distribution` The distribution of the service times.
+    /// * `servers` The number of servers, c.
+    /// * `seed` The seed of the random number generator.
+    pub fn seeded(customer_arrival_distribution: DA, customer_service_distribution: DS, servers: u32, seed: u64) -> Queue<DA, DS> {
+        Queue::with_rng(customer_arrival_distribution, customer_service_distribution, servers, StdRng::seed_from_u64(seed))
+    }
 }
 
-impl Queue {
-    pub fn new_exp_exp(customer_arrival_rate: f64, customer_service_rate: f64, servers: u8) -> Queue {
-        let customer_service_distribution = Exp::new(customer_service_rate).unwrap();
-        let mut rng = rand::thread_rng();
+impl<DA: Distribution<f64>, DS: Distribution<f64>, R: Rng> Queue<DA, DS, R> {
+    /// A G/G/c queue drawing from the given random number generator.
+    ///
+    /// # Arguments
+    /// * `customer_arrival_distribution` The distribution of the interarrival times.
+    /// * `customer_service_distribution` The distribution of the service times.
+    /// * `servers` The number of servers, c.
+    /// * `rng` The random number generator all samples are drawn from.
+    pub fn with_rng(customer_arrival_distribution: DA, customer_service_distribution: DS, servers: u32, mut rng: R) -> Queue<DA, DS, R> {
         let customer = ArrivingCustomer::first(&mut rng, &customer_service_distribution);
 
+        let mut server_selection = IdleServerPolicy::Lowest.selection(&[]);
+        for server in 0..servers as usize {
+            server_selection.release(server, 0.);
+        }
+
         Queue {
-            customer_arrival_lambda: customer_arrival_rate,
-            customer_service_lambda: customer_service_rate,
             servers,
+            capacity: None,
+            closing_time: f64::INFINITY,
+
+            classes: vec![CustomerClass::new(customer_arrival_distribution, customer_service_distribution)],
+            rng,
+            discipline: Box::new(FirstComeFirstServed),
+            preemption: Preemption::None,
+            srpt: false,
+            patience_distribution: None,
+            balking: None,
+            batch_sizes: None,
+            bulk_size: 1,
+            arrival_profile: None,
+            sources: None,
+            retrial_distribution: None,
+            orbit: BinaryHeap::new(),
+            feedback_probabilities: Vec::new(),
+            sharing: None,
+
+            queue: VecDeque::new(),
+            abandonments: BinaryHeap::new(),
+            abandonment_times: HashMap::new(),
+            in_service: vec![Vec::new(); servers as usize],
+            busy: 0,
+            available: servers as usize,
+            staffing: None,
+            staffed: servers as usize,
+            next_staffing_change: f64::INFINITY,
+            vacations: None,
+            breakdowns: None,
+            on_vacation: vec![false; servers as usize],
+            uptime: vec![f64::INFINITY; servers as usize],
+            failure_times: vec![f64::INFINITY; servers as usize],
+            turn_ends: vec![f64::INFINITY; servers as usize],
+            server_events: BinaryHeap::new(),
+            serving: 0,
+            server_selection,
+            server_service_distributions: Vec::new(),
+            departures: BinaryHeap::with_capacity(servers as usize),
+            time: 0.,
+            next_customers: vec![customer],
+            next_id: 0,
+            batches: vec![(0, 0)],
+            next_batch: 0,
+            last_event: QueueEvent::new(),
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Adds another class of customers, with their own arrival stream, sharing the servers. The class created by the
+    /// constructor is class 0, the classes added here are numbered from 1 in the order they are added.
+    pub fn with_class(mut self, customer_arrival_distribution: DA, customer_service_distribution: DS) -> Queue<DA, DS, R> {
+        let class = self.classes.len();
+        let customer = ArrivingCustomer::first_of_class(&mut self.rng, &customer_arrival_distribution, &customer_service_distribution, class);
+        self.classes.push(CustomerClass::new(customer_arrival_distribution, customer_service_distribution));
+        self.next_customers.push(customer);
+        self.batches.push((0, 0));
+        self
+    }
+
+    /// Replaces the queue discipline, which decides who is served next when a server frees up.
+    pub fn with_discipline<Q: QueueDiscipline + 'static>(mut self, discipline: Q) -> Queue<DA, DS, R> {
+        assert!(!discipline.by_service_time() || self.server_service_distributions.is_empty(),
+                "A discipline by service time needs the customers' service times, not the servers'.");
+        self.discipline = Box::new(discipline);
+        self
+    }
+
+    /// Replaces the policy choosing which idle server serves the next customer. Every server must be idle, so this is
+    /// for setting up the queue before it starts.
+    pub fn with_server_selection<S: ServerSelection + 'static>(mut self, mut server_selection: S) -> Queue<DA, DS, R> {
+        assert!(self.busy == 0, "The server selection can only be replaced while every server is idle.");
+        for server in 0..self.staffed {
+            server_selection.release(server, self.time);
+        }
+        self.server_selection = Box::new(server_selection);
+        self
+    }
+
+    /// Varies the number of servers on duty over time by `staffing`, up to `servers`, which are all there are. When
+    /// the number goes down the highest numbered servers go off duty, and what they do with the customers they are
+    /// serving is the schedule's shift end.
+    pub fn with_staffing(mut self, staffing: StaffingSchedule) -> Queue<DA, DS, R> {
+        assert!(self.busy == 0, "The staffing can only be set while every server is idle.");
+        assert!(self.vacations.is_none() && self.breakdowns.is_none(), "Servers on a staffing schedule don't take vacations or break down.");
+        assert!(staffing.max_servers() <= self.servers, "The staffing can't have more servers on duty than there are.");
+        assert!(self.sharing != Some(Sharing::ProcessorSharing), "A shared processor is always there to serve.");
+        let staffed = staffing.servers_at(self.time) as usize;
+        for server in staffed..self.staffed {
+            self.server_selection.withdraw(server);
+        }
+        self.available = staffed;
+        self.staffed = staffed;
+        self.next_staffing_change = staffing.next_change(self.time);
+        self.staffing = Some(staffing);
+        self
+    }
+
+    /// Puts servers on or off duty at the next change of the staffing schedule. Customers that servers going off duty
+    /// hand back, and those that servers coming on duty start serving, are pending events.
+    fn change_staffing(&mut self) {
+        let staffing = self.staffing.as_ref().expect("Staffing only changes with a schedule.");
+        self.time = self.next_staffing_change;
+        self.next_staffing_change = staffing.next_change(self.time);
+        let staffed = staffing.servers_at(self.time) as usize;
+        let shift_end = staffing.shift_end();
+
+        for server in self.staffed..staffed {
+            // A server still finishing a customer from its last shift is released when it does.
+            if self.in_service[server].is_empty() {
+                self.server_selection.release(server, self.time);
+                self.available += 1;
+            }
+        }
+        let leaving = staffed..self.staffed;
+        self.staffed = staffed;
+        for server in leaving {
+            if self.in_service[server].is_empty() {
+                self.server_selection.withdraw(server);
+                self.available -= 1;
+            } else if shift_end == ShiftEnd::Requeue && self.in_service[server][0].time_of_departure > self.time {
+                // Customers finishing right at the change depart rather than being handed back with nothing to go.
+                // Back to the front of the queue, in the order they were taken, to resume where they left off.
+                let handed_back = self.free_server(server);
+                for customer in &handed_back {
+                    self.pending_events.push_back(self.latest_event().preempted(self.time, customer, server));
+                }
+                for customer in handed_back.iter().rev() {
+                    if self.retrial_distribution.is_some() {
+                        self.enter_orbit(customer.preempt(self.time, false));
+                    } else {
+                        let waiting_customer = self.start_waiting(customer.preempt(self.time, false));
+                        self.queue.push_front(waiting_customer);
+                    }
+                }
+            }
+        }
+        self.serve_waiting();
+        self.discard_stale_departures();
+    }
+
+    /// Whether the staffing changes before anything else happens, or with nothing else to happen while customers
+    /// wait for a server to come on duty.
+    fn staffing_changes_next(&self) -> bool {
+        let next_event_time = self.next_customer_event_time();
+        self.next_staffing_change <= next_event_time && (next_event_time.is_finite() || !self.queue.is_empty())
+    }
+
+    /// Gives each server its own service distribution, as when some servers are faster than others. The service
+    /// times of the classes are then ignored: a customer's service time is sampled when they start service, from the
+    /// distribution of their server. A preempted customer keeps their service time wherever they resume. So the
+    /// disciplines by service time, which order the waiting customers by theirs, can't be used.
+    pub fn with_server_service_distributions(mut self, server_service_distributions: Vec<DS>) -> Queue<DA, DS, R> {
+        assert_eq!(self.servers as usize, server_service_distributions.len(), "Every server needs a service distribution.");
+        assert!(!self.srpt, "Under SRPT the service times are the customers', not the servers'.");
+        assert!(!self.discipline.by_service_time(),
+                "A discipline by service time needs the customers' service times, not the servers'.");
+        self.server_service_distributions = server_service_distributions;
+        self
+    }
+
+    /// Limits the customers to a finite population of `population`, the M/M/c//N machine repair model. Each
+    /// customer outside the system arrives after an interarrival time from the time they left, the first time from
+    /// now. Only a single class is supported.
+    pub fn with_population(mut self, population: u32) -> Queue<DA, DS, R> {
+        assert_eq!(1, self.classes.len(), "A finite population only has one class of customers.");
+        assert!(self.batch_sizes.is_none(), "The customers of a finite population arrive one at a time.");
+        assert!(self.arrival_profile.is_none(), "The customers of a finite population arrive after their interarrival times.");
+        let CustomerClass { arrival_distribution, service_distribution } = &self.classes[0];
+        let (rng, time) = (&mut self.rng, self.time);
+        let sources = (0..population)
+            .map(|_| Source(ArrivingCustomer::after(rng, arrival_distribution, service_distribution, 0, time)))
+            .collect();
+        self.sources = Some(sources);
+        self
+    }
+
+    /// A customer left the system at the current time, served or not. When the population is finite they go back
+    /// to the sources, to arrive again after another interarrival time.
+    fn leave(&mut self, class: usize) {
+        if let Some(sources) = &mut self.sources {
+            let CustomerClass { arrival_distribution, service_distribution } = &self.classes[class];
+            sources.push(Source(ArrivingCustomer::after(&mut self.rng, arrival_distribution, service_distribution, class, self.time)));
+        }
+    }
+
+    /// Lets arriving customers interrupt the service of customers of lower priority, that is of a higher class.
+    pub fn with_preemption(mut self, preemption: Preemption) -> Queue<DA, DS, R> {
+        assert!(preemption == Preemption::None || self.bulk_size == 1, "Customers served in bulk can't be preempted.");
+        assert!(preemption == Preemption::None || self.retrial_distribution.is_none(), "Customers who retry can't preempt.");
+        assert!(preemption == Preemption::None || self.sharing.is_none(), "Servers that share their time can't be preempted.");
+        self.preemption = preemption;
+        self
+    }
+
+    /// Serves the customer with the least service left first, shortest remaining processing time first. An arriving
+    /// customer who needs less than the customer in service with the most left preempts them, and they resume later.
+    /// The service times must be known as customers arrive, so they can't depend on the server.
+    pub fn with_srpt(mut self) -> Queue<DA, DS, R> {
+        assert!(self.server_service_distributions.is_empty(), "Under SRPT the service times are the customers', not the servers'.");
+        self.discipline = Box::new(ShortestRemainingProcessingTime);
+        self.srpt = true;
+        self.with_preemption(Preemption::Resume)
+    }
+
+    /// Class 0 arrives as a nonhomogeneous Poisson process with the rate of `arrival_profile`, instead of from its
+    /// interarrival distribution. With batches, it is the batches that arrive at that rate.
+    pub fn with_arrival_profile(mut self, arrival_profile: RateProfile) -> Queue<DA, DS, R> {
+        assert!(self.sources.is_none(), "The customers of a finite population arrive after their interarrival times.");
+        let (rng, time) = (&mut self.rng, self.time);
+        let service_distribution = &self.classes[0].service_distribution;
+        self.next_customers[0] = ArrivingCustomer::after(rng, &arrival_profile.interarrivals_from(time), service_distribution, 0, time);
+        self.arrival_profile = Some(arrival_profile);
+        self
+    }
+
+    /// Customers arrive in batches, with sizes from `batch_sizes`. The interarrival times are between batches.
+    pub fn with_batch_arrivals(mut self, batch_sizes: BatchSize) -> Queue<DA, DS, R> {
+        assert!(self.sources.is_none(), "The customers of a finite population arrive one at a time.");
+        self.batch_sizes = Some(batch_sizes);
+        self
+    }
+
+    /// Each server takes up to `bulk_size` waiting customers at once, and serves them together: they all take the
+    /// service time of the first and depart together.
+    pub fn with_bulk_service(mut self, bulk_size: u32) -> Queue<DA, DS, R> {
+        assert!(bulk_size > 0, "A server takes at least one customer at a time.");
+        assert!(bulk_size == 1 || self.retrial_distribution.is_none(), "Customers who retry are served one at a time.");
+        assert!(self.preemption == Preemption::None, "Customers served in bulk can't be preempted.");
+        assert!(bulk_size == 1 || self.sharing.is_none(), "Servers that share their time serve customers one at a time.");
+        self.bulk_size = bulk_size as usize;
+        self
+    }
+
+    /// Limits the number of customers in the system (in service and waiting), K. Must be at least the number of servers.
+    pub fn with_capacity(mut self, capacity: u32) -> Queue<DA, DS, R> {
+        assert!(capacity >= self.servers, "The capacity must leave room for every server to be busy.");
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Customers who find every server busy join an orbit instead of the queue, and try again after a time sampled
+    /// from `retrial_distribution`, until they find a server free.
+    pub fn with_retrials<DR: Distribution<f64> + 'static>(mut self, retrial_distribution: DR) -> Queue<DA, DS, R> {
+        assert!(self.preemption == Preemption::None, "Customers who retry can't preempt.");
+        assert!(self.bulk_size == 1, "Customers who retry are served one at a time.");
+        assert!(self.sharing.is_none(), "Customers who retry don't share servers.");
+        self.retrial_distribution = Some(Box::new(retrial_distribution));
+        self
+    }
+
+    /// Served customers need rework with a feedback probability, and rejoin the back of the queue for another pass
+    /// instead of leaving. One probability applies to every class, or give one for each class in order.
+    pub fn with_feedback(mut self, feedback_probabilities: Vec<f64>) -> Queue<DA, DS, R> {
+        assert!(feedback_probabilities.iter().all(|p| (0. ..1.).contains(p)),
+                "Customers must leave eventually, so feedback probabilities are at least zero and below one.");
+        self.feedback_probabilities = feedback_probabilities;
+        self
+    }
+
+    /// The probability that a served customer of the class is fed back for another pass.
+    fn feedback_probability(&self, class: usize) -> f64 {
+        match self.feedback_probabilities.as_slice() {
+            [probability] => *probability,
+            probabilities => probabilities.get(class).copied().unwrap_or(0.),
+        }
+    }
+
+    /// Whether a customer of the class just served needs rework. Only draws a number when they might, so that
+    /// queues without feedback sample the same as before.
+    fn needs_rework(&mut self, class: usize) -> bool {
+        let probability = self.feedback_probability(class);
+        probability > 0. && self.rng.gen::<f64>() < probability
+    }
+
+    /// A customer who was fed back rejoins the back of the queue, or with retrials, tries for a server like an
+    /// arrival and goes into orbit if they are all busy.
+    fn rejoin(&mut self, customer: ArrivingCustomer) {
+        if self.sharing == Some(Sharing::ProcessorSharing) {
+            self.share_processor(customer);
+        } else if self.retrial_distribution.is_none() {
+            let waiting_customer = self.start_waiting(customer);
+            self.queue.push_back(waiting_customer);
+        } else if self.available > 0 {
+            self.start_service(customer);
+        } else {
+            self.enter_orbit(customer);
+        }
+    }
+
+    /// Servers divide their time between customers by `sharing`. Processor sharing is for a single server that is
+    /// always there, and neither can be mixed with preemption by priority, retrials, bulk service or breakdowns.
+    pub fn with_sharing(mut self, sharing: Sharing) -> Queue<DA, DS, R> {
+        assert!(self.preemption == Preemption::None, "Servers that share their time can't be preempted.");
+        assert!(self.retrial_distribution.is_none(), "Customers who retry don't share servers.");
+        assert!(self.bulk_size == 1, "Servers that share their time serve customers one at a time.");
+        assert!(self.breakdowns.is_none(), "Servers that share their time don't break down.");
+        if sharing == Sharing::ProcessorSharing {
+            assert!(self.servers == 1, "Processor sharing is for a single server.");
+            assert!(self.staffing.is_none() && self.vacations.is_none(), "A shared processor is always there to serve.");
+            assert!(self.server_service_distributions.is_empty(), "A shared processor takes the service times of the classes.");
+        }
+        self.sharing = Some(sharing);
+        self
+    }
+
+    /// Servers that finish serving and find no one waiting go on vacation for a time sampled from
+    /// `vacation_distribution`. After a single vacation a server waits idle if there is still no one, with multiple
+    /// vacations it goes off again. Servers on vacation don't serve, even when customers arrive, and start out idle.
+    pub fn with_vacations<DV: Distribution<f64> + 'static>(mut self, policy: VacationPolicy, vacation_distribution: DV) -> Queue<DA, DS, R> {
+        assert!(self.staffing.is_none(), "Servers on a staffing schedule don't take vacations.");
+        assert!(self.preemption == Preemption::None, "Servers that take vacations can't be preempted.");
+        assert!(self.sharing != Some(Sharing::ProcessorSharing), "A shared processor is always there to serve.");
+        self.vacations = Some(Vacations { policy, distribution: Box::new(vacation_distribution) });
+        self
+    }
+
+    /// Servers break down after serving for a time sampled from `time_to_failure`, counting only the time they spend
+    /// serving, and are repaired in a time sampled from `repair`. Their customers wait at them while they are, and
+    /// `interruption` says whether their service then resumes or starts over.
+    pub fn with_breakdowns<DF: Distribution<f64> + 'static, DR: Distribution<f64> + 'static>(
+        mut self,
+        time_to_failure: DF,
+        repair: DR,
+        interruption: Interruption,
+    ) -> Queue<DA, DS, R> {
+        assert!(self.staffing.is_none(), "Servers on a staffing schedule don't break down.");
+        assert!(self.preemption == Preemption::None, "Servers that break down can't be preempted.");
+        assert!(self.sharing.is_none(), "Servers that share their time don't break down.");
+        let rng = &mut self.rng;
+        self.uptime = (0..self.servers).map(|_| time_to_failure.sample(rng)).collect();
+        self.breakdowns = Some(Breakdowns { time_to_failure: Box::new(time_to_failure), repair: Box::new(repair), interruption });
+        self
+    }
+
+    /// Stops admitting customers after `closing_time`, like a service desk closing its doors. Those already in the
+    /// system are still served, after which there are no more events.
+    pub fn with_closing_time(mut self, closing_time: f64) -> Queue<DA, DS, R> {
+        self.closing_time = closing_time;
+        self
+    }
+
+    /// Customers that have to wait will abandon the queue after a patience sampled from `patience_distribution`.
+    pub fn with_patience<DP: Distribution<f64> + 'static>(mut self, patience_distribution: DP) -> Queue<DA, DS, R> {
+        self.patience_distribution = Some(Box::new(patience_distribution));
+        self
+    }
+
+    /// Arriving customers only join with the probability given by `balking` for the number they see in the system.
+    pub fn with_balking<B: BalkingPolicy + 'static>(mut self, balking: B) -> Queue<DA, DS, R> {
+        self.balking = Some(Box::new(balking));
+        self
+    }
+
+    /// Whether an arriving customer decides not to join, given the number of customers in the system.
+    fn balks(&mut self) -> bool {
+        let in_system = self.in_system() as u64;
+        match &self.balking {
+            Some(balking) => self.rng.gen::<f64>() >= balking.join_probability(in_system),
+            None => false,
+        }
+    }
+
+    /// Whether an arriving customer would find the system full and be blocked.
+    fn is_full(&self) -> bool {
+        match self.capacity {
+            Some(capacity) => self.in_system() >= capacity as usize,
+            None => false,
+        }
+    }
+
+    /// The number of customers in the system, in service, waiting and in orbit.
+    pub fn in_system(&self) -> usize {
+        self.serving + self.queue.len() + self.orbit.len()
+    }
+
+    /// The number of customers in orbit, who will try for a server again.
+    pub fn in_orbit(&self) -> usize {
+        self.orbit.len()
+    }
+
+    /// Whether no one is waiting for a server, in the queue or in orbit.
+    fn no_one_waiting(&self) -> bool {
+        self.queue.is_empty() && self.orbit.is_empty()
+    }
+
+    /// Whether no one is in the system, in service or waiting.
+    pub fn is_idle(&self) -> bool {
+        self.in_system() == 0
+    }
+
+    /// The time of the next event, or `None` when the queue `is_finished`.
+    pub fn peek_next_event_time(&self) -> Option<f64> {
+        if !self.pending_events.is_empty() {
+            return Some(self.time);
+        }
+        if self.server_changes_next() {
+            return Some(self.next_server_change());
+        }
+        let next_event_time = self.next_customer_event_time();
+        if next_event_time.is_finite() {
+            Some(next_event_time)
+        } else if !self.queue.is_empty() && self.next_staffing_change.is_finite() {
+            // Customers are waiting for a server to come on duty.
+            Some(self.next_staffing_change)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the queue is past closing time and idle, so that there will be no more events.
+    pub fn is_finished(&self) -> bool {
+        self.peek_next_event_time().is_none()
+    }
+
+    /// Simulates up to the next event and returns it.
+    ///
+    /// # Panics
+    /// When the queue `is_finished`, since there is no next event.
+    pub fn next_event(&mut self) -> &QueueEvent {
+        if let Some(event) = self.pending_events.pop_front() {
+            self.last_event = event;
+            return &self.last_event;
+        }
+        while self.staffing_changes_next() {
+            self.change_staffing();
+            if let Some(event) = self.pending_events.pop_front() {
+                self.last_event = event;
+                return &self.last_event;
+            }
+        }
+        if self.server_changes_next() {
+            self.change_server();
+            self.last_event = self.pending_events.pop_front().expect("Every server change is an event.");
+            return &self.last_event;
+        }
+
+        let (server, departure_time) = self.next_departure();
+        let (abandoning_customer, abandonment_time) = self.next_abandonment();
+
+        let (class, next_arrival_time) = self.next_arrival();
+        let retrial_time = self.next_retrial();
+        assert!(self.next_customer_event_time().is_finite(), "The queue is closed and idle, there are no more events.");
+
+        if abandonment_time < departure_time && abandonment_time < next_arrival_time {
+            // A waiting customer runs out of patience before anything else happens.
+            let index = self.queue.iter().position(|customer| customer.id() == abandoning_customer)
+                .expect("The next abandonment is always a waiting customer.");
+            let waiting_customer = self.leave_queue(index);
+            self.time = abandonment_time;
+            let abandoned_customer = Customer::abandon(waiting_customer, self.time);
+            self.last_event = self.last_event.abandonment(self.time, &abandoned_customer);
+            self.leave(abandoned_customer.class());
+        } else if retrial_time < departure_time && retrial_time < next_arrival_time {
+            // A customer in orbit tries again, and goes back if every server is still busy.
+            let Retrial { customer, .. } = self.orbit.pop().expect("The next retrial is from the orbit.");
+            self.time = retrial_time;
+            let retrying_customer = customer.retry();
+            self.last_event = self.last_event.retrial(self.time, &retrying_customer);
+            if self.available > 0 {
+                self.start_service(retrying_customer);
+            } else {
+                self.enter_orbit(retrying_customer);
+            }
+        } else if departure_time < next_arrival_time && departure_time < self.in_service[server][0].time_of_departure {
+            // The turn of a customer in service ends before they are done, and they go to the back of the queue.
+            self.departures.pop();
+            self.time = departure_time;
+            let interrupted_customer = self.free_server(server).pop().expect("Only busy servers are freed.");
+            self.last_event = self.last_event.preempted(self.time, &interrupted_customer, server);
+            let waiting_customer = self.start_waiting(interrupted_customer.preempt(self.time, false));
+            self.queue.push_back(waiting_customer);
+            self.serve_waiting();
+        } else if departure_time < next_arrival_time {
+            // A customer in service departs before the next customer arrives.
+            self.departures.pop();
+            self.time = departure_time;
+            let served_customers = match self.sharing {
+                Some(Sharing::ProcessorSharing) => self.leave_processor(server),
+                _ => self.free_server(server),
+            };
+            // Customers served in bulk depart together, one event each.
+            for (i, served_customer) in served_customers.into_iter().enumerate() {
+                let class = served_customer.class();
+                let rework = self.needs_rework(class);
+                let departure = if rework {
+                    self.latest_event().feedback(self.time, &served_customer, server)
+                } else {
+                    self.latest_event().departure(self.time, &served_customer, server)
+                };
+                if i == 0 {
+                    self.last_event = departure;
+                } else {
+                    self.pending_events.push_back(departure);
+                }
+                if rework {
+                    let service_time = self.classes[class].service_distribution.sample(&mut self.rng);
+                    self.rejoin(served_customer.feed_back(self.time, service_time));
+                } else {
+                    self.leave(class);
+                }
+            }
+
+            self.serve_waiting();
+            if self.no_one_waiting() && self.in_service[server].is_empty() {
+                self.start_vacation(server);
+            }
+        } else {
+            let arriving_customer = match &mut self.sources {
+                Some(sources) => sources.pop().expect("The next arrival is from the sources.").0,
+                None => self.next_customers[class],
+            }.with_id(self.next_id);
+            self.next_id += 1;
+            self.time = next_arrival_time;
+
+            let (batch, still_arriving) = match self.batches[class] {
+                (batch, still_arriving) if still_arriving > 0 => (batch, still_arriving - 1),
+                _ => {
+                    let size = self.batch_sizes.map_or(1, |batch_sizes| batch_sizes.sample(&mut self.rng));
+                    self.next_batch += 1;
+                    (self.next_batch - 1, size - 1)
+                }
+            };
+            self.batches[class] = (batch, still_arriving);
+            let arriving_customer = arriving_customer.with_batch(batch);
+            // With bulk service, a batch waits until it is all in so that it can be served together.
+            let held_back = self.bulk_size > 1 && still_arriving > 0;
+
+            if self.is_full() {
+                self.last_event = self.last_event.blocked(self.time, &arriving_customer);
+                self.leave(class);
+            } else if self.balks() {
+                self.last_event = self.last_event.balked(self.time, &arriving_customer);
+                self.leave(class);
+            } else if let Some(server) = self.preemptable(&arriving_customer) {
+                self.last_event = self.last_event.arrival(self.time, &arriving_customer);
+                for interrupted_customer in self.free_server(server) {
+                    let preempted = self.latest_event().preempted(self.time, &interrupted_customer, server);
+                    self.pending_events.push_back(preempted);
+                    let repeat = self.preemption == Preemption::Repeat;
+                    let waiting_customer = self.start_waiting(interrupted_customer.preempt(self.time, repeat));
+                    self.queue.push_back(waiting_customer);
+                }
+                self.start_service(arriving_customer);
+            } else if self.sharing == Some(Sharing::ProcessorSharing) {
+                self.last_event = self.last_event.arrival(self.time, &arriving_customer);
+                self.share_processor(arriving_customer);
+            } else if self.available == 0 && self.retrial_distribution.is_some() {
+                self.last_event = self.last_event.arrival(self.time, &arriving_customer);
+                self.enter_orbit(arriving_customer);
+            } else if self.available == 0 || held_back {
+                self.last_event = self.last_event.arrival(self.time, &arriving_customer);
+                let waiting_customer = self.start_waiting(arriving_customer);
+                self.queue.push_back(waiting_customer);
+            } else {
+                self.last_event = self.last_event.arrival(self.time, &arriving_customer);
+                self.start_service(arriving_customer);
+                // The rest of a batch that was held back for bulk service may need more servers.
+                self.serve_waiting();
+            }
+            if self.sources.is_none() {
+                let CustomerClass { arrival_distribution, service_distribution } = &self.classes[class];
+                self.next_customers[class] = if still_arriving > 0 {
+                    ArrivingCustomer::next_in_batch(&mut self.rng, service_distribution, arriving_customer)
+                } else if let (0, Some(profile)) = (class, &self.arrival_profile) {
+                    let interarrivals = profile.interarrivals_from(self.time);
+                    ArrivingCustomer::after(&mut self.rng, &interarrivals, service_distribution, class, self.time)
+                } else {
+                    ArrivingCustomer::next_1_fcfs(&mut self.rng, arrival_distribution, service_distribution, arriving_customer)
+                };
+            }
+        };
+        self.discard_stale_departures();
+        self.discard_stale_breakdowns();
+
+        &self.last_event
+    }
+
+    /// The most recent event, which the next event's counts follow on from: the last of the pending events, or the
+    /// last event returned if none are pending.
+    fn latest_event(&self) -> QueueEvent {
+        *self.pending_events.back().unwrap_or(&self.last_event)
+    }
+
+    /// Starts serving the customer on an idle server at the current time, and puts their departure on the calendar.
+    /// The event of the service starting is pending, it follows the event that caused it.
+    ///
+    /// With bulk service the server takes more of the waiting customers along, chosen by the discipline, and serves
+    /// them all in the service time of this customer.
+    fn start_service(&mut self, arriving_customer: ArrivingCustomer) {
+        let server = self.server_selection.acquire(&mut self.rng);
+        self.available -= 1;
+        let arriving_customer = match self.server_service_distributions.get(server) {
+            Some(distribution) if !arriving_customer.was_preempted() => {
+                arriving_customer.with_service_time(distribution.sample(&mut self.rng))
+            }
+            _ => arriving_customer,
+        };
+        let customer = Customer::start_service(arriving_customer, self.time);
+        self.pending_events.push_back(self.latest_event().service_start(self.time, &customer, server));
+        if let Some(Sharing::RoundRobin { quantum }) = self.sharing {
+            self.turn_ends[server] = self.time + quantum;
+        }
+        self.departures.push(ScheduledDeparture { time: customer.time_of_departure.min(self.turn_ends[server]), server });
+        self.start_operating(server);
+        self.in_service[server].push(customer);
+        self.busy += 1;
+        self.serving += 1;
+
+        while self.in_service[server].len() < self.bulk_size && !self.queue.is_empty() {
+            let next_to_be_served = self.discipline.select(&self.queue, &mut self.rng);
+            let waiting_customer = self.leave_queue(next_to_be_served);
+            let customer = Customer::start_service(waiting_customer.with_service_time(arriving_customer.remaining_service()), self.time);
+            self.pending_events.push_back(self.latest_event().service_start(self.time, &customer, server));
+            self.in_service[server].push(customer);
+            self.serving += 1;
+        }
+    }
+
+    /// The customer arrives at the shared processor and starts service straight away, slowing down everyone else in
+    /// service. Their departures are recomputed, and the calendar has the first of them.
+    fn share_processor(&mut self, arriving_customer: ArrivingCustomer) {
+        let n = self.in_service[0].len();
+        if n == 0 {
+            self.start_service(arriving_customer);
+            return;
+        }
+        let time = self.time;
+        for customer in &mut self.in_service[0] {
+            *customer = customer.share(time, n, n + 1);
+        }
+        let customer = Customer::start_service(arriving_customer, time).share(time, 1, n + 1);
+        self.pending_events.push_back(self.latest_event().service_start(time, &customer, 0));
+        // Everyone is served at the same rate, so the order they depart in doesn't change as more arrive or leave.
+        let index = self.in_service[0].partition_point(|other| other.time_of_departure <= customer.time_of_departure);
+        self.in_service[0].insert(index, customer);
+        self.serving += 1;
+        self.departures.push(ScheduledDeparture { time: self.in_service[0][0].time_of_departure, server: 0 });
+    }
+
+    /// The first customer of the shared processor departs, and the others speed up. Returns the departing customer,
+    /// the processor is freed if they were the last.
+    fn leave_processor(&mut self, server: usize) -> Vec<Customer> {
+        let n = self.in_service[server].len();
+        if n == 1 {
+            return self.free_server(server);
+        }
+        let customer = self.in_service[server].remove(0);
+        self.serving -= 1;
+        let time = self.time;
+        for other in &mut self.in_service[server] {
+            *other = other.share(time, n, n - 1);
+        }
+        self.departures.push(ScheduledDeparture { time: self.in_service[server][0].time_of_departure, server });
+        vec![customer]
+    }
+
+    /// Starts serving waiting customers, chosen by the discipline, as long as there are idle servers.
+    fn serve_waiting(&mut self) {
+        while self.available > 0 && !self.queue.is_empty() {
+            let next_to_be_served = self.discipline.select(&self.queue, &mut self.rng);
+            let waiting_customer = self.leave_queue(next_to_be_served);
+            self.start_service(waiting_customer);
+        }
+    }
+
+    /// Frees the server and returns the customers it was serving. Their departure is left on the calendar if it is
+    /// still there, `next_departure` discards it. A server that is off duty isn't available to serve again.
+    fn free_server(&mut self, server: usize) -> Vec<Customer> {
+        let customers = std::mem::take(&mut self.in_service[server]);
+        assert!(!customers.is_empty(), "Only busy servers are freed.");
+        if self.breakdowns.is_some() {
+            // The time to failure only counts down while serving.
+            self.uptime[server] = self.failure_times[server] - self.time;
+            self.failure_times[server] = f64::INFINITY;
+        }
+        if server < self.staffed {
+            self.server_selection.release(server, self.time);
+            self.available += 1;
+        }
+        self.busy -= 1;
+        self.serving -= customers.len();
+        customers
+    }
+
+    /// Returns the server serving the customer the arriving customer would preempt, if any. That is the lowest
+    /// priority customer in service, if every server is busy and their priority is lower than the arriving customer's.
+    /// Between customers of the same class, the one that started service last is preempted.
+    ///
+    /// This looks at every server, so unlike the other events an arrival that can preempt is O(c).
+    fn preemptable(&self, arriving_customer: &ArrivingCustomer) -> Option<usize> {
+        if self.preemption == Preemption::None || self.available > 0 {
+            return None;
+        }
+        if self.srpt {
+            return self.preemptable_by_remaining_service(arriving_customer);
+        }
+
+        let mut preempted: Option<(usize, &Customer)> = None;
+        for (server, customers) in self.in_service.iter().enumerate().take(self.staffed) {
+            let customer = customers.first().expect("Every server is busy.");
+            let lower = match preempted {
+                Some((_, lowest)) => (customer.class(), customer.time_of_service_start)
+                    > (lowest.class(), lowest.time_of_service_start),
+                None => customer.class() > arriving_customer.class(),
+            };
+            if lower {
+                preempted = Some((server, customer));
+            }
+        }
+
+        preempted.map(|(server, _)| server)
+    }
+
+    /// Returns the server serving the customer with the most service left, if every server is busy and that is more
+    /// than the arriving customer needs. Between customers with as much left, the one that started service last is
+    /// preempted.
+    fn preemptable_by_remaining_service(&self, arriving_customer: &ArrivingCustomer) -> Option<usize> {
+        let mut preempted: Option<(usize, f64, f64)> = None;
+        for (server, customers) in self.in_service.iter().enumerate().take(self.staffed) {
+            let customer = customers.first().expect("Every server is busy.");
+            let remaining_service = customer.remaining_service(self.time);
+            let longer = match preempted {
+                Some((_, longest, since)) => (remaining_service, customer.time_of_service_start) > (longest, since),
+                None => remaining_service > arriving_customer.remaining_service(),
+            };
+            if longer {
+                preempted = Some((server, remaining_service, customer.time_of_service_start));
+            }
+        }
+
+        preempted.map(|(server, _, _)| server)
+    }
+
+    /// Returns the class of the next customer to arrive and the time they arrive.
+    ///
+    /// When no one arrives before closing time, time is set to infinity and the class shouldn't be used.
+    fn next_arrival(&self) -> (usize, f64) {
+        if let Some(sources) = &self.sources {
+            return match sources.peek() {
+                Some(Source(customer)) if customer.arrival_time() <= self.closing_time => (customer.class(), customer.arrival_time()),
+                _ => (0, f64::INFINITY),
+            };
+        }
+
+        let mut next_arrival_time = f64::INFINITY;
+        let mut class = 0;
+        for (i, customer) in self.next_customers.iter().enumerate() {
+            if customer.arrival_time() < next_arrival_time && customer.arrival_time() <= self.closing_time {
+                next_arrival_time = customer.arrival_time();
+                class = i;
+            }
+        }
+
+        (class, next_arrival_time)
+    }
+
+    /// Returns the time the next customer in orbit tries again, infinite if no one is in orbit.
+    fn next_retrial(&self) -> f64 {
+        self.orbit.peek().map_or(f64::INFINITY, |retrial| retrial.time)
+    }
+
+    /// Sends a customer who found every server busy into orbit, to try again after a retrial time.
+    fn enter_orbit(&mut self, customer: ArrivingCustomer) {
+        let distribution = self.retrial_distribution.as_ref().expect("Customers only go into orbit when they retry.");
+        let time = self.time + distribution.sample_dyn(&mut self.rng);
+        self.orbit.push(Retrial { time, customer });
+    }
+
+    /// The time of the next event that happens to a customer: a departure, abandonment, arrival or retrial.
+    fn next_customer_event_time(&self) -> f64 {
+        self.next_departure().1.min(self.next_abandonment().1).min(self.next_arrival().1).min(self.next_retrial())
+    }
+
+    /// Returns the server of the next departure and its time, from the top of the calendar.
+    ///
+    /// When there are no customers in service, time is set to infinity and the server shouldn't be used.
+    fn next_departure(&self) -> (usize, f64) {
+        match self.departures.peek() {
+            Some(departure) => (departure.server, departure.time),
+            None => (0, f64::INFINITY),
+        }
+    }
+
+    /// Departures of customers that were preempted, or whose departure was recomputed when the processor they share
+    /// sped up or slowed down, are left on the calendar, so this discards entries from the top until one matches the
+    /// customer its server is serving (or the end of their turn). That way the top of the calendar is always the next
+    /// departure. Two entries can only match the same customer if they have the same time, so whichever is used,
+    /// the departure happens at the right time.
+    fn discard_stale_departures(&mut self) {
+        while let Some(departure) = self.departures.peek() {
+            match self.in_service[departure.server].first() {
+                Some(customer) if customer.time_of_departure.min(self.turn_ends[departure.server]) == departure.time => return,
+                _ => {
+                    self.departures.pop();
+                }
+            }
+        }
+    }
+
+    /// The time of the next vacation ending, breakdown or repair, infinite if there is none.
+    fn next_server_change(&self) -> f64 {
+        self.server_events.peek().map_or(f64::INFINITY, |change| change.time)
+    }
+
+    /// Whether a server changes state before any customer event, or with no customer event to happen while
+    /// customers wait for a server to come back from vacation. Customer events at the same time go first.
+    fn server_changes_next(&self) -> bool {
+        let next_event_time = self.next_customer_event_time();
+        self.next_server_change() < next_event_time && (next_event_time.is_finite() || !self.queue.is_empty())
+    }
+
+    /// Processes the next vacation ending, breakdown or repair on the server calendar. Its event is pending, followed
+    /// by the service starts it leads to.
+    fn change_server(&mut self) {
+        let ScheduledServerChange { time, server, change } = self.server_events.pop()
+            .expect("A server only changes when one is due.");
+        self.time = time;
+        match change {
+            ServerChange::VacationEnd => {
+                let multiple = matches!(&self.vacations, Some(vacations) if vacations.policy == VacationPolicy::Multiple);
+                if multiple && self.no_one_waiting() {
+                    self.start_vacation(server);
+                } else {
+                    self.pending_events.push_back(self.latest_event().server_change(time, EventType::ServerReturn, server));
+                    self.on_vacation[server] = false;
+                    self.server_selection.release(server, time);
+                    self.available += 1;
+                    self.serve_waiting();
+                }
+            }
+            ServerChange::Breakdown => {
+                let breakdowns = self.breakdowns.as_ref().expect("Servers only break down with breakdowns.");
+                let repair_time = breakdowns.repair.sample_dyn(&mut self.rng);
+                self.uptime[server] = breakdowns.time_to_failure.sample_dyn(&mut self.rng);
+                self.failure_times[server] = f64::INFINITY;
+                let repeat = breakdowns.interruption == Interruption::Restart;
+                for customer in &mut self.in_service[server] {
+                    *customer = customer.interrupt(time, repair_time, repeat);
+                }
+                // The departure already on the calendar is stale now, and discarded when it comes up.
+                let time_of_departure = self.in_service[server][0].time_of_departure;
+                self.departures.push(ScheduledDeparture { time: time_of_departure, server });
+                self.server_events.push(ScheduledServerChange { time: time + repair_time, server, change: ServerChange::Repaired });
+                self.pending_events.push_back(self.latest_event().server_change(time, EventType::Breakdown, server));
+            }
+            ServerChange::Repaired => {
+                self.pending_events.push_back(self.latest_event().server_change(time, EventType::ServerReturn, server));
+                self.start_operating(server);
+            }
+        }
+        self.discard_stale_departures();
+        self.discard_stale_breakdowns();
+    }
+
+    /// Sends the idle `server` on vacation, if servers take them, or on another one if it is already away. Its event
+    /// is pending.
+    fn start_vacation(&mut self, server: usize) {
+        let vacation = match &self.vacations {
+            Some(vacations) => vacations.distribution.sample_dyn(&mut self.rng),
+            None => return,
+        };
+        if !self.on_vacation[server] {
+            self.on_vacation[server] = true;
+            self.server_selection.withdraw(server);
+            self.available -= 1;
+        }
+        self.server_events.push(ScheduledServerChange { time: self.time + vacation, server, change: ServerChange::VacationEnd });
+        self.pending_events.push_back(self.latest_event().server_change(self.time, EventType::Vacation, server));
+    }
+
+    /// The server starts serving, or is repaired while serving, so its time to failure counts down again.
+    fn start_operating(&mut self, server: usize) {
+        if self.breakdowns.is_some() {
+            self.failure_times[server] = self.time + self.uptime[server];
+            let breakdown = ScheduledServerChange { time: self.failure_times[server], server, change: ServerChange::Breakdown };
+            self.server_events.push(breakdown);
+        }
+    }
+
+    /// Breakdowns of servers that stopped serving before them are left on the calendar, so this discards them from
+    /// the top, so that the top of the calendar is always the next change.
+    fn discard_stale_breakdowns(&mut self) {
+        while let Some(change) = self.server_events.peek() {
+            if change.change != ServerChange::Breakdown || self.failure_times[change.server] == change.time {
+                return;
+            }
+            self.server_events.pop();
+        }
+    }
+
+    /// Returns the ID of the next waiting customer to abandon the queue and the time they do, from the top of the
+    /// calendar.
+    ///
+    /// When no one will abandon, time is set to infinity and the ID shouldn't be used.
+    fn next_abandonment(&self) -> (u64, f64) {
+        match self.abandonments.peek() {
+            Some(abandonment) => (abandonment.customer, abandonment.time),
+            None => (0, f64::INFINITY),
+        }
+    }
+
+    /// The customer joins (or rejoins) the waiting line now. When customers are impatient they sample a patience
+    /// from now, however long they waited before, and their abandonment goes on the calendar.
+    fn start_waiting(&mut self, customer: ArrivingCustomer) -> ArrivingCustomer {
+        let customer = match &self.patience_distribution {
+            Some(distribution) => customer.with_patience(self.time, distribution.sample_dyn(&mut self.rng)),
+            None => return customer,
+        };
+        self.abandonment_times.insert(customer.id(), customer.abandonment_time());
+        self.abandonments.push(ScheduledAbandonment { time: customer.abandonment_time(), customer: customer.id() });
+        customer
+    }
+
+    /// Takes the customer at `index` out of the waiting line, to be served or because they abandon. Their
+    /// abandonment is left on the calendar, so entries of customers no longer waiting are discarded from the top.
+    fn leave_queue(&mut self, index: usize) -> ArrivingCustomer {
+        let customer = self.queue.remove(index).expect("Only waiting customers leave the queue.");
+        if self.abandonment_times.remove(&customer.id()).is_some() {
+            while let Some(abandonment) = self.abandonments.peek() {
+                if self.abandonment_times.get(&abandonment.customer) == Some(&abandonment.time) {
+                    break;
+                }
+                self.abandonments.pop();
+            }
+        }
+        customer
+    }
+
+    /// Closes the queue now and returns the events of serving everyone still in the system, in service or waiting.
+    /// The iterator ends when the queue is idle.
+    pub fn drain(&mut self) -> Events<'_, DA, DS, R> {
+        self.closing_time = self.closing_time.min(self.time);
+        Events { queue: self, until: f64::INFINITY }
+    }
+
+    /// Returns the events up to and including `time`. Once they are all taken the clock of the queue is at `time`
+    /// (unless it was already past it), so stepping to evenly spaced times lines up even when nothing happens in
+    /// between.
+    pub fn advance_until(&mut self, time: f64) -> Events<'_, DA, DS, R> {
+        Events { queue: self, until: time }
+    }
+
+    /// The time of the last event, or the time the queue was advanced to.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+}
+
+/// A queue is an endless stream of events, unless it closes.
+impl<DA: Distribution<f64>, DS: Distribution<f64>, R: Rng> Iterator for Queue<DA, DS, R> {
+    type Item = QueueEvent;
+
+    fn next(&mut self) -> Option<QueueEvent> {
+        self.peek_next_event_time()?;
+        Some(*self.next_event())
+    }
+}
+
+/// The events of a `Queue` up to some time, see `Queue::advance_until` and `Queue::drain`.
+pub struct Events<'a, DA: Distribution<f64>, DS: Distribution<f64>, R: Rng> {
+    queue: &'a mut Queue<DA, DS, R>,
+    until: f64,
+}
+
+impl<'a, DA: Distribution<f64>, DS: Distribution<f64>, R: Rng> Iterator for Events<'a, DA, DS, R> {
+    type Item = QueueEvent;
+
+    fn next(&mut self) -> Option<QueueEvent> {
+        match self.queue.peek_next_event_time() {
+            Some(time) if time <= self.until => Some(*self.queue.next_event()),
+            _ => {
+                if self.until.is_finite() {
+                    self.queue.time = self.queue.time.max(self.until);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// An entry in the event calendar of a `Queue`: `server` is due to finish serving its customer at `time`.
+///
+/// Ordered so that the earliest departure is the greatest, and on top of the `BinaryHeap`. Ties go to the lowest
+/// server so that runs are reproducible.
+#[derive(Clone, Copy, Debug)]
+struct ScheduledDeparture {
+    time: f64,
+    server: usize,
+}
+
+impl Ord for ScheduledDeparture {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time).then_with(|| other.server.cmp(&self.server))
+    }
+}
+
+impl PartialOrd for ScheduledDeparture {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScheduledDeparture {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScheduledDeparture {}
+
+/// A waiting customer abandoning the queue, ordered like `ScheduledDeparture` so that the earliest is on top of the
+/// `BinaryHeap`. Ties go to the customer who arrived first.
+#[derive(Clone, Copy, Debug)]
+struct ScheduledAbandonment {
+    time: f64,
+    customer: u64,
+}
+
+impl Ord for ScheduledAbandonment {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time).then_with(|| other.customer.cmp(&self.customer))
+    }
+}
+
+impl PartialOrd for ScheduledAbandonment {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScheduledAbandonment {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScheduledAbandonment {}
+
+/// What happens to a server on the server calendar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ServerChange {
+    VacationEnd,
+    Breakdown,
+    Repaired,
+}
+
+/// A server changing state, ordered like `ScheduledDeparture` so that the earliest is on top of the `BinaryHeap`.
+#[derive(Clone, Copy, Debug)]
+struct ScheduledServerChange {
+    time: f64,
+    server: usize,
+    change: ServerChange,
+}
+
+impl Ord for ScheduledServerChange {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time).then_with(|| other.server.cmp(&self.server))
+    }
+}
+
+impl PartialOrd for ScheduledServerChange {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScheduledServerChange {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScheduledServerChange {}
+
+/// A customer outside the system when the population is finite, ordered so that the first to arrive is the greatest,
+/// and on top of the `BinaryHeap`.
+struct Source(ArrivingCustomer);
+
+impl Ord for Source {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.arrival_time().total_cmp(&self.0.arrival_time())
+    }
+}
+
+impl PartialOrd for Source {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Source {}
+
+/// A customer in orbit, who tries for a server again at `time`. Ordered like `Source` so that the first to try is on
+/// top of the `BinaryHeap`, and ties go to the customer who arrived first.
+struct Retrial {
+    time: f64,
+    customer: ArrivingCustomer,
+}
+
+impl Ord for Retrial {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time).then_with(|| other.customer.id().cmp(&self.customer.id()))
+    }
+}
+
+impl PartialOrd for Retrial {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Retrial {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Retrial {}
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error("Failed to read line.")]
+    LineReading(#[from] std::io::Error),
+    #[error("Failed to deserialize parameters from file header")]
+    ParameterReading(#[from] serde_json::Error),
+    #[error("Failed to parse a value from a Counts line")]
+    LineParsing(String),
+    /// The file was written by a version of the simulator with other columns, which the events can't be read from.
+    #[error("The columns of the event file are '{found}', expected '{expected}'")]
+    ColumnMismatch { expected: String, found: String },
+}
+
+const COLUMNS: [&str; 19] = [
+    "time(s)",
+    "arrivals",
+    "departures",
+    "in_system",
+    "type",
+    "class",
+    "customer",
+    "batch",
+    "attempts",
+    "visit",
+    "server",
+    "interarrival_time",
+    "time_of_arrival",
+    "service_time",
+    "time_of_service_start",
+    "time_of_departure",
+    "wait_in_queue",
+    "wait_in_system",
+    "sojourn_time",
+];
+
+const I_TIME: usize = 0;
+const I_ARRIVALS: usize = 1;
+const I_DEPARTURES: usize = 2;
+const I_IN_SYSTEM: usize = 3;
+const I_TYPE: usize = 4;
+const I_CLASS: usize = 5;
+const I_CUSTOMER: usize = 6;
+const I_BATCH: usize = 7;
+const I_ATTEMPTS: usize = 8;
+const I_VISIT: usize = 9;
+const I_SERVER: usize = 10;
+const I_INTERARRIVAL_TIME: usize = 11;
+const I_TIME_OF_ARRIVAL: usize = 12;
+const I_SERVICE_TIME: usize = 13;
+const I_TIME_OF_SERVICE_START: usize = 14;
+const I_TIME_OF_DEPARTURE: usize = 15;
+const I_WAIT_IN_QUEUE: usize = 16;
+const I_WAIT_IN_SYSTEM: usize = 17;
+const I_SOJOURN_TIME: usize = 18;
+
+/// The parameters of a simulation, written as JSON on the first line of an event file.
+#[derive(Deserialize, Serialize)]
+pub struct Parameters {
+    /// The total arrival rate, of all classes. With a finite population, the arrival rate of each customer outside the
+    /// system.
+    lambda: f64,
+    /// The service rate of an average customer, weighted by the arrival rates of the classes. When the servers have
+    /// their own service distributions, the average of their rates, so that c mu is the capacity of the system.
+    mu: f64,
+    servers: Option<u32>,
+    /// Missing in files written before the distributions were configurable, which were always exponential, and when
+    /// class 0 arrives by `arrival_profile` instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arrival_distribution: Option<TimeDistribution>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    service_distribution: Option<TimeDistribution>,
+    /// The classes after class 0, whose distributions are the two above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional_classes: Vec<CustomerClass<TimeDistribution, TimeDistribution>>,
+    /// The seed of the random number generator, when the simulation can be reproduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(default)]
+    discipline: Discipline,
+    #[serde(default)]
+    preemption: Preemption,
+    /// The maximum number of customers in the system, K, when it is finite.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capacity: Option<u32>,
+    /// The distribution of how long customers will wait before abandoning, when they can abandon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patience_distribution: Option<TimeDistribution>,
+    /// How arriving customers decide whether to join, when they can balk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    balking: Option<Balking>,
+    /// The time after which no one was admitted, when the queue closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    closing_time: Option<f64>,
+    /// The simulated time the simulation was to stop at, when it was run to a horizon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    horizon: Option<f64>,
+    /// The state at the end of the warm-up, when the events up to then were left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    warm_up: Option<WarmUp>,
+    #[serde(default)]
+    server_selection: IdleServerPolicy,
+    /// The service distribution of each server, when they aren't identical. The classes' service distributions
+    /// are ignored then.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    server_service_distributions: Vec<TimeDistribution>,
+    /// The number of customers, N, when the population is finite.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    population: Option<u32>,
+    /// The distribution of the number of customers arriving together, when they arrive in batches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    batch_size: Option<BatchSize>,
+    /// The most customers a server takes at once, when the service is in bulk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bulk_service: Option<u32>,
+    /// The time-varying arrival rate of class 0, when its arrivals are a nonhomogeneous Poisson process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arrival_profile: Option<RateProfile>,
+    /// The number of servers on duty over time, when it varies. `servers` is then the most on duty at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    staffing: Option<StaffingSchedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vacations: Option<Vacations<TimeDistribution>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    breakdowns: Option<Breakdowns<TimeDistribution>>,
+    /// The distribution of the time between the attempts of a customer in orbit, when customers who find every
+    /// server busy retry instead of waiting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retrial_distribution: Option<TimeDistribution>,
+    /// The probability that a served customer is fed back for rework: one for every class, or one per class.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    feedback_probabilities: Vec<f64>,
+    /// How the servers divided their time between customers, when they didn't serve one at a time to the end.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sharing: Option<Sharing>,
+}
+
+/// Where the warm-up of a simulation ended. The events up to then are left out of the event file, so that the
+/// statistics aren't biased by the queue starting empty.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct WarmUp {
+    /// The time the warm-up ended, the first event written is after it.
+    pub time: f64,
+    /// The number of events left out.
+    pub events: u64,
+    /// The number of customers in the system at the end of the warm-up.
+    pub in_system: u64,
+    /// How many of them were in orbit, waiting to retry.
+    #[serde(default)]
+    pub in_orbit: u64,
+}
+
+impl Parameters {
+    /// The rates lambda and mu are derived from the means of the distributions.
+    pub fn new(arrival_distribution: TimeDistribution, service_distribution: TimeDistribution, servers: u32) -> Parameters {
+        Parameters {
+            lambda: arrival_distribution.rate(),
+            mu: service_distribution.rate(),
+            servers: Some(servers),
+            arrival_distribution: Some(arrival_distribution),
+            service_distribution: Some(service_distribution),
+            additional_classes: Vec::new(),
+            seed: None,
+            discipline: Discipline::Fcfs,
+            preemption: Preemption::None,
+            capacity: None,
+            patience_distribution: None,
+            balking: None,
+            closing_time: None,
+            horizon: None,
+            warm_up: None,
+            server_selection: IdleServerPolicy::Lowest,
+            server_service_distributions: Vec::new(),
+            population: None,
+            batch_size: None,
+            bulk_service: None,
+            arrival_profile: None,
+            staffing: None,
+            vacations: None,
+            breakdowns: None,
+            retrial_distribution: None,
+            feedback_probabilities: Vec::new(),
+            sharing: None,
+        }
+    }
+
+    pub fn with_server_selection(self, server_selection: IdleServerPolicy) -> Parameters {
+        Parameters { server_selection, ..self }
+    }
+
+    /// Gives each server its own service distribution, and mu the average of their rates.
+    pub fn with_server_service_distributions(self, server_service_distributions: Vec<TimeDistribution>) -> Parameters {
+        let mu = server_service_distributions.iter().map(TimeDistribution::rate).sum::<f64>()
+            / server_service_distributions.len() as f64;
+        Parameters { mu, server_service_distributions, ..self }
+    }
+
+    pub fn with_population(self, population: u32) -> Parameters {
+        Parameters { population: Some(population), ..self }
+    }
+
+    /// Customers arrive in batches, so lambda, the rate of customers, is the rate of batches times the mean size.
+    pub fn with_batch_size(self, batch_size: BatchSize) -> Parameters {
+        let lambda = self.lambda * batch_size.mean();
+        Parameters { lambda, batch_size: Some(batch_size), ..self }
+    }
+
+    pub fn with_bulk_service(self, bulk_service: u32) -> Parameters {
+        Parameters { bulk_service: Some(bulk_service), ..self }
+    }
+
+    pub fn with_vacations(self, policy: VacationPolicy, vacation_distribution: TimeDistribution) -> Parameters {
+        Parameters { vacations: Some(Vacations { policy, distribution: vacation_distribution }), ..self }
+    }
+
+    pub fn with_breakdowns(self, time_to_failure: TimeDistribution, repair: TimeDistribution, interruption: Interruption) -> Parameters {
+        Parameters { breakdowns: Some(Breakdowns { time_to_failure, repair, interruption }), ..self }
+    }
+
+    pub fn with_retrials(self, retrial_distribution: TimeDistribution) -> Parameters {
+        Parameters { retrial_distribution: Some(retrial_distribution), ..self }
+    }
+
+    pub fn with_feedback(self, feedback_probabilities: Vec<f64>) -> Parameters {
+        Parameters { feedback_probabilities, ..self }
+    }
+
+    pub fn with_sharing(self, sharing: Sharing) -> Parameters {
+        Parameters { sharing: Some(sharing), ..self }
+    }
+
+    /// The rate at which customers join the queue, counting every pass of those fed back for rework: the rate of each
+    /// class over one minus its feedback probability. Just lambda without feedback.
+    pub fn effective_lambda(&self) -> f64 {
+        match self.feedback_probabilities.as_slice() {
+            [] => self.lambda,
+            [probability] => self.lambda / (1. - probability),
+            probabilities => {
+                let class_0 = match &self.arrival_profile {
+                    Some(arrival_profile) => arrival_profile.mean_rate(),
+                    None => self.arrival_distribution.as_ref().map_or(0., TimeDistribution::rate),
+                };
+                let batch_size = self.batch_size.map_or(1., |batch_size| batch_size.mean());
+                std::iter::once(class_0)
+                    .chain(self.additional_classes.iter().map(|class| class.arrival_distribution.rate()))
+                    .enumerate()
+                    .map(|(class, rate)| rate * batch_size / (1. - probabilities.get(class).copied().unwrap_or(0.)))
+                    .sum()
+            }
+        }
+    }
+
+    /// The number of servers varies by the staffing schedule, and `servers` is the most on duty at once.
+    pub fn with_staffing(self, staffing: StaffingSchedule) -> Parameters {
+        Parameters { servers: Some(staffing.max_servers()), staffing: Some(staffing), ..self }
+    }
+
+    /// Class 0 arrives by the rate profile rather than its arrival distribution, and lambda is the profile's mean rate.
+    pub fn with_arrival_profile(self, arrival_profile: RateProfile) -> Parameters {
+        let lambda = arrival_profile.mean_rate() * self.batch_size.map_or(1., |batch_size| batch_size.mean());
+        Parameters { lambda, arrival_distribution: None, arrival_profile: Some(arrival_profile), ..self }
+    }
+
+    pub fn with_horizon(self, horizon: f64) -> Parameters {
+        Parameters { horizon: Some(horizon), ..self }
+    }
+
+    pub fn with_warm_up(self, warm_up: WarmUp) -> Parameters {
+        Parameters { warm_up: Some(warm_up), ..self }
+    }
+
+    pub fn with_closing_time(self, closing_time: f64) -> Parameters {
+        Parameters { closing_time: Some(closing_time), ..self }
+    }
+
+    pub fn with_balking(self, balking: Balking) -> Parameters {
+        Parameters { balking: Some(balking), ..self }
+    }
+
+    pub fn with_patience(self, patience_distribution: TimeDistribution) -> Parameters {
+        Parameters { patience_distribution: Some(patience_distribution), ..self }
+    }
+
+    pub fn with_preemption(self, preemption: Preemption) -> Parameters {
+        Parameters { preemption, ..self }
+    }
+
+    pub fn with_capacity(self, capacity: u32) -> Parameters {
+        Parameters { capacity: Some(capacity), ..self }
+    }
+
+    pub fn with_discipline(self, discipline: Discipline) -> Parameters {
+        Parameters { discipline, ..self }
+    }
+
+    /// Adds a class, updating lambda and mu to the totals over all classes.
+    pub fn with_class(mut self, class: CustomerClass<TimeDistribution, TimeDistribution>) -> Parameters {
+        self.additional_classes.push(class);
+
+        let mut lambda = match &self.arrival_profile {
+            Some(arrival_profile) => arrival_profile.mean_rate(),
+            None => self.arrival_distribution.as_ref().map_or(0., TimeDistribution::rate),
+        };
+        let mut work = self.service_distribution.as_ref().map_or(0., TimeDistribution::mean) * lambda;
+        for class in &self.additional_classes {
+            let lambda_i = class.arrival_distribution.rate();
+            lambda += lambda_i;
+            work += lambda_i * class.service_distribution.mean();
+        }
+        self.lambda = lambda * self.batch_size.map_or(1., |batch_size| batch_size.mean());
+        self.mu = lambda / work;
+        self
+    }
+
+    pub fn with_seed(self, seed: u64) -> Parameters {
+        Parameters { seed: Some(seed), ..self }
+    }
+
+    pub fn dump_header<OUT: Write>(&self, out: &mut OUT) -> Result<(), std::io::Error> {
+        writeln!(out, "# {}", serde_json::to_string(self)?)?;
+        QueueEvent::dump_line_header(out)
+    }
+
+    /// The arrival and service rates of every class, when they are all exponential.
+    pub fn exponential_class_rates(&self) -> Option<Vec<(f64, f64)>> {
+        let rate = |distribution: &TimeDistribution| match distribution {
+            TimeDistribution::Exponential { rate } => Some(*rate),
+            _ => None,
+        };
+        let mut rates = vec![(rate(self.arrival_distribution.as_ref()?)?, rate(self.service_distribution.as_ref()?)?)];
+        for class in &self.additional_classes {
+            rates.push((rate(&class.arrival_distribution)?, rate(&class.service_distribution)?));
+        }
+        Some(rates)
+    }
+
+    /// Whether both distributions are exponential and no one abandons or balks, so the queue is M/M/c (or M/M/c/K) and
+    /// the theory applies. Classes are fine as long as they are all served at the same exponential rate.
+    ///
+    /// When the servers have their own service distributions they must all be exponential at the same rate.
+    pub fn is_markovian(&self) -> bool {
+        self.batch_size.is_none() && self.bulk_service.is_none() && self.arrival_profile.is_none()
+            && self.servers_always_serve() && self.has_markovian_customers()
+    }
+
+    /// Whether the queue would be M/M/c if the rate of the arrival profile held still, so that the M/M/c theory at the
+    /// rate of each segment, the pointwise stationary approximation, is a reference for it.
+    pub fn is_pointwise_markovian(&self) -> bool {
+        self.arrival_profile.is_some() && self.capacity.is_none() && self.batch_size.is_none()
+            && self.bulk_service.is_none() && self.feedback_probabilities.is_empty() && self.servers_always_serve()
+            && self.has_markovian_customers()
+    }
+
+    /// Whether the servers are always there to serve: on duty, and never away on vacation or broken down.
+    fn servers_always_serve(&self) -> bool {
+        self.staffing.is_none() && self.vacations.is_none() && self.breakdowns.is_none()
+    }
+
+    /// The M/G/1 theory with vacations and breakdowns, when customers arrive one at a time as a Poisson process at a
+    /// single server, with any service time and no limits on who joins or stays. Breakdowns need a constant failure
+    /// rate and the service to resume, and single vacations the Laplace transform of the vacation time.
+    pub fn interruption_theory(&self) -> Option<theory::MG1Interruptions> {
+        if self.vacations.is_none() && self.breakdowns.is_none() {
+            return None;
+        }
+        let arrivals_are_poisson = matches!(self.arrival_distribution, None | Some(TimeDistribution::Exponential { .. }))
+            && self.additional_classes.is_empty();
+        let single_server = self.servers == Some(1) && self.server_service_distributions.is_empty();
+        let unlimited = self.capacity.is_none() && self.population.is_none() && self.patience_distribution.is_none()
+            && self.balking.is_none() && self.batch_size.is_none() && self.bulk_service.is_none()
+            && self.arrival_profile.is_none() && self.staffing.is_none() && self.retrial_distribution.is_none()
+            && self.feedback_probabilities.is_empty() && self.sharing.is_none();
+        if !(arrivals_are_poisson && single_server && unlimited) {
+            return None;
+        }
+
+        let service = self.service_distribution.clone().unwrap_or(TimeDistribution::Exponential { rate: self.mu });
+        let mut theory = theory::MG1Interruptions::new(self.lambda, service.mean(), service.second_moment());
+        if let Some(breakdowns) = &self.breakdowns {
+            let failure_rate = match (&breakdowns.time_to_failure, breakdowns.interruption) {
+                (TimeDistribution::Exponential { rate }, Interruption::Resume) => *rate,
+                _ => return None,
+            };
+            theory = theory.with_breakdowns(failure_rate, breakdowns.repair.mean(), breakdowns.repair.second_moment());
+        }
+        if let Some(vacations) = &self.vacations {
+            let vacation = &vacations.distribution;
+            theory = match vacations.policy {
+                VacationPolicy::Multiple => theory.with_multiple_vacations(vacation.mean(), vacation.second_moment()),
+                VacationPolicy::Single => {
+                    let no_arrival = vacation.laplace_transform(self.lambda)?;
+                    theory.with_single_vacations(vacation.mean(), vacation.second_moment(), no_arrival)
+                }
+            };
+        }
+        Some(theory)
+    }
+
+    /// Whether the queue is M/G/1 under processor sharing. Its number in the system doesn't depend on the service
+    /// distribution beyond its mean, so the M/M/1 theory holds for any service times, like the mean sojourn
+    /// `1 / (mu - lambda)`. Customers never wait in the queue, so there is no one to abandon.
+    pub fn is_insensitive(&self) -> bool {
+        let arrivals_are_poisson = matches!(self.arrival_distribution, None | Some(TimeDistribution::Exponential { .. }))
+            && self.additional_classes.iter()
+                .all(|class| matches!(class.arrival_distribution, TimeDistribution::Exponential { .. }));
+        self.sharing == Some(Sharing::ProcessorSharing) && arrivals_are_poisson && self.balking.is_none()
+            && self.batch_size.is_none() && self.arrival_profile.is_none() && self.feedback_probabilities.is_empty()
+            && !(self.population.is_some() && self.capacity.is_some())
+    }
+
+    /// Whether the customers, taken one at a time, arrive and are served as in `is_markovian`, which is all the
+    /// batch theory needs.
+    ///
+    /// Customers fed back with the same probability whatever their class leave at a constant rate, so the number in
+    /// the system is that of M/M/c at the effective arrival rate.
+    fn has_markovian_customers(&self) -> bool {
+        let uniform_feedback = self.feedback_probabilities.windows(2).all(|pair| pair[0] == pair[1]);
+        self.has_markovian_times() && self.retrial_distribution.is_none() && uniform_feedback
+    }
+
+    /// Whether the arrivals and services are exponential and no one abandons or balks, whatever the customers who
+    /// find every server busy do.
+    fn has_markovian_times(&self) -> bool {
+        let is_exponential = |distribution: &Option<TimeDistribution>| {
+            matches!(distribution, None | Some(TimeDistribution::Exponential { .. }))
+        };
+        let is_exponential_at = |distribution: &TimeDistribution, base: f64| {
+            matches!(distribution, TimeDistribution::Exponential { rate } if is_same_rate(*rate, base))
+        };
+        let service_is_markovian = if self.server_service_distributions.is_empty() {
+            // Every class is served at the rate of class 0, which is mu (older files only have mu).
+            let base = match &self.service_distribution {
+                Some(TimeDistribution::Exponential { rate }) => *rate,
+                Some(_) => return false,
+                None => self.mu,
+            };
+            is_same_rate(base, self.mu)
+                && self.additional_classes.iter().all(|class| is_exponential_at(&class.service_distribution, base))
+        } else {
+            // Every server at the rate of the first, mu being their mean.
+            let base = self.server_service_distributions[0].rate();
+            self.server_service_distributions.iter().all(|distribution| is_exponential_at(distribution, base))
+        };
+        is_exponential(&self.arrival_distribution)
+            && self.additional_classes.iter()
+                .all(|class| matches!(class.arrival_distribution, TimeDistribution::Exponential { .. }))
+            && service_is_markovian
+            && self.patience_distribution.is_none()
+            && self.balking.is_none()
+            // There is no theory for a finite population and a finite capacity together.
+            && !(self.population.is_some() && self.capacity.is_some())
+    }
+
+    /// The heterogeneous M/M/2 theory, when there are two exponential servers with their own rates, Poisson arrivals,
+    /// no limits on who joins or stays, and a server selection that sends a customer arriving to an empty system to
+    /// each server with a fixed probability.
+    pub fn heterogeneous_theory(&self) -> Option<theory::MM2Heterogeneous> {
+        let rate = |distribution: &TimeDistribution| match distribution {
+            TimeDistribution::Exponential { rate } => Some(*rate),
+            _ => None,
+        };
+        let (mu_0, mu_1) = match self.server_service_distributions.as_slice() {
+            [server_0, server_1] => (rate(server_0)?, rate(server_1)?),
+            _ => return None,
+        };
+        let arrivals_are_poisson = rate(self.arrival_distribution.as_ref()?).is_some()
+            && self.additional_classes.iter().all(|class| rate(&class.arrival_distribution).is_some());
+        let p_0 = match self.server_selection {
+            IdleServerPolicy::Lowest => 1.,
+            IdleServerPolicy::Fastest if mu_0 >= mu_1 => 1.,
+            IdleServerPolicy::Fastest => 0.,
+            IdleServerPolicy::Random => 0.5,
+            // Which server is chosen depends on the history, not just on the state.
+            IdleServerPolicy::LongestIdle | IdleServerPolicy::RoundRobin => return None,
+        };
+        let unlimited = self.capacity.is_none() && self.patience_distribution.is_none() && self.balking.is_none()
+            && self.preemption == Preemption::None && self.population.is_none() && self.retrial_distribution.is_none()
+            && self.feedback_probabilities.is_empty() && self.batch_size.is_none() && self.bulk_service.is_none()
+            && self.sharing.is_none() && self.servers_always_serve();
+        if arrivals_are_poisson && unlimited {
+            Some(theory::MM2Heterogeneous::new(self.lambda, mu_0, mu_1, p_0))
+        } else {
+            None
+        }
+    }
+
+    /// The M/M/c retrial theory, when customers who find every server busy retry after exponential times and the
+    /// queue would otherwise be M/M/c, and stable.
+    pub fn retrial_theory(&self) -> Option<theory::MMCRetrial> {
+        let retrial_rate = match self.retrial_distribution {
+            Some(TimeDistribution::Exponential { rate }) => rate,
+            _ => return None,
+        };
+        let servers = self.servers.unwrap_or(1);
+        let unlimited = self.capacity.is_none() && self.population.is_none() && self.batch_size.is_none()
+            && self.bulk_service.is_none() && self.arrival_profile.is_none() && self.feedback_probabilities.is_empty()
+            && self.servers_always_serve();
+        let stable = self.lambda < servers as f64 * self.mu;
+        if unlimited && stable && self.has_markovian_times() {
+            Some(theory::MMCRetrial::new(self.lambda, self.mu, servers, retrial_rate))
+        } else {
+            None
+        }
+    }
+
+    /// The M/M/1 theory under shortest remaining processing time first, when it is preemptive-resume and there are no
+    /// limits on who joins or stays.
+    pub fn srpt_theory(&self) -> Option<theory::MM1Srpt> {
+        let preemptive = self.discipline == Discipline::Srpt && self.preemption == Preemption::Resume;
+        let unlimited = self.capacity.is_none() && self.population.is_none() && self.feedback_probabilities.is_empty();
+        let stable = self.lambda < self.mu;
+        if preemptive && self.servers == Some(1) && unlimited && stable && self.is_markovian() {
+            Some(theory::MM1Srpt::new(self.lambda, self.mu))
+        } else {
+            None
+        }
+    }
+
+    /// The M^X/M/1 theory, when batches of customers arrive as a Poisson process at a single exponential server that
+    /// serves them one at a time, with no limits on who joins or stays.
+    pub fn batch_theory(&self) -> Option<theory::MXM1> {
+        let batch_size = self.batch_size?;
+        let single_server = self.servers == Some(1) && self.server_service_distributions.is_empty();
+        let unlimited = self.capacity.is_none() && self.population.is_none() && self.bulk_service.is_none()
+            && self.arrival_profile.is_none() && self.feedback_probabilities.is_empty() && self.servers_always_serve();
+        if single_server && unlimited && self.has_markovian_customers() {
+            Some(theory::MXM1::new(self.lambda / batch_size.mean(), self.mu, batch_size.probabilities()))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EventAnalyser {
+    lambda: f64,
+    /// The rate customers join the queue counting the passes of those fed back, which the M/M/c theory is at.
+    effective_lambda: f64,
+    mu: f64,
+    servers: i32,
+    markovian: bool,
+    seed: Option<u64>,
+    discipline: Discipline,
+    preemption: Preemption,
+    /// The arrival and service rates of the classes, when they are exponential, for the priority theory.
+    exponential_class_rates: Option<Vec<(f64, f64)>>,
+    capacity: Option<u32>,
+    population: Option<u32>,
+    reneging: bool,
+    balking: Option<Balking>,
+    closing_time: Option<f64>,
+    horizon: Option<f64>,
+    warm_up: Option<WarmUp>,
+    server_selection: IdleServerPolicy,
+    /// The theory for two servers of different speeds, when it applies.
+    heterogeneous_theory: Option<theory::MM2Heterogeneous>,
+    batch_size: Option<BatchSize>,
+    bulk_service: Option<u32>,
+    /// The theory for customers arriving in batches, when it applies.
+    batch_theory: Option<theory::MXM1>,
+    vacations: Option<Vacations<TimeDistribution>>,
+    breakdowns: Option<Breakdowns<TimeDistribution>>,
+    interruption_theory: Option<theory::MG1Interruptions>,
+    retrial_distribution: Option<TimeDistribution>,
+    /// The theory for customers who retry from an orbit, when it applies.
+    retrial_theory: Option<theory::MMCRetrial>,
+    sharing: Option<Sharing>,
+    /// The theory for shortest remaining processing time first, when it applies.
+    srpt_theory: Option<theory::MM1Srpt>,
+    arrival_profile: Option<RateProfile>,
+    /// Whether the M/M/c theory at the rate of each segment of the arrival profile is a reference.
+    pointwise_markovian: bool,
+    /// The tallies of each segment of the arrival profile, over every period.
+    segments: Vec<SegmentStatistics>,
+    staffing: Option<StaffingSchedule>,
+    /// The tallies of each interval of the staffing schedule, over every period.
+    staffing_intervals: Vec<SegmentStatistics>,
+
+    last_service_start: f64,
+    n_served: u64,
+    service_time_sum: f64,
+    last_arrival: f64,
+    n_arrivals: u64,
+    arrival_time_sum: f64,
+    n_blocked: u64,
+    n_balked: u64,
+    /// The waits in queue of customers who were eventually served, and of those who abandoned.
+    served_waits: WaitStatistics,
+    abandoned_waits: WaitStatistics,
+    /// The customers in orbit when they retry, and the integral of their number over time.
+    in_orbit: u64,
+    orbit_area: f64,
+    n_retrials: u64,
+    /// The attempts of the customers who were served, their arrival and their retrials.
+    attempts_sum: u64,
+    feedback_probabilities: Vec<f64>,
+    n_feedback: u64,
+    /// The passes through the queue of the customers who left after service, and their sojourns over all of them.
+    visits_sum: u64,
+    sojourn_times: WaitStatistics,
+
+    queue_wait_sum: f64,
+    system_wait_sum: f64,
+    queue_wait_square_sum: f64,
+    system_wait_square_sum: f64,
+
+    classes: Vec<ClassStatistics>,
+    /// The tallies of the customers served by their job size, in bins of a doubling of the service time.
+    sizes: BTreeMap<i32, SizeStatistics>,
+    server_statistics: Vec<ServerStatistics>,
+
+    last_n: u64,
+    time_in_n: HashMap<u64, f64>,
+    /// When the events start, zero unless a warm-up was left out.
+    start_time: f64,
+    time_of_last_event: f64,
+}
+
+/// The tallies of a single server, from the events that name it.
+#[derive(Clone)]
+struct ServerStatistics {
+    state: ServerState,
+    /// The time spent serving, up to the last time the server stopped.
+    busy_time: f64,
+    n_served: u64,
+    /// The busy and idle periods that were seen from start to end, not those cut off by the start of the events.
+    busy_periods: WaitStatistics,
+    idle_periods: WaitStatistics,
+    /// Since when the server has been away, and whether on vacation or broken down.
+    away: Option<(f64, EventType)>,
+    vacations: WaitStatistics,
+    repairs: WaitStatistics,
+}
+
+#[derive(Clone, Copy)]
+enum ServerState {
+    /// Nothing has been seen of the server since the events started at `since`.
+    Unknown { since: f64 },
+    /// Serving since `since`, in a busy period that started at `busy_since` if it was seen.
+    Busy { since: f64, busy_since: Option<f64> },
+    /// Idle since `since`, after a busy period that started at `busy_since` if it was seen.
+    Idle { since: f64, busy_since: Option<f64> },
+}
+
+impl ServerStatistics {
+    fn new(start_time: f64) -> ServerStatistics {
+        ServerStatistics {
+            state: ServerState::Unknown { since: start_time },
+            busy_time: 0.,
+            n_served: 0,
+            busy_periods: WaitStatistics::default(),
+            idle_periods: WaitStatistics::default(),
+            away: None,
+            vacations: WaitStatistics::default(),
+            repairs: WaitStatistics::default(),
+        }
+    }
+
+    /// The server went on vacation or broke down at `time`. Another vacation straight after one counts on its own.
+    fn leave(&mut self, time: f64, event_type: EventType) {
+        self.come_back(time);
+        self.away = Some((time, event_type));
+    }
+
+    /// The server came back from vacation or was repaired at `time`. Nothing is known of an absence that started
+    /// before the events.
+    fn come_back(&mut self, time: f64) {
+        match self.away.take() {
+            Some((since, EventType::Vacation)) => self.vacations.add(time - since),
+            Some((since, _)) => self.repairs.add(time - since),
+            None => {}
+        }
+    }
+
+    /// The server started serving a customer at `time`.
+    fn start(&mut self, time: f64) {
+        let busy_since = match self.state {
+            // Freed and straight away serving the next customer, so the busy period goes on.
+            ServerState::Idle { since, busy_since } if since == time => busy_since,
+            ServerState::Idle { since, busy_since } => {
+                if let Some(busy_since) = busy_since {
+                    self.busy_periods.add(since - busy_since);
+                }
+                self.idle_periods.add(time - since);
+                Some(time)
+            }
+            ServerState::Unknown { .. } => Some(time),
+            // Another customer joining a bulk that is starting service.
+            ServerState::Busy { since, .. } if since == time => return,
+            ServerState::Busy { .. } => panic!("A server started serving a customer while busy with another."),
+        };
+        self.state = ServerState::Busy { since: time, busy_since };
+    }
+
+    /// The server stopped serving a customer at `time`, because they departed or were preempted. Returns when it
+    /// started serving, or `None` if it had already stopped.
+    fn stop(&mut self, time: f64) -> Option<f64> {
+        let (since, busy_since) = match self.state {
+            ServerState::Busy { since, busy_since } => (since, busy_since),
+            // The server was already serving when the events started.
+            ServerState::Unknown { since } => (since, None),
+            // Another customer of a bulk departing together.
+            ServerState::Idle { since, .. } if since == time => return None,
+            ServerState::Idle { .. } => panic!("An idle server stopped serving a customer."),
+        };
+        self.busy_time += time - since;
+        self.state = ServerState::Idle { since: time, busy_since };
+        Some(since)
+    }
+
+    /// When the server started serving, if it is busy.
+    fn serving_since(&self) -> Option<f64> {
+        match self.state {
+            ServerState::Busy { since, .. } => Some(since),
+            _ => None,
+        }
+    }
+
+    /// The fraction of the `duration` up to `end_time` that the server was busy.
+    fn utilization(&self, end_time: f64, duration: f64) -> f64 {
+        match self.state {
+            ServerState::Busy { since, .. } => (self.busy_time + end_time - since) / duration,
+            _ => self.busy_time / duration,
+        }
+    }
+}
+
+/// The tallies of the time spent in a segment of the arrival profile or an interval of the staffing schedule, like an
+/// hour of the day.
+#[derive(Clone, Default)]
+struct SegmentStatistics {
+    duration: f64,
+    /// The server time spent serving during the segment, for the utilization of a staffing interval.
+    busy_time: f64,
+    /// The integral of the number in the system over the time spent in the segment.
+    area: f64,
+    /// The customers that arrived during the segment, whether they joined or not.
+    n_arrivals: u64,
+    /// The customers that arrived during the segment and were served, whenever that was.
+    n_served: u64,
+    system_wait_sum: f64,
+}
+
+impl SegmentStatistics {
+    /// Time spent in the segment with `n` in the system.
+    fn add_time(&mut self, duration: f64, n: u64) {
+        self.duration += duration;
+        self.area += n as f64 * duration;
+    }
+
+    /// Counts an arrival in the segment it happened in, and a departure's wait in the segment the customer arrived
+    /// in, when they found the queue they waited in.
+    fn add_event<F: Fn(f64) -> usize>(segments: &mut [SegmentStatistics], count: &QueueEvent, segment_at: F) {
+        match (count.event_type, &count.served_customer) {
+            (EventType::Arrival | EventType::Blocked | EventType::Balked, _) => {
+                segments[segment_at(count.time)].n_arrivals += 1;
+            }
+            (EventType::Departure, Some(customer)) => {
+                let segment = &mut segments[segment_at(customer.time_of_arrival)];
+                segment.n_served += 1;
+                segment.system_wait_sum += customer.wait_in_system;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The tallies of a single class of customers.
+#[derive(Clone, Default)]
+struct ClassStatistics {
+    /// Only customers that joined the system.
+    n_arrivals: u64,
+    n_served: u64,
+    queue_wait_sum: f64,
+    system_wait_sum: f64,
+}
+
+/// The tallies of the customers served whose job size, the service time they needed, fell in a bin.
+#[derive(Clone, Default)]
+struct SizeStatistics {
+    n_served: u64,
+    size_sum: f64,
+    /// The sums of their response times, from arrival to departure, and of their slowdowns, response time over size.
+    response_sum: f64,
+    slowdown_sum: f64,
+}
+
+impl EventAnalyser {
+    pub fn new<R: Read>(reader: &mut BufReader<R>) -> Result<EventAnalyser, QueueError> {
+        let mut line_0 = String::new();
+        let _ = reader.read_line(&mut line_0).map_err(QueueError::LineReading)?;
+        let params: Parameters = serde_json::from_str(&line_0[1..]).map_err(QueueError::ParameterReading)?;
+
+        // Sanity check header line.
+        let mut line_1 = String::new();
+        let _ = reader.read_line(&mut line_1).map_err(QueueError::LineReading)?;
+        let expected = format!("# {}", COLUMNS.join(" "));
+        let found = line_1.trim_end();
+        if found != expected {
+            return Err(QueueError::ColumnMismatch { expected, found: found.to_string() });
+        }
+
+        // After a warm-up the events pick up from where it ended, rather than from an empty queue at t=0.
+        let start_time = params.warm_up.map_or(0., |warm_up| warm_up.time);
+        let servers = params.servers.unwrap_or(1);
+        let heterogeneous_theory = params.heterogeneous_theory();
+        let batch_theory = params.batch_theory();
+        let interruption_theory = params.interruption_theory();
+        let retrial_theory = params.retrial_theory();
+        let srpt_theory = params.srpt_theory();
+        Ok(EventAnalyser {
+            lambda: params.lambda,
+            effective_lambda: params.effective_lambda(),
+            mu: params.mu,
+            servers: servers as i32,
+            markovian: params.is_markovian() || params.is_insensitive() || heterogeneous_theory.is_some()
+                || batch_theory.is_some() || retrial_theory.is_some(),
+            seed: params.seed,
+            discipline: params.discipline,
+            preemption: params.preemption,
+            exponential_class_rates: params.exponential_class_rates(),
+            capacity: params.capacity,
+            population: params.population,
+            reneging: params.patience_distribution.is_some(),
+            balking: params.balking,
+            closing_time: params.closing_time,
+            horizon: params.horizon,
+            warm_up: params.warm_up,
+            server_selection: params.server_selection,
+            heterogeneous_theory,
+            batch_size: params.batch_size,
+            bulk_service: params.bulk_service,
+            batch_theory,
+            interruption_theory,
+            retrial_theory,
+            pointwise_markovian: params.is_pointwise_markovian(),
+            vacations: params.vacations,
+            breakdowns: params.breakdowns,
+            retrial_distribution: params.retrial_distribution,
+            sharing: params.sharing,
+            srpt_theory,
+            feedback_probabilities: params.feedback_probabilities,
+            segments: params.arrival_profile.as_ref()
+                .map_or_else(Vec::new, |profile| vec![SegmentStatistics::default(); profile.segments()]),
+            arrival_profile: params.arrival_profile,
+            staffing_intervals: params.staffing.as_ref()
+                .map_or_else(Vec::new, |staffing| vec![SegmentStatistics::default(); staffing.intervals()]),
+            staffing: params.staffing,
+            server_statistics: vec![ServerStatistics::new(start_time); servers as usize],
+            last_service_start: start_time,
+            last_arrival: start_time,
+            last_n: params.warm_up.map_or(0, |warm_up| warm_up.in_system),
+            in_orbit: params.warm_up.map_or(0, |warm_up| warm_up.in_orbit),
+            start_time,
+            time_of_last_event: start_time,
+            ..EventAnalyser::default()
+        })
+    }
+
+    pub fn add_count(&mut self, count: QueueEvent) {
+        if !self.time_in_n.contains_key(&self.last_n) {
+            self.time_in_n.insert(self.last_n, 0.);
+        }
+        let delta_t = count.time - self.time_of_last_event;
+        let new_time_in_n = self.time_in_n.get(&self.last_n).unwrap() + delta_t;
+        self.time_in_n.insert(self.last_n, new_time_in_n);
+        self.orbit_area += self.in_orbit as f64 * delta_t;
+
+        let class = self.class_statistics(count.class);
+        match count.event_type {
+            EventType::Arrival => class.n_arrivals += 1,
+            EventType::Departure => {
+                let customer = count.served_customer.as_ref()
+                    .expect("A departure event without a served_customer was constructed improperly.");
+                class.n_served += 1;
+                class.queue_wait_sum += customer.wait_in_queue;
+                class.system_wait_sum += customer.wait_in_system;
+            }
+            _ => {}
+        }
+
+        match count.event_type {
+            EventType::Departure => {
+                self.add_departure(&count);
+                let customer = count.served_customer.as_ref()
+                    .expect("A departure event without a served_customer was constructed improperly.");
+                self.visits_sum += customer.visit as u64;
+                self.sojourn_times.add(customer.sojourn_time());
+            }
+            EventType::Feedback => {
+                // Each pass is served and waits like any other, the customer just doesn't leave after it.
+                self.add_departure(&count);
+                self.n_feedback += 1;
+            }
+            EventType::Abandonment => {
+                let customer = count.served_customer.as_ref()
+                    .expect("An abandonment event without the abandoning customer was constructed improperly.");
+                self.abandoned_waits.add(customer.wait_in_queue);
+            }
+            EventType::Arrival => self.add_arrival(&count),
+            EventType::Blocked => {
+                // Blocked customers still arrived, they count towards the offered arrival rate.
+                self.add_interarrival(&count);
+                self.n_blocked += 1;
+            }
+            EventType::Balked => {
+                self.add_interarrival(&count);
+                self.n_balked += 1;
+            }
+            EventType::Retrial => self.n_retrials += 1,
+            EventType::ServiceStart | EventType::Preempted => {}
+            EventType::Vacation | EventType::Breakdown | EventType::ServerReturn => {}
+        }
+        if self.retrial_distribution.is_some() {
+            // No one waits in the queue, so everyone in the system not being served is in orbit.
+            match count.event_type {
+                EventType::Arrival | EventType::Preempted | EventType::Feedback => self.in_orbit += 1,
+                EventType::ServiceStart => self.in_orbit -= 1,
+                _ => {}
+            }
+        }
+
+        let n = self.last_n;
+        if let Some(profile) = &self.arrival_profile {
+            let segments = &mut self.segments;
+            profile.for_each_segment_between(self.time_of_last_event, count.time, |i, duration| segments[i].add_time(duration, n));
+            SegmentStatistics::add_event(segments, &count, |time| profile.segment_at(time));
+        }
+        if let Some(staffing) = &self.staffing {
+            let intervals = &mut self.staffing_intervals;
+            staffing.for_each_interval_between(self.time_of_last_event, count.time, |i, duration| intervals[i].add_time(duration, n));
+            SegmentStatistics::add_event(intervals, &count, |time| staffing.interval_at(time));
+        }
+
+        if let Some(server) = count.server {
+            if self.server_statistics.len() <= server {
+                self.server_statistics.resize(server + 1, ServerStatistics::new(self.start_time));
+            }
+            let statistics = &mut self.server_statistics[server];
+            // A shared processor is busy as long as anyone is in the system, whoever starts or finishes.
+            let shared = self.sharing == Some(Sharing::ProcessorSharing);
+            let serving_since = match count.event_type {
+                EventType::ServiceStart if shared && statistics.serving_since().is_some() => None,
+                EventType::Departure | EventType::Feedback if shared && count.in_system > 0 => {
+                    statistics.n_served += 1;
+                    None
+                }
+                EventType::ServiceStart => {
+                    statistics.start(count.time);
+                    None
+                }
+                EventType::Departure | EventType::Feedback => {
+                    statistics.n_served += 1;
+                    statistics.stop(count.time)
+                }
+                EventType::Preempted => statistics.stop(count.time),
+                EventType::Vacation | EventType::Breakdown => {
+                    statistics.leave(count.time, count.event_type);
+                    None
+                }
+                EventType::ServerReturn => {
+                    statistics.come_back(count.time);
+                    None
+                }
+                _ => None,
+            };
+            if let (Some(since), Some(staffing)) = (serving_since, &self.staffing) {
+                let intervals = &mut self.staffing_intervals;
+                staffing.for_each_interval_between(since, count.time, |i, duration| intervals[i].busy_time += duration);
+            }
+        }
+
+        self.last_n = count.in_system;
+        self.time_of_last_event = count.time;
+    }
+
+    /// The tallies of a class, which are created the first time the class is seen.
+    fn class_statistics(&mut self, class: usize) -> &mut ClassStatistics {
+        if self.classes.len() <= class {
+            self.classes.resize(class + 1, ClassStatistics::default());
+        }
+        &mut self.classes[class]
+    }
+
+    fn add_departure(&mut self, count: &QueueEvent) {
+        let service_time = count.time - self.last_service_start;
+        self.n_served += 1;
+        self.service_time_sum += service_time;
+        self.last_service_start = count.time;
+
+        let customer = count.served_customer.as_ref()
+            .expect("A departure event without a served_customer was constructed improperly.");
+        self.system_wait_sum += customer.wait_in_system;
+        self.queue_wait_sum += customer.wait_in_queue;
+        self.system_wait_square_sum += customer.wait_in_system * customer.wait_in_system;
+        self.queue_wait_square_sum += customer.wait_in_queue * customer.wait_in_queue;
+        self.served_waits.add(customer.wait_in_queue);
+        self.attempts_sum += customer.attempts as u64;
+        if customer.service_time > 0. {
+            let size = self.sizes.entry(customer.service_time.log2().floor() as i32).or_default();
+            size.n_served += 1;
+            size.size_sum += customer.service_time;
+            size.response_sum += customer.wait_in_system;
+            size.slowdown_sum += customer.wait_in_system / customer.service_time;
+        }
+    }
+
+    fn add_arrival(&mut self, count: &QueueEvent) {
+        self.add_interarrival(count);
+
+        if self.last_n == 0 {
+            self.last_service_start = count.time;
+        }
+    }
+
+    fn add_interarrival(&mut self, count: &QueueEvent) {
+        let interarrival_time = count.time - self.last_arrival;
+        self.n_arrivals += 1;
+        self.arrival_time_sum += interarrival_time;
+        self.last_arrival = count.time;
+    }
+
+    pub fn analysis(&self) -> CountAnalysis {
+        let sample_lambda = self.n_arrivals as f64 / self.arrival_time_sum;
+        let sample_mu = self.n_served as f64 / self.service_time_sum;
+
+        // The state after the last event lasts until the horizon, if there was one.
+        let end_time = self.horizon.map_or(self.time_of_last_event, |horizon| horizon.max(self.time_of_last_event));
+        let duration = end_time - self.start_time;
+        let mut proportions = HashMap::new();
+        for (n, time_in_n) in &self.time_in_n {
+            proportions.insert(*n, time_in_n / duration);
+        }
+        *proportions.entry(self.last_n).or_insert(0.) += (end_time - self.time_of_last_event) / duration;
+        let mut segments = self.segments.clone();
+        if let Some(profile) = &self.arrival_profile {
+            profile.for_each_segment_between(self.time_of_last_event, end_time, |i, duration| segments[i].add_time(duration, self.last_n));
+        }
+        let mut staffing_intervals = self.staffing_intervals.clone();
+        if let Some(staffing) = &self.staffing {
+            staffing.for_each_interval_between(self.time_of_last_event, end_time, |i, duration| {
+                staffing_intervals[i].add_time(duration, self.last_n)
+            });
+            for since in self.server_statistics.iter().filter_map(ServerStatistics::serving_since) {
+                staffing.for_each_interval_between(since, end_time, |i, duration| staffing_intervals[i].busy_time += duration);
+            }
+        }
+        
+        let theory: Box<dyn QueueTheory> = if let Some(batch_theory) = &self.batch_theory {
+            Box::new(batch_theory.clone())
+        } else if let Some(retrial_theory) = &self.retrial_theory {
+            Box::new(retrial_theory.clone())
+        } else {
+            match (self.heterogeneous_theory, self.population, self.capacity) {
+                (Some(heterogeneous_theory), _, _) => Box::new(heterogeneous_theory),
+                (None, Some(population), _) => {
+                    Box::new(theory::MMCN::new(self.effective_lambda, self.mu, self.servers as u32, population))
+                }
+                (None, None, Some(capacity)) => {
+                    let queue_capacity = capacity - self.servers as u32;
+                    Box::new(theory::MMCK::new(self.effective_lambda, self.mu, self.servers as u32, queue_capacity))
+                }
+                (None, None, None) => Box::new(theory::MMC::new(self.effective_lambda, self.mu, self.servers as u32)),
+            }
+        };
+        let sample_w_q = self.queue_wait_sum / self.n_served as f64;
+        let sample_w = self.system_wait_sum / self.n_served as f64;
+        let n_joined = self.n_arrivals - self.n_blocked - self.n_balked;
+        // The customers fed back join the queue again.
+        let n_passes = n_joined + self.n_feedback;
+
+        // The priority theory is only for a single server, and under preemptive-repeat the service isn't memoryless.
+        let priority_theory = match &self.exponential_class_rates {
+            Some(rates) if self.discipline == Discipline::Priority && self.servers == 1 && self.preemption != Preemption::Repeat
+                && self.sharing.is_none() => {
+                let (lambdas, mus) = rates.iter().copied().unzip();
+                Some(theory::MM1Priority::new(lambdas, mus, self.preemption == Preemption::Resume))
+            }
+            _ => None,
+        };
+
+        CountAnalysis {
+            theory,
+            lambda: self.lambda,
+            mu: self.mu,
+            servers: self.servers,
+            capacity: self.capacity,
+            population: self.population,
+            reneging: self.reneging,
+            balking: self.balking,
+            closing_time: self.closing_time,
+            markovian: self.markovian,
+            seed: self.seed,
+            discipline: self.discipline,
+            preemption: self.preemption,
+            sharing: self.sharing,
+            srpt_theory: self.srpt_theory,
+            sizes: self.sizes.clone(),
+            sample_lambda,
+            sample_effective_lambda: n_passes as f64 / duration,
+            sample_blocking_probability: self.n_blocked as f64 / self.n_arrivals as f64,
+            sample_balking_fraction: self.n_balked as f64 / self.n_arrivals as f64,
+            sample_abandonment_fraction: self.abandoned_waits.count() as f64 / n_joined as f64,
+            served_waits: self.served_waits.clone(),
+            classes: self.classes.clone(),
+            priority_theory,
+            server_utilizations: self.server_statistics.iter()
+                .map(|statistics| statistics.utilization(end_time, duration))
+                .collect(),
+            server_statistics: self.server_statistics.clone(),
+            server_selection: self.server_selection,
+            heterogeneous_theory: self.heterogeneous_theory,
+            batch_size: self.batch_size,
+            bulk_service: self.bulk_service,
+            arrival_profile: self.arrival_profile.clone(),
+            pointwise_markovian: self.pointwise_markovian,
+            segments,
+            staffing: self.staffing.clone(),
+            staffing_intervals,
+            vacations: self.vacations.clone(),
+            breakdowns: self.breakdowns.clone(),
+            interruption_theory: self.interruption_theory,
+            retrial_distribution: self.retrial_distribution.clone(),
+            retrial_theory: self.retrial_theory.clone(),
+            sample_mean_orbit: self.retrial_distribution.as_ref()
+                .map(|_| (self.orbit_area + self.in_orbit as f64 * (end_time - self.time_of_last_event)) / duration),
+            sample_attempts: self.attempts_sum as f64 / self.n_served as f64,
+            n_retrials: self.n_retrials,
+            feedback_probabilities: self.feedback_probabilities.clone(),
+            effective_lambda: self.effective_lambda,
+            sample_visits: self.visits_sum as f64 / self.sojourn_times.count() as f64,
+            sojourn_times: self.sojourn_times.clone(),
+            duration,
+            horizon: self.horizon,
+            warm_up: self.warm_up,
+            abandoned_waits: self.abandoned_waits.clone(),
+            sample_mu,
+            sample_w_q,
+            sample_w,
+            sample_w_q_variance: self.queue_wait_square_sum / self.n_served as f64 - sample_w_q * sample_w_q,
+            sample_w_variance: self.system_wait_square_sum / self.n_served as f64 - sample_w * sample_w,
+            proportions,
+        }
+    }
+}
+
+/// What happened to the queue, written as a single character in the type column of an event file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventType {
+    /// "A": a customer arrived and joined the system.
+    Arrival,
+    /// "D": a customer finished service and left.
+    Departure,
+    /// "B": a customer arrived to a full system and left without joining.
+    Blocked,
+    /// "R": a waiting customer ran out of patience and reneged, leaving without being served.
+    Abandonment,
+    /// "K": a customer arrived, didn't like the look of the line and balked, leaving without joining.
+    Balked,
+    /// "U": a customer started (or resumed) service, `U_n` in the notes.
+    ServiceStart,
+    /// "P": a customer in service was preempted by a customer of higher priority and went back to waiting.
+    Preempted,
+    /// "V": a server went on vacation, or on another one.
+    Vacation,
+    /// "F": a server broke down, and its customers wait at it while it is repaired.
+    Breakdown,
+    /// "O": a server came back from vacation or was repaired.
+    ServerReturn,
+    /// "T": a customer in orbit tried for a server again, and either started service or went back into orbit.
+    Retrial,
+    /// "W": a customer finished service but needs rework, so they rejoin the queue for another pass instead of leaving.
+    Feedback,
+}
+
+impl EventType {
+    fn code(&self) -> &'static str {
+        match self {
+            EventType::Arrival => "A",
+            EventType::Departure => "D",
+            EventType::Blocked => "B",
+            EventType::Abandonment => "R",
+            EventType::Balked => "K",
+            EventType::ServiceStart => "U",
+            EventType::Preempted => "P",
+            EventType::Vacation => "V",
+            EventType::Breakdown => "F",
+            EventType::ServerReturn => "O",
+            EventType::Retrial => "T",
+            EventType::Feedback => "W",
+        }
+    }
+
+    /// Whether the event is a customer finishing in the system, served or not, so that the event carries their record.
+    pub fn has_served_customer(&self) -> bool {
+        matches!(self, EventType::Departure | EventType::Abandonment | EventType::Feedback)
+    }
+
+    /// Whether the event is a server changing state, rather than something happening to a customer.
+    pub fn is_server_change(&self) -> bool {
+        matches!(self, EventType::Vacation | EventType::Breakdown | EventType::ServerReturn)
+    }
+}
+
+impl std::str::FromStr for EventType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "A" => Ok(EventType::Arrival),
+            "D" => Ok(EventType::Departure),
+            "B" => Ok(EventType::Blocked),
+            "R" => Ok(EventType::Abandonment),
+            "K" => Ok(EventType::Balked),
+            "U" => Ok(EventType::ServiceStart),
+            "P" => Ok(EventType::Preempted),
+            "V" => Ok(EventType::Vacation),
+            "F" => Ok(EventType::Breakdown),
+            "O" => Ok(EventType::ServerReturn),
+            "T" => Ok(EventType::Retrial),
+            "W" => Ok(EventType::Feedback),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct QueueEvent {
+    time: f64,
+    event_type: EventType,
+    /// The class of the customer the event happened to.
+    class: usize,
+    /// The ID of the customer the event happened to.
+    customer: u64,
+    /// The ID of the batch the customer arrived in.
+    batch: u64,
+    /// How many times the customer has tried to get a server, counting their arrival as the first.
+    attempts: u32,
+    /// Which pass through the queue the customer is on.
+    visit: u32,
+    /// The server that started, stopped or finished serving the customer, for the events that involve one.
+    server: Option<usize>,
+    /// The number of customers that have joined the system, blocked and balking customers are not counted.
+    arrivals: u64,
+    /// The number of customers that have left the system, whether they were served or abandoned.
+    departures: u64,
+    in_system: u64,
+    /// The customer leaving the system on a departure or abandonment.
+    served_customer: Option<Customer>,
+}
+
+impl TryFrom<String> for QueueEvent {
+    type Error = QueueError;
+
+    fn try_from(line: String) -> Result<Self, Self::Error> {
+        let tokens: Vec<&str> = line.split_ascii_whitespace().collect();
+
+        let event_type: EventType = parse(&tokens, I_TYPE)?;
+        // Server changes don't happen to a customer.
+        let (class, customer, batch, attempts, visit) = if event_type.is_server_change() {
+            (0, 0, 0, 0, 0)
+        } else {
+            (parse(&tokens, I_CLASS)?, parse(&tokens, I_CUSTOMER)?, parse(&tokens, I_BATCH)?, parse(&tokens, I_ATTEMPTS)?,
+             parse(&tokens, I_VISIT)?)
+        };
+        let server = match tokens.get(I_SERVER) {
+            Some(&"-") => None,
+            _ => Some(parse(&tokens, I_SERVER)?),
+        };
+        let served_customer = if !event_type.has_served_customer() {
+            None
+        } else {
+            let time_of_departure: f64 = parse(&tokens, I_TIME_OF_DEPARTURE)?;
+            let sojourn_time: f64 = parse(&tokens, I_SOJOURN_TIME)?;
+            Some(Customer {
+                id: customer,
+                class,
+                batch,
+                attempts,
+                visit,
+                time_of_entry: time_of_departure - sojourn_time,
+                interarrival_time: parse(&tokens, I_INTERARRIVAL_TIME)?,
+                time_of_arrival: parse(&tokens, I_TIME_OF_ARRIVAL)?,
+                service_time: parse(&tokens, I_SERVICE_TIME)?,
+                time_of_service_start: parse(&tokens, I_TIME_OF_SERVICE_START)?,
+                time_of_departure,
+                wait_in_queue: parse(&tokens, I_WAIT_IN_QUEUE)?,
+                wait_in_system: parse(&tokens, I_WAIT_IN_SYSTEM)?,
+            })
+        };
+
+        Ok(QueueEvent {
+            time: parse(&tokens, I_TIME)?,
+            event_type,
+            class,
+            customer,
+            batch,
+            attempts,
+            visit,
+            server,
+            arrivals: parse(&tokens, I_ARRIVALS)?,
+            departures: parse(&tokens, I_DEPARTURES)?,
+            in_system: parse(&tokens, I_IN_SYSTEM)?,
+            served_customer,
+        })
+    }
+}
+
+/// Whether `rate` is `base` up to rounding, as when mu is derived from the rates it should equal.
+fn is_same_rate(rate: f64, base: f64) -> bool {
+    (rate - base).abs() <= 1.0e-12 * base
+}
+
+fn parse<T: std::str::FromStr>(tokens: &Vec<&str>, index: usize) -> Result<T, QueueError> {
+    let maybe_token = tokens.get(index);
+    if maybe_token.is_none() {
+        let msg = format!("Not enough tokens to read {}.", COLUMNS[index]);
+        return Err(QueueError::LineParsing(msg));
+    }
+
+    let value_or_parse_error = maybe_token.unwrap().parse::<T>();
+    if let Ok(value) = value_or_parse_error {
+        return Ok(value);
+    }
+
+    let msg = format!("Couldn't parse {} as {}", maybe_token.unwrap(), COLUMNS[index]);
+    Err(QueueError::LineParsing(msg))
+}
+
+impl QueueEvent {
+    pub fn dump_line_header<OUT: Write>(out: &mut OUT) -> Result<(), std::io::Error> {
+        writeln!(out, "# {}", COLUMNS.join(" "))?;
+
+        Ok(())
+    }
+
+    pub fn dump_line<OUT: Write>(&self, out: &mut OUT) -> Result<(), std::io::Error> {
+        let server = self.server.map_or_else(|| "-".to_string(), |server| server.to_string());
+        if let Some(customer) = &self.served_customer {
+            writeln!(out, "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}", self.time, self.arrivals, self.departures,
+                     self.in_system, self.event_type.code(), self.class, self.customer, self.batch, self.attempts, self.visit,
+                     server, customer.interarrival_time, customer.time_of_arrival, customer.service_time,
+                     customer.time_of_service_start, customer.time_of_departure,
+                     customer.wait_in_queue, customer.wait_in_system, customer.sojourn_time(),
+            )?;
+        } else if self.event_type.is_server_change() {
+            writeln!(out, "{} {} {} {} {} - - - - - {} - - - - - - - -", self.time, self.arrivals, self.departures, self.in_system,
+                     self.event_type.code(), server)?;
+        } else {
+            writeln!(out, "{} {} {} {} {} {} {} {} {} {} {} - - - - - - - -", self.time, self.arrivals, self.departures,
+                     self.in_system, self.event_type.code(), self.class, self.customer, self.batch, self.attempts, self.visit,
+                     server)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn new() -> QueueEvent {
+        QueueEvent {
+            time: 0.0,
+            event_type: EventType::Arrival,
+            class: 0,
+            customer: 0,
+            batch: 0,
+            attempts: 0,
+            visit: 0,
+            server: None,
+            arrivals: 0,
+            departures: 0,
+            in_system: 0,
+            served_customer: None,
+        }
+    }
+
+    fn arrival(self, time: f64, arrival: &ArrivingCustomer) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Arrival,
+            class: arrival.class(),
+            customer: arrival.id(),
+            batch: arrival.batch(),
+            attempts: arrival.attempts(),
+            visit: arrival.visit(),
+            server: None,
+            arrivals: self.arrivals + 1,
+            departures: self.departures,
+            in_system: self.in_system + 1,
+            served_customer: None,
+        }
+    }
+
+    fn departure(self, time: f64, served_customer: &Customer, server: usize) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Departure,
+            class: served_customer.class,
+            customer: served_customer.id,
+            batch: served_customer.batch,
+            attempts: served_customer.attempts,
+            visit: served_customer.visit,
+            server: Some(server),
+            arrivals: self.arrivals,
+            departures: self.departures + 1,
+            in_system: self.in_system - 1,
+            served_customer: Some(*served_customer),
+        }
+    }
+
+    /// A customer finishing a pass through the queue and rejoining it, so the counts don't change.
+    fn feedback(self, time: f64, served_customer: &Customer, server: usize) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Feedback,
+            class: served_customer.class,
+            customer: served_customer.id,
+            batch: served_customer.batch,
+            attempts: served_customer.attempts,
+            visit: served_customer.visit,
+            server: Some(server),
+            served_customer: Some(*served_customer),
+            ..self
+        }
+    }
+
+    fn abandonment(self, time: f64, abandoned_customer: &Customer) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Abandonment,
+            class: abandoned_customer.class,
+            customer: abandoned_customer.id,
+            batch: abandoned_customer.batch,
+            attempts: abandoned_customer.attempts,
+            visit: abandoned_customer.visit,
+            server: None,
+            departures: self.departures + 1,
+            in_system: self.in_system - 1,
+            served_customer: Some(*abandoned_customer),
+            ..self
+        }
+    }
+
+    fn balked(self, time: f64, arrival: &ArrivingCustomer) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Balked,
+            class: arrival.class(),
+            customer: arrival.id(),
+            batch: arrival.batch(),
+            attempts: arrival.attempts(),
+            visit: arrival.visit(),
+            server: None,
+            served_customer: None,
+            ..self
+        }
+    }
+
+    fn blocked(self, time: f64, arrival: &ArrivingCustomer) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Blocked,
+            class: arrival.class(),
+            customer: arrival.id(),
+            batch: arrival.batch(),
+            attempts: arrival.attempts(),
+            visit: arrival.visit(),
+            server: None,
+            served_customer: None,
+            ..self
+        }
+    }
+
+    fn retrial(self, time: f64, customer: &ArrivingCustomer) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Retrial,
+            class: customer.class(),
+            customer: customer.id(),
+            batch: customer.batch(),
+            attempts: customer.attempts(),
+            visit: customer.visit(),
+            server: None,
+            served_customer: None,
+            ..self
+        }
+    }
+
+    fn service_start(self, time: f64, customer: &Customer, server: usize) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::ServiceStart,
+            class: customer.class,
+            customer: customer.id,
+            batch: customer.batch,
+            attempts: customer.attempts,
+            visit: customer.visit,
+            server: Some(server),
+            served_customer: None,
+            ..self
+        }
+    }
+
+    fn preempted(self, time: f64, customer: &Customer, server: usize) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type: EventType::Preempted,
+            class: customer.class,
+            customer: customer.id,
+            batch: customer.batch,
+            attempts: customer.attempts,
+            visit: customer.visit,
+            server: Some(server),
+            served_customer: None,
+            ..self
+        }
+    }
+
+    /// A server changing state, with the counts of the event before.
+    fn server_change(self, time: f64, event_type: EventType, server: usize) -> QueueEvent {
+        QueueEvent {
+            time,
+            event_type,
+            class: 0,
+            customer: 0,
+            batch: 0,
+            attempts: 0,
+            visit: 0,
+            server: Some(server),
+            served_customer: None,
+            ..self
+        }
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn customer(&self) -> u64 {
+        self.customer
+    }
+
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn visit(&self) -> u32 {
+        self.visit
+    }
+
+    pub fn server(&self) -> Option<usize> {
+        self.server
+    }
+
+    pub fn served_customer(&self) -> &Option<Customer> {
+        &self.served_customer
+    }
+}
+
+impl Default for QueueEvent {
+    fn default() -> Self {
+        QueueEvent::new()
+    }
+}
+
+pub struct CountAnalysis {
+    /// M/M/c, or M/M/c/K when the capacity is finite, or M/M/c//N when the population is, or M/M/2 with
+    /// heterogeneous servers, or M^X/M/1 with batch arrivals, or M/M/c with retrials from an orbit.
+    theory: Box<dyn QueueTheory>,
+    lambda: f64,
+    mu: f64,
+    servers: i32,
+    capacity: Option<u32>,
+    population: Option<u32>,
+    reneging: bool,
+    balking: Option<Balking>,
+    closing_time: Option<f64>,
+    /// The theory is only Markovian, so the expected values are only a reference when the simulation was not.
+    markovian: bool,
+    seed: Option<u64>,
+    discipline: Discipline,
+    preemption: Preemption,
+    sharing: Option<Sharing>,
+    srpt_theory: Option<theory::MM1Srpt>,
+    sizes: BTreeMap<i32, SizeStatistics>,
+    sample_lambda: f64,
+    /// The rate of customers that actually joined the system.
+    sample_effective_lambda: f64,
+    sample_blocking_probability: f64,
+    sample_balking_fraction: f64,
+    /// The fraction of the customers that joined who abandoned before being served.
+    sample_abandonment_fraction: f64,
+    served_waits: WaitStatistics,
+    abandoned_waits: WaitStatistics,
+    classes: Vec<ClassStatistics>,
+    priority_theory: Option<theory::MM1Priority>,
+    /// The simulated time covered by the events.
+    server_statistics: Vec<ServerStatistics>,
+    server_utilizations: Vec<f64>,
+    server_selection: IdleServerPolicy,
+    heterogeneous_theory: Option<theory::MM2Heterogeneous>,
+    batch_size: Option<BatchSize>,
+    bulk_service: Option<u32>,
+    arrival_profile: Option<RateProfile>,
+    pointwise_markovian: bool,
+    segments: Vec<SegmentStatistics>,
+    staffing: Option<StaffingSchedule>,
+    staffing_intervals: Vec<SegmentStatistics>,
+    vacations: Option<Vacations<TimeDistribution>>,
+    breakdowns: Option<Breakdowns<TimeDistribution>>,
+    /// M/G/1 with vacations and breakdowns, when the simulation was.
+    interruption_theory: Option<theory::MG1Interruptions>,
+    retrial_distribution: Option<TimeDistribution>,
+    retrial_theory: Option<theory::MMCRetrial>,
+    /// The time-average number of customers in orbit, when customers retry.
+    sample_mean_orbit: Option<f64>,
+    /// The mean number of attempts of the customers who were served.
+    sample_attempts: f64,
+    n_retrials: u64,
+    feedback_probabilities: Vec<f64>,
+    effective_lambda: f64,
+    /// The mean passes through the queue of the customers who left after service.
+    sample_visits: f64,
+    sojourn_times: WaitStatistics,
+    duration: f64,
+    horizon: Option<f64>,
+    warm_up: Option<WarmUp>,
+    sample_mu: f64,
+    sample_w_q: f64,
+    sample_w: f64,
+    sample_w_q_variance: f64,
+    sample_w_variance: f64,
+    proportions: HashMap<u64, f64>,
+}
+
+impl CountAnalysis {
+    /// The measured proportion of time with `n` customers in the system.
+    fn measured_p(&self, n: u64) -> f64 {
+        self.proportions.get(&n).copied().unwrap_or(0.)
+    }
+
+    /// The most customers that were in the system at once.
+    fn max_n(&self) -> u64 {
+        self.proportions.keys().copied().max().unwrap_or(0)
+    }
+
+    /// The expected values are only valid for M/M/c and M/M/c/K...
+    pub fn dump_proportions(&self) {
+        println!("n measured_p_n p_n");
+        for n in 0..=self.max_n() {
+            println!("{} {} {}", n, self.measured_p(n), self.theory.p(n as u32));
+        }
+    }
+
+    pub fn dump_cross_sectional_statistics(&self) {
+        if !self.markovian {
+            println!("Note: the simulation was not Markovian, so the expected values are only the M/M/c reference.");
+        }
+        if let Some(seed) = self.seed {
+            println!("seed: {}", seed);
+        }
+        println!("discipline: {}", self.discipline);
+        if self.servers > 1 {
+            println!("server selection: {}", self.server_selection);
+        }
+        if self.preemption != Preemption::None {
+            println!("preemption: {}", self.preemption);
+        }
+        match self.sharing {
+            Some(Sharing::ProcessorSharing) => println!("sharing: processor sharing"),
+            Some(Sharing::RoundRobin { quantum }) => println!("sharing: round robin, quantum {} s", quantum),
+            None => {}
+        }
+        if let Some(warm_up) = self.warm_up {
+            println!("warm-up: the first {} events, up to {} s, were left out", warm_up.events, warm_up.time);
+        }
+        if let Some(horizon) = self.horizon {
+            println!("horizon: {} s", horizon);
+        }
+        if let Some(closing_time) = self.closing_time {
+            println!("Note: no one was admitted after closing time, {} s, so the system wasn't at steady state.", closing_time);
+        }
+        if self.arrival_profile.is_some() {
+            println!("Note: the arrival rate varies with time, so the system wasn't at steady state. See the breakdown by time of day.");
+        }
+        if self.staffing.is_some() {
+            println!("Note: the number of servers varies with time, so the system wasn't at steady state. See the breakdown by staffing interval.");
+        }
+        println!("lambda: sample = {}, input = {}", self.sample_lambda, self.lambda);
+        println!("mu: sample = {}, input = {}", self.sample_mu, self.mu);
+        if let Some(capacity) = self.capacity {
+            println!("capacity, K: {}", capacity);
+            println!("Blocking probability, p_K: sample = {}, expected = {}",
+                     self.sample_blocking_probability, self.theory.blocking_probability());
+        }
+        if let Some(population) = self.population {
+            // The arrival rate falls as the system fills, lambda is only the rate of each customer outside it.
+            println!("population, N: {}", population);
+            println!("Effective arrival rate: sample = {}, lambda per customer = {}, expected = {}",
+                     self.sample_effective_lambda, self.lambda, self.lambda * (population as f64 - self.theory.l()));
+        }
+        if let Some(batch_size) = self.batch_size {
+            println!("batch size, X: {} (mean {})", batch_size, batch_size.mean());
+        }
+        if let Some(bulk_service) = self.bulk_service {
+            println!("bulk service: up to {} customers at once", bulk_service);
+        }
+        if let Some(balking) = self.balking {
+            println!("balking: {}", balking);
+            println!("Balking fraction: sample = {}", self.sample_balking_fraction);
+        }
+        if self.balking.is_some() {
+            // The theory doesn't know about balking, so there is nothing to expect.
+            println!("Effective arrival rate: sample = {}, offered lambda = {}", self.sample_effective_lambda, self.lambda);
+        } else if self.capacity.is_some() {
+            println!("Effective arrival rate: sample = {}, offered lambda = {}, expected = {}",
+                     self.sample_effective_lambda, self.lambda, self.lambda * (1. - self.theory.blocking_probability()));
+        }
+
+        if self.reneging {
+            println!("Abandonment fraction: sample = {}", self.sample_abandonment_fraction);
+            println!("Wait in queue of served customers: {}", self.served_waits.summary());
+            println!("Wait in queue of abandoning customers: {}", self.abandoned_waits.summary());
+        }
+
+        // Steady state count of people in queue/system
+        let mut steady_customers_count = 0.;
+        for n in 0..=self.max_n() {
+            steady_customers_count += n as f64 * self.measured_p(n);
+        }
+        println!("Average number in system, L: sample = {}, expected = {}", steady_customers_count, self.theory.l());
+
+        // Only the customers beyond the number of servers are waiting, unless they retry from an orbit, where those
+        // who find a server free can be.
+        let mut steady_queue_count = 0.;
+        for n in (self.servers as u64 + 1)..=self.max_n() {
+            steady_queue_count += (n - self.servers as u64) as f64 * self.measured_p(n);
+        }
+        let steady_queue_count = self.sample_mean_orbit.unwrap_or(steady_queue_count);
+        println!("Average number in queue, L_q: sample = {}, expected = {}", steady_queue_count, self.theory.l_q());
+
+        // Average waits.
+        println!("Average wait in Queue, W_q: sample = {}, expected = {}", self.sample_w_q, self.theory.wait_in_queue());
+        println!("Average wait in system, W: sample = {}, expected = {}", self.sample_w, self.theory.wait_in_system());
+        // The mean waits don't depend on the discipline (as long as it doesn't look at service times), the variances do.
+        println!("Variance of wait in queue: sample = {}", self.sample_w_q_variance);
+        println!("Variance of wait in system: sample = {}", self.sample_w_variance);
+
+        println!();
+        println!("Little's Law:");
+        // Only the customers that joined spend time in the system, so Little's law uses the effective arrival rate.
+        println!("L = W * lambda_eff = {}, expected = {}", self.sample_w * self.sample_effective_lambda, self.theory.l());
+        println!("L_q = W_q * lambda_eff = {}, expected = {}", self.sample_w_q * self.sample_effective_lambda, self.theory.l_q());
+
+        if self.classes.len() > 1 {
+            println!();
+            println!("Per class:");
+            match &self.priority_theory {
+                Some(_) => println!("class lambda throughput W_q W expected_W_q expected_W"),
+                None => println!("class lambda throughput W_q W"),
+            }
+            for (i, class) in self.classes.iter().enumerate() {
+                print!("{} {} {} {} {}", i,
+                       class.n_arrivals as f64 / self.duration,
+                       class.n_served as f64 / self.duration,
+                       class.queue_wait_sum / class.n_served as f64,
+                       class.system_wait_sum / class.n_served as f64);
+                match &self.priority_theory {
+                    Some(theory) => println!(" {} {}", theory.wait_in_queue(i), theory.wait_in_system(i)),
+                    None => println!(),
+                }
+            }
+        }
+
+        if self.arrival_profile.is_some() {
+            println!();
+            self.dump_time_of_day();
+        }
+        if self.staffing.is_some() {
+            println!();
+            self.dump_staffing();
+        }
+        if self.vacations.is_some() || self.breakdowns.is_some() {
+            println!();
+            self.dump_interruptions();
+        }
+        if self.retrial_distribution.is_some() {
+            println!();
+            self.dump_retrials();
+        }
+        if !self.feedback_probabilities.is_empty() {
+            println!();
+            self.dump_feedback();
+        }
+        if matches!(self.discipline, Discipline::Sjf | Discipline::Ljf | Discipline::Srpt) || self.sharing.is_some() {
+            println!();
+            self.dump_response_by_size();
+        }
+
+        println!();
+        self.dump_server_statistics();
+    }
+
+    /// How long the servers were away on vacation or under repair, and the waits against the M/G/1 theory with
+    /// vacations and breakdowns when it applies. A broken down server holds its customers, so its repairs count
+    /// towards its utilization.
+    pub fn dump_interruptions(&self) {
+        println!("Interruptions:");
+        if let Some(vacations) = &self.vacations {
+            println!("vacations: {}, vacation time: {}", vacations.policy, vacations.distribution);
+        }
+        if let Some(breakdowns) = &self.breakdowns {
+            println!("breakdowns: time to failure: {}, repair time: {}, interruption: {}",
+                     breakdowns.time_to_failure, breakdowns.repair, breakdowns.interruption);
+        }
+        let mut vacations = WaitStatistics::default();
+        let mut repairs = WaitStatistics::default();
+        for statistics in &self.server_statistics {
+            vacations.merge(&statistics.vacations);
+            repairs.merge(&statistics.repairs);
+        }
+        let server_time = self.duration * self.server_statistics.len() as f64;
+        if self.vacations.is_some() {
+            println!("Vacations: {}", vacations.summary());
+            println!("Fraction of server time on vacation: sample = {}", vacations.mean() * vacations.count() as f64 / server_time);
+        }
+        if self.breakdowns.is_some() {
+            println!("Repairs: {}", repairs.summary());
+            println!("Fraction of server time under repair: sample = {}", repairs.mean() * repairs.count() as f64 / server_time);
+        }
+        if let Some(theory) = &self.interruption_theory {
+            println!("M/G/1 with interruptions:");
+            println!("Utilization, rho: sample = {}, expected = {}", self.server_utilizations[0], theory.utilization());
+            println!("Average wait in Queue, W_q: sample = {}, expected = {}", self.sample_w_q, theory.wait_in_queue());
+            println!("Average wait in system, W: sample = {}, expected = {}", self.sample_w, theory.wait_in_system());
+            println!("L = W * lambda_eff = {}, expected = {}", self.sample_w * self.sample_effective_lambda, theory.number_in_system());
+        }
+    }
+
+    /// The orbit of customers who found every server busy, and how many times customers tried for a server, against
+    /// the M/M/c retrial theory when it applies. The wait in the queue above is the time spent in orbit.
+    pub fn dump_retrials(&self) {
+        let retrial_distribution = match &self.retrial_distribution {
+            Some(retrial_distribution) => retrial_distribution,
+            None => return,
+        };
+        println!("Retrials:");
+        println!("retrial time: {}", retrial_distribution);
+        let mean_orbit = self.sample_mean_orbit.unwrap_or(0.);
+        // Everyone in orbit retries at the same rate, so the retrials over the time spent in orbit estimate it.
+        println!("Retrial rate, theta: sample = {}, input = {}", self.n_retrials as f64 / (mean_orbit * self.duration),
+                 retrial_distribution.rate());
+        match &self.retrial_theory {
+            Some(theory) => {
+                println!("Average number in orbit: sample = {}, expected = {}", mean_orbit, theory.number_in_queue());
+                println!("Average attempts per customer: sample = {}, expected = {}", self.sample_attempts, theory.attempts());
+            }
+            None => {
+                println!("Average number in orbit: sample = {}", mean_orbit);
+                println!("Average attempts per customer: sample = {}", self.sample_attempts);
+            }
+        }
+    }
+
+    /// The rework of customers fed back after service. The waits above are of each pass through the queue, and the
+    /// effective arrival rate counts every pass, while a customer's sojourn is from their first arrival to leaving.
+    pub fn dump_feedback(&self) {
+        println!("Feedback:");
+        let probabilities: Vec<String> = self.feedback_probabilities.iter().map(f64::to_string).collect();
+        println!("feedback probability: {}", probabilities.join(" "));
+        let expected_visits = self.effective_lambda / self.lambda;
+        println!("Effective arrival rate: sample = {}, expected = {}", self.sample_effective_lambda, self.effective_lambda);
+        println!("Visits per customer: sample = {}, expected = {}", self.sample_visits, expected_visits);
+        println!("Sojourn time: {}", self.sojourn_times.summary());
+        // By Little's law over the whole system the sojourn is L / lambda, the visits times the wait of a pass.
+        println!("Average sojourn time: sample = {}, expected = {}", self.sojourn_times.mean(),
+                 expected_visits * self.theory.wait_in_system());
+    }
+
+    /// The mean response time (wait in system) and slowdown (response time over job size) of the customers served, by
+    /// their job size, the service time they needed, in bins of a doubling. When the order of service depends on the
+    /// sizes, or the servers share their time, this is where large jobs pay for small ones.
+    ///
+    /// The expected values are at the mean size of each bin: from the SRPT theory, or x / (1 - rho) for a job of size
+    /// x under processor sharing.
+    pub fn dump_response_by_size(&self) {
+        println!("Response time by job size:");
+        let expected_response_time = |size: f64| match (&self.srpt_theory, self.sharing) {
+            (Some(theory), _) => Some(theory.response_time(size)),
+            (None, Some(Sharing::ProcessorSharing)) if self.markovian => Some(size / (1. - self.theory.utilization())),
+            _ => None,
+        };
+        if let Some(theory) = &self.srpt_theory {
+            println!("Average response time, W: sample = {}, expected = {}", self.sample_w, theory.wait_in_system());
+        }
+        match expected_response_time(1.) {
+            Some(_) => println!("size_from size_to n mean_size W slowdown expected_W expected_slowdown"),
+            None => println!("size_from size_to n mean_size W slowdown"),
+        }
+        for (bin, size) in &self.sizes {
+            let mean_size = size.size_sum / size.n_served as f64;
+            print!("{} {} {} {} {} {}", 2f64.powi(*bin), 2f64.powi(bin + 1), size.n_served, mean_size,
+                   size.response_sum / size.n_served as f64, size.slowdown_sum / size.n_served as f64);
+            match expected_response_time(mean_size) {
+                Some(expected) => println!(" {} {}", expected, expected / mean_size),
+                None => println!(),
+            }
+        }
+    }
+
+    /// The utilization, arrival rate, number in system and wait in each interval of the staffing schedule, over every
+    /// period. The utilization is of the servers on duty, so it can go over one while servers that went off duty
+    /// finish their customers. The waits are by the interval the customers arrived in.
+    pub fn dump_staffing(&self) {
+        let staffing = match &self.staffing {
+            Some(staffing) => staffing,
+            None => return,
+        };
+        println!("Staffing:");
+        match staffing.period() {
+            Some(period) => println!("staffing: {} intervals repeating every {} s, shift end: {}",
+                                     staffing.intervals(), period, staffing.shift_end()),
+            None => println!("staffing: {} intervals, shift end: {}", staffing.intervals(), staffing.shift_end()),
+        }
+        println!("interval start end servers utilization lambda L W");
+        for (i, interval) in self.staffing_intervals.iter().enumerate() {
+            let (start, end) = staffing.interval_bounds(i);
+            let servers = staffing.interval_servers(i);
+            let utilization = if servers > 0 {
+                (interval.busy_time / (servers as f64 * interval.duration)).to_string()
+            } else {
+                "-".to_string()
+            };
+            println!("{} {} {} {} {} {} {} {}", i, start, end, servers, utilization,
+                     interval.n_arrivals as f64 / interval.duration,
+                     interval.area / interval.duration, interval.system_wait_sum / interval.n_served as f64);
+        }
+    }
+
+    /// The arrival rate, number in system and wait in each segment of the arrival profile, over every period. The
+    /// waits are by the segment the customers arrived in. When the queue would otherwise be M/M/c, L is compared with
+    /// the pointwise stationary approximation, M/M/c at the segment's mean rate, which holds when the rate changes
+    /// slowly compared to the service.
+    pub fn dump_time_of_day(&self) {
+        let profile = match &self.arrival_profile {
+            Some(profile) => profile,
+            None => return,
+        };
+        println!("Time of day:");
+        match profile.period() {
+            Some(period) => println!("arrival profile: {} segments repeating every {} s", profile.segments(), period),
+            None => println!("arrival profile: {} segments", profile.segments()),
+        }
+        println!("segment start end lambda expected_lambda L W expected_L");
+        let mean_batch_size = self.batch_size.map_or(1., |batch_size| batch_size.mean());
+        for (i, segment) in self.segments.iter().enumerate() {
+            let (start, end) = profile.segment_bounds(i);
+            let expected_lambda = profile.segment_mean_rate(i) * mean_batch_size;
+            let expected_l = if self.pointwise_markovian && expected_lambda < self.servers as f64 * self.mu {
+                theory::MMC::new(expected_lambda, self.mu, self.servers as u32).l().to_string()
+            } else {
+                "-".to_string()
+            };
+            println!("{} {} {} {} {} {} {} {}", i, start, end,
+                     segment.n_arrivals as f64 / segment.duration, expected_lambda,
+                     segment.area / segment.duration, segment.system_wait_sum / segment.n_served as f64, expected_l);
+        }
+    }
+
+    /// How busy each server was, and the lengths of their busy and idle periods.
+    pub fn dump_server_statistics(&self) {
+        println!("Per server:");
+        let mean_utilization = self.server_utilizations.iter().sum::<f64>() / self.server_utilizations.len() as f64;
+        println!("Utilization, rho: sample = {}, expected = {}", mean_utilization, self.theory.utilization());
+        println!("server utilization served mean_busy_period mean_idle_period");
+        let mut busy_periods = WaitStatistics::default();
+        let mut idle_periods = WaitStatistics::default();
+        for (i, (statistics, utilization)) in self.server_statistics.iter().zip(&self.server_utilizations).enumerate() {
+            println!("{} {} {} {} {}", i, utilization, statistics.n_served,
+                     statistics.busy_periods.mean(), statistics.idle_periods.mean());
+            busy_periods.merge(&statistics.busy_periods);
+            idle_periods.merge(&statistics.idle_periods);
+        }
+        println!("Busy periods: {}", busy_periods.summary());
+        println!("Idle periods: {}", idle_periods.summary());
+        if let Some(theory) = &self.heterogeneous_theory {
+            let [utilization_0, utilization_1] = theory.server_utilizations();
+            println!("Server utilizations: expected = {} {}", utilization_0, utilization_1);
+        }
+        // Customers in orbit leave the server idle, so it isn't just waiting for the next arrival.
+        if self.servers == 1 && self.capacity.is_none() && self.population.is_none() && self.markovian
+            && self.retrial_distribution.is_none() {
+            // For M/M/1 the idle periods are interarrival times, and busy periods last 1 / (mu - lambda) on average.
+            // With batches they are the times between batches, and a busy period starts with a whole batch of work.
+            // Either way the server is busy for rho of the time, which with feedback includes the rework.
+            let mean_idle_period = self.batch_size.map_or(1., |batch_size| batch_size.mean()) / self.lambda;
+            let rho = self.theory.utilization();
+            println!("Mean busy period: expected = {}", mean_idle_period * rho / (1. - rho));
+            println!("Mean idle period: expected = {}", mean_idle_period);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::queues::*;
+    use crate::profiles::Interpolation;
+    use crate::servers::{FastestFirst, RoundRobin};
+    use crate::interruptions::{Interruption, VacationPolicy};
+    use crate::staffing::ShiftEnd;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn dump_events<DA: Distribution<f64>, DS: Distribution<f64>>(mut queue: Queue<DA, DS>, n: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for _ in 0..n {
+            queue.next_event().dump_line(&mut out).unwrap();
+        }
+        out
+    }
+
+    fn exponential(rate: f64) -> TimeDistribution {
+        TimeDistribution::Exponential { rate }
+    }
+
+    /// The next `n` events of the queue.
+    fn simulate<DA: Distribution<f64>, DS: Distribution<f64>>(queue: &mut Queue<DA, DS>, n: usize) -> Vec<QueueEvent> {
+        (0..n).map(|_| *queue.next_event()).collect()
+    }
 
-            customer_arrival_distribution: Exp::new(customer_arrival_rate).unwrap(),
-            customer_service_distribution,
-            rng,
+    /// Writes the events out under the header of `parameters`, as `simulate_queue` does, and analyses them as read
+    /// back by `analyse_queue`.
+    fn analyse(parameters: &Parameters, events: &[QueueEvent]) -> CountAnalysis {
+        let mut out = Vec::new();
+        parameters.dump_header(&mut out).unwrap();
+        for event in events {
+            event.dump_line(&mut out).unwrap();
+        }
 
-            queue: VecDeque::new(),
-            in_service: Vec::new(),
-            time: 0.,
-            next_customer: customer,
-            last_event: QueueEvent::new(),
+        let mut reader = BufReader::new(out.as_slice());
+        let mut analyser = EventAnalyser::new(&mut reader).unwrap();
+        for line in reader.lines() {
+            analyser.add_count(QueueEvent::try_from(line.unwrap()).unwrap());
         }
+        analyser.analysis()
     }
 
-    pub fn next_event(&mut self) -> &QueueEvent {
-        let (index, departure_time) = self.next_departure();
+    /// Analyses the next `n` events of the queue, simulated with `parameters`.
+    fn simulate_and_analyse<DA: Distribution<f64>, DS: Distribution<f64>>(
+        mut queue: Queue<DA, DS>,
+        parameters: &Parameters,
+        n: usize,
+    ) -> CountAnalysis {
+        analyse(parameters, &simulate(&mut queue, n))
+    }
 
-        let next_arrival_time = self.next_customer.arrival_time();
+    #[test]
+    fn same_seed_same_events() {
+        let first = dump_events(Queue::seeded(Exp::new(1.).unwrap(), Exp::new(0.6).unwrap(), 2, 42), 1000);
+        let second = dump_events(Queue::seeded(Exp::new(1.).unwrap(), Exp::new(0.6).unwrap(), 2, 42), 1000);
+        let other = dump_events(Queue::seeded(Exp::new(1.).unwrap(), Exp::new(0.6).unwrap(), 2, 43), 1000);
 
-        if departure_time < next_arrival_time {
-            // A customer in service departs before the next customer arrives.
-            let served_customer = self.in_service.remove(index);
-            self.time = departure_time;
-            self.last_event = self.last_event.departure(self.time, &served_customer);
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
 
-            // TODO: this is a function of queue discipline, this is first come first served.
-            let next_to_be_served = self.queue.pop_front();
+    #[test]
+    fn classes_are_superposed() {
+        // Class 0 arrives every 3 seconds from t=0, class 1 every 2 seconds from t=2, and service is instant.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(3.), deterministic(0.), 1, 0)
+            .with_class(deterministic(2.), deterministic(0.));
 
-            if let Some(waiting_customer) = next_to_be_served {
-                let customer = Customer::start_service(waiting_customer, self.time);
-                self.in_service.push(customer);
+        let mut arrivals = Vec::new();
+        while arrivals.len() < 6 {
+            let event = *queue.next_event();
+            if event.event_type() == EventType::Arrival {
+                arrivals.push((event.time, event.class));
             }
-        } else {
-            let arriving_customer = self.next_customer;
-            self.time = next_arrival_time;
-            self.last_event = self.last_event.arrival(self.time, &arriving_customer);
+        }
+        // Simultaneous arrivals are in order of class.
+        assert_eq!(vec![(0., 0), (2., 1), (3., 0), (4., 1), (6., 0), (6., 1)], arrivals);
+    }
 
-            if self.in_service.len() >= self.servers as usize {
-                self.queue.push_back(arriving_customer);
-            } else {
-                let customer = Customer::start_service(arriving_customer, self.time);
-                self.in_service.push(customer);
+    #[test]
+    fn impatient_customers_abandon() {
+        // One slow server and a customer every second, so everyone after the first has to wait, and waits at most 5.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(1.), deterministic(10.), 1, 0)
+            .with_patience(deterministic(5.));
+
+        let mut abandonments = 0;
+        for _ in 0..1000 {
+            let event = *queue.next_event();
+            if let Some(customer) = event.served_customer() {
+                assert!(customer.wait_in_queue() <= 5.);
+                if event.event_type() == EventType::Abandonment {
+                    assert_eq!(5., customer.wait_in_queue());
+                    abandonments += 1;
+                }
             }
-            self.next_customer = ArrivingCustomer::next_1_fcfs(&mut self.rng, &self.customer_arrival_distribution, &self.customer_service_distribution, arriving_customer);
+        }
+        assert!(abandonments > 0);
+    }
+
+    #[test]
+    fn higher_priorities_preempt_in_turn() {
+        // Customer 1 (class 2) starts at 97, is preempted at 98 by customer 2 (class 1), who is preempted at 100 by
+        // customer 3 (class 0). They go back into service in order of priority once customer 3 is done at 104.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let events = |preemption| {
+            let mut queue = Queue::seeded(deterministic(100.), deterministic(4.), 1, 0)
+                .with_class(deterministic(98.), deterministic(10.))
+                .with_class(deterministic(97.), deterministic(20.))
+                .with_discipline(Discipline::Priority)
+                .with_preemption(preemption);
+            let mut events = Vec::new();
+            while queue.time() < 140. {
+                events.push(*queue.next_event());
+            }
+            events
         };
+        let preemptions = |events: &[QueueEvent]| events.iter().filter(|event| event.event_type() == EventType::Preempted)
+            .map(|event| (event.time(), event.customer()))
+            .collect::<Vec<_>>();
+        let departures = |events: &[QueueEvent]| events.iter()
+            .filter_map(|event| event.served_customer()
+                .map(|customer| (event.time(), customer.id(), customer.wait_in_system(), customer.wait_in_queue())))
+            .collect::<Vec<_>>();
 
-        &self.last_event
+        // Resumed, customer 2 has 8 left and customer 1 has 19.
+        let resumed = events(Preemption::Resume);
+        assert_eq!(vec![(98., 1), (100., 2)], preemptions(&resumed));
+        assert_eq!(vec![(4., 0, 4., 0.), (104., 3, 4., 0.), (112., 2, 14., 4.), (131., 1, 34., 14.)], departures(&resumed));
+        // Repeated, they start over, and the service they lost counts as waiting.
+        let repeated = events(Preemption::Repeat);
+        assert_eq!(vec![(98., 1), (100., 2)], preemptions(&repeated));
+        assert_eq!(vec![(4., 0, 4., 0.), (104., 3, 4., 0.), (114., 2, 16., 6.), (134., 1, 37., 17.)], departures(&repeated));
     }
 
-    /// Returns the time of the next departure and the index of the customer in the in_service vector.
-    ///
-    /// When there are no customers in service, time is set to infinity and the index shouldn't be used.
-    fn next_departure(&self) -> (usize, f64) {
-        let mut next_departure_time = f64::INFINITY;
-        let mut index = 0;
-        for (i, customer) in self.in_service.iter().enumerate() {
-            if customer.time_of_departure < next_departure_time {
-                next_departure_time = customer.time_of_departure;
+    #[test]
+    fn priority_classes_wait_as_expected() {
+        for preemption in [Preemption::None, Preemption::Resume] {
+            let queue = Queue::seeded(exponential(0.3), exponential(1.), 1, 13)
+                .with_class(exponential(0.4), exponential(1.))
+                .with_discipline(Discipline::Priority)
+                .with_preemption(preemption);
+            let parameters = Parameters::new(exponential(0.3), exponential(1.), 1)
+                .with_class(CustomerClass::new(exponential(0.4), exponential(1.)))
+                .with_discipline(Discipline::Priority)
+                .with_preemption(preemption);
+            let analysis = simulate_and_analyse(queue, &parameters, 400000);
+            let theory = analysis.priority_theory.as_ref().unwrap();
+            for (k, class) in analysis.classes.iter().enumerate() {
+                let expected = theory.wait_in_system(k);
+                assert_approx_eq!(expected, class.system_wait_sum / class.n_served as f64, 0.05 * expected);
+            }
+        }
+    }
+
+    #[test]
+    fn discouraged_customers_balk() {
+        // With discouraged arrivals, b_n = 1 / (n + 1), M/M/1 has Poisson(lambda / mu) customers in the system, so the
+        // server is busy 1 - exp(-2) of the time and that is the effective arrival rate.
+        let mut queue = Queue::seeded(exponential(2.), exponential(1.), 1, 17).with_balking(Balking::Discouraged);
+        let events = simulate(&mut queue, 300000);
+        // The arrivals and balks by the number in the system they found.
+        let mut found = [(0u64, 0u64); 4];
+        for (i, event) in events.iter().enumerate().skip(1) {
+            let n = events[i - 1].in_system;
+            match event.event_type() {
+                EventType::Arrival if n < 4 => found[n as usize].0 += 1,
+                EventType::Balked => {
+                    assert_eq!((n, events[i - 1].arrivals), (event.in_system, event.arrivals));
+                    if n < 4 {
+                        found[n as usize].1 += 1;
+                    }
+                }
+                _ => {}
             }
-            index = i;
+        }
+        assert_eq!(0, found[0].1);
+        for (n, (arrived, balked)) in found.iter().enumerate().skip(1) {
+            let join_probability = Balking::Discouraged.join_probability(n as u64);
+            assert_approx_eq!(1. - join_probability, *balked as f64 / (arrived + balked) as f64, 0.02);
         }
 
-        return (index, next_departure_time);
+        let analysis = analyse(&Parameters::new(exponential(2.), exponential(1.), 1).with_balking(Balking::Discouraged), &events);
+        assert_approx_eq!(1. - (-2f64).exp(), analysis.sample_effective_lambda, 0.02);
+        assert_approx_eq!(2., analysis.sample_lambda, 0.02);
+        assert_approx_eq!(1. - analysis.sample_effective_lambda / analysis.sample_lambda, analysis.sample_balking_fraction, 0.01);
     }
 
-    pub fn empty(&mut self) -> Vec<QueueEvent> {
-        self.next_customer = ArrivingCustomer::never();
-        let mut emptied = Vec::new();
+    #[test]
+    fn busy_periods_match_mm1() {
+        // M/M/1 at rho = 0.6: busy periods last 1 / (mu - lambda) = 2.5 on average, idle periods 1 / lambda.
+        let queue = Queue::seeded(exponential(0.6), exponential(1.), 1, 19);
+        let analysis = simulate_and_analyse(queue, &Parameters::new(exponential(0.6), exponential(1.), 1), 300000);
+        assert_approx_eq!(0.6, analysis.server_utilizations[0], 0.01);
+        let server = &analysis.server_statistics[0];
+        assert_eq!(server.busy_periods.count(), server.idle_periods.count());
+        assert_approx_eq!(2.5, server.busy_periods.mean(), 0.1);
+        assert_approx_eq!(1. / 0.6, server.idle_periods.mean(), 0.03);
+        // A busy period serves 1 / (1 - rho) customers on average.
+        assert_approx_eq!(2.5, server.n_served as f64 / server.busy_periods.count() as f64, 0.1);
+    }
 
-        while !self.queue.is_empty() {
-             emptied.push(self.next_event().clone());
-        }
+    #[test]
+    fn classes_at_the_same_service_rate_are_markovian() {
+        // mu is derived from the work of the classes, and comes out as 3.0000000000000004.
+        let parameters = Parameters::new(exponential(0.1), exponential(3.), 1)
+            .with_class(CustomerClass::new(exponential(0.2), exponential(3.)));
+        assert_ne!(3., parameters.mu);
+        assert!(parameters.is_markovian());
+        let parameters = Parameters::new(exponential(0.1), exponential(3.), 1)
+            .with_class(CustomerClass::new(exponential(0.2), exponential(2.)));
+        assert!(!parameters.is_markovian());
+    }
 
-        emptied
+    #[test]
+    fn identical_servers_are_markovian() {
+        // mu is the mean of the server rates, and comes out as 0.10000000000000002.
+        let parameters = Parameters::new(exponential(0.2), exponential(0.1), 3)
+            .with_server_service_distributions(vec![exponential(0.1); 3]);
+        assert_ne!(0.1, parameters.mu);
+        assert!(parameters.is_markovian());
+        let parameters = Parameters::new(exponential(0.2), exponential(0.1), 3)
+            .with_server_service_distributions(vec![exponential(0.1), exponential(0.1), exponential(0.2)]);
+        assert!(!parameters.is_markovian());
     }
-}
 
-#[derive(Debug, Error)]
-pub enum QueueError {
-    #[error("Failed to read line.")]
-    LineReading(#[from] std::io::Error),
-    #[error("Failed to deserialize parameters from file header")]
-    ParameterReading(#[from] serde_json::Error),
-    #[error("Failed to parse a value from a Counts line")]
-    LineParsing(String),
-}
+    #[test]
+    fn files_with_other_columns_are_an_error() {
+        // The columns before classes, batches and servers were added.
+        let header = "# {\"lambda\":1.0,\"mu\":2.0,\"servers\":1}\n\
+            # time(s) arrivals departures in_system type interarrival_time time_of_arrival service_time \
+            time_of_service_start time_of_departure wait_in_queue wait_in_system\n";
+        let mut reader = BufReader::new(header.as_bytes());
+        match EventAnalyser::new(&mut reader) {
+            Err(QueueError::ColumnMismatch { found, .. }) => assert!(found.starts_with("# time(s) arrivals")),
+            _ => panic!("The old columns should be reported, not read."),
+        }
+    }
 
-const COLUMNS: [&str; 12] = [
-    "time(s)",
-    "arrivals",
-    "departures",
-    "in_system",
-    "type",
-    "interarrival_time",
-    "time_of_arrival",
-    "service_time",
-    "time_of_service_start",
-    "time_of_departure",
-    "wait_in_queue",
-    "wait_in_system",
-];
+    #[test]
+    fn preempted_customers_can_abandon() {
+        // Customer 1 (class 1) starts at 95 and is preempted at 100 by customer 2 (class 0), who takes until 120.
+        // Back in the queue they are only willing to wait 5 more, and abandon at 105 rather than resume.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(100.), deterministic(20.), 1, 0)
+            .with_class(deterministic(95.), deterministic(10.))
+            .with_discipline(Discipline::Priority)
+            .with_preemption(Preemption::Resume)
+            .with_patience(deterministic(5.));
+        let mut events = Vec::new();
+        while queue.time() < 130. {
+            events.push(*queue.next_event());
+        }
 
-const I_TIME: usize = 0;
-const I_ARRIVALS: usize = 1;
-const I_DEPARTURES: usize = 2;
-const I_IN_SYSTEM: usize = 3;
-const I_TYPE: usize = 4;
-const I_INTERARRIVAL_TIME: usize = 5;
-const I_TIME_OF_ARRIVAL: usize = 6;
-const I_SERVICE_TIME: usize = 7;
-const I_TIME_OF_SERVICE_START: usize = 8;
-const I_TIME_OF_DEPARTURE: usize = 9;
-const I_WAIT_IN_QUEUE: usize = 10;
-const I_WAIT_IN_SYSTEM: usize = 11;
+        let abandonments = events.iter().filter(|event| event.event_type() == EventType::Abandonment)
+            .map(|event| (event.time(), event.customer()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(105., 1)], abandonments);
+        let departures = events.iter().filter(|event| event.event_type() == EventType::Departure)
+            .map(|event| (event.time(), event.customer()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(20., 0), (120., 2)], departures);
+    }
 
-#[derive(Deserialize, Serialize)]
-pub struct Parameters {
-    lambda: f64,
-    mu: f64,
-    servers: Option<u8>,
-}
+    #[test]
+    fn drain_serves_everyone() {
+        let mut queue = Queue::seeded(Exp::new(3.).unwrap(), Exp::new(1.).unwrap(), 2, 5);
+        for _ in 0..1000 {
+            queue.next_event();
+        }
+        assert!(!queue.is_idle());
 
-#[derive(Default)]
-pub struct EventAnalyser {
-    lambda: f64,
-    mu: f64,
-    servers: i32,
+        let last = queue.drain().last().unwrap();
+        assert!(queue.is_finished());
+        assert_eq!(EventType::Departure, last.event_type());
+        assert_eq!(0, last.in_system);
+        assert_eq!(last.arrivals, last.departures);
+    }
 
-    last_service_start: f64,
-    n_served: u64,
-    service_time_sum: f64,
-    last_arrival: f64,
-    n_arrivals: u64,
-    arrival_time_sum: f64,
+    #[test]
+    fn no_one_is_admitted_after_closing_time() {
+        // A customer every second until closing at 10.5, each taking 3 seconds of the one server.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(1.), deterministic(3.), 1, 0)
+            .with_closing_time(10.5);
 
-    queue_wait_sum: f64,
-    system_wait_sum: f64,
-    arrival_epsilon: f64,
+        let mut events = Vec::new();
+        while !queue.is_finished() {
+            events.push(*queue.next_event());
+        }
+        let arrivals: Vec<f64> = events.iter()
+            .filter(|event| event.event_type() == EventType::Arrival)
+            .map(|event| event.time)
+            .collect();
+        assert_eq!((0..=10).map(f64::from).collect::<Vec<f64>>(), arrivals);
+        assert_eq!(33., events.last().unwrap().time);
+    }
 
-    last_n: u64,
-    time_in_n: HashMap<u64, f64>,
-    time_of_last_event: f64,
-}
+    #[test]
+    fn analysis_starts_after_the_warm_up() {
+        let mut queue = Queue::seeded(exponential(0.5), exponential(1.), 1, 3);
+        let mut events = 0;
+        while queue.peek_next_event_time().unwrap() <= 100. {
+            queue.next_event();
+            events += 1;
+        }
+        let warm_up = WarmUp { time: 100., events, in_system: queue.in_system() as u64, in_orbit: 0 };
 
-impl EventAnalyser {
-    pub fn new<R: Read>(reader: &mut BufReader<R>) -> Result<EventAnalyser, QueueError> {
-        let mut line_0 = String::new();
-        let _ = reader.read_line(&mut line_0).map_err(|e| QueueError::LineReading(e))?;
-        let params: Parameters = serde_json::from_str(&line_0[1..]).map_err(|e| QueueError::ParameterReading(e))?;
+        let parameters = Parameters::new(exponential(0.5), exponential(1.), 1)
+            .with_warm_up(warm_up)
+            .with_horizon(1100.);
+        let mut events = Vec::new();
+        while queue.peek_next_event_time().unwrap() <= 1100. {
+            events.push(*queue.next_event());
+        }
 
-        // Sanity check header line. TODO: Surely there is a better way to column serialize...
-        let mut line_1 = String::new();
-        let _ = reader.read_line(&mut line_1).map_err(|e| QueueError::LineReading(e))?;
-        let expected = format!("# {} {} {} {} {} {} {} {} {} {} {} {}\n",
-                               COLUMNS[I_TIME], COLUMNS[I_ARRIVALS], COLUMNS[I_DEPARTURES], COLUMNS[I_IN_SYSTEM],
-                               COLUMNS[I_TYPE],
-                               COLUMNS[I_INTERARRIVAL_TIME], COLUMNS[I_TIME_OF_ARRIVAL], COLUMNS[I_SERVICE_TIME],
-                               COLUMNS[I_TIME_OF_SERVICE_START], COLUMNS[I_TIME_OF_DEPARTURE],
-                               COLUMNS[I_WAIT_IN_QUEUE], COLUMNS[I_WAIT_IN_SYSTEM],
-        );
-        assert_eq!(expected, line_1);
+        let analysis = analyse(&parameters, &events);
+        assert_eq!(1000., analysis.duration);
+        let total: f64 = (0..=analysis.max_n()).map(|n| analysis.measured_p(n)).sum();
+        assert_approx_eq!(1., total, 1.0e-9);
+    }
 
-        let mut analyser = EventAnalyser::default();
-        analyser.lambda = params.lambda;
-        analyser.mu = params.mu;
-        // TODO: get this from params
-        analyser.servers = params.servers.unwrap_or(1) as i32;
+    #[test]
+    fn queues_can_be_stepped() {
+        let mut queue = Queue::seeded(Exp::new(1.).unwrap(), Exp::new(1.5).unwrap(), 1, 11);
 
-        Ok(analyser)
+        let departures = queue.by_ref()
+            .take(100)
+            .filter(|event| event.event_type() == EventType::Departure)
+            .count();
+        assert!(departures > 0);
+
+        for step in 1..=10 {
+            let until = 100. * step as f64;
+            assert!(queue.advance_until(until).all(|event| event.time() <= until));
+            assert_eq!(until, queue.time());
+            assert!(queue.peek_next_event_time().unwrap() > until);
+        }
     }
 
-    pub fn add_count(&mut self, count: QueueEvent) {
-        if !self.time_in_n.contains_key(&self.last_n) {
-            self.time_in_n.insert(self.last_n, 0.);
+    #[test]
+    fn customers_can_be_followed_through_the_system() {
+        let mut queue = Queue::seeded(Exp::new(1.8).unwrap(), Exp::new(1.).unwrap(), 2, 13);
+
+        let mut lifecycles: HashMap<u64, Vec<QueueEvent>> = HashMap::new();
+        for event in queue.by_ref().take(10_000) {
+            lifecycles.entry(event.customer()).or_default().push(event);
+        }
+        for event in queue.drain() {
+            lifecycles.entry(event.customer()).or_default().push(event);
         }
-        let delta_t = count.time - self.time_of_last_event;
-        let new_time_in_n = self.time_in_n.get(&self.last_n).unwrap() + delta_t;
-        self.time_in_n.insert(self.last_n, new_time_in_n);
 
-        if count.in_system < self.last_n {
-            // Departure.
-            let service_time = count.time - self.last_service_start;
-            self.n_served += 1;
-            self.service_time_sum += service_time;
-            self.last_service_start = count.time;
+        assert_eq!((0..lifecycles.len() as u64).collect::<Vec<u64>>(), {
+            let mut ids: Vec<u64> = lifecycles.keys().copied().collect();
+            ids.sort_unstable();
+            ids
+        });
+        for events in lifecycles.values() {
+            let types: Vec<EventType> = events.iter().map(QueueEvent::event_type).collect();
+            assert_eq!(vec![EventType::Arrival, EventType::ServiceStart, EventType::Departure], types);
+            let (start, departure) = (events[1], events[2]);
+            assert_eq!(start.server(), departure.server());
+            let customer = departure.served_customer().unwrap();
+            assert_eq!(start.time(), customer.time_of_service_start);
+        }
+    }
 
-            let customer = count.served_customer.as_ref()
-                .expect("If the in_system count has decreased and the line event didn't contain a served_customer, the event was constructed improperly.");
-            self.system_wait_sum += customer.wait_in_system;
-            self.queue_wait_sum += customer.wait_in_queue;
-        } else {
-            // Arrival.
-            let interarrival_time = count.time - self.last_arrival;
-            self.n_arrivals += 1;
-            self.arrival_time_sum += interarrival_time;
-            self.last_arrival = count.time;
+    #[test]
+    fn customers_depart_when_their_service_ends() {
+        let mut queue = Queue::seeded(Exp::new(4.).unwrap(), Exp::new(1.).unwrap(), 5, 7);
 
-            if self.last_n == 0 {
-                self.last_service_start = count.time;
+        let mut time = 0.;
+        for _ in 0..10_000 {
+            let event = *queue.next_event();
+            assert!(event.time >= time);
+            time = event.time;
+            if let Some(customer) = event.served_customer() {
+                assert_eq!(event.time, customer.time_of_departure());
             }
         }
-
-        self.last_n = count.in_system;
-        self.time_of_last_event = count.time;
     }
 
-    pub fn analysis(&self) -> CountAnalysis {
-        let sample_lambda = self.n_arrivals as f64 / self.arrival_time_sum;
-        let sample_mu = self.n_served as f64 / self.service_time_sum;
+    #[test]
+    fn service_time_depends_on_the_server() {
+        // Customers every 3 seconds, server 1 takes 1 second and server 0 takes 2, so they never overlap.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let queue = Queue::seeded(deterministic(3.), deterministic(5.), 2, 0)
+            .with_server_service_distributions(vec![deterministic(2.), deterministic(1.)]);
+        let mut fastest = queue.with_server_selection(FastestFirst::new(&[2., 1.]));
+        for _ in 0..30 {
+            let event = *fastest.next_event();
+            if let Some(customer) = event.served_customer() {
+                assert_eq!(Some(1), event.server());
+                assert_eq!(1., customer.wait_in_system());
+            }
+        }
 
-        let mut proportions = HashMap::new();
-        for (n, time_in_n) in &self.time_in_n {
-            proportions.insert(*n, time_in_n / self.time_of_last_event);
+        let mut round_robin = Queue::seeded(deterministic(3.), deterministic(5.), 2, 0)
+            .with_server_service_distributions(vec![deterministic(2.), deterministic(1.)])
+            .with_server_selection(RoundRobin::default());
+        let mut departures = Vec::new();
+        while departures.len() < 4 {
+            let event = *round_robin.next_event();
+            if let Some(customer) = event.served_customer() {
+                departures.push((event.server().unwrap(), customer.wait_in_system()));
+            }
         }
-        
-        let theory = theory::MMC::new(self.lambda, self.mu, self.servers as u8);
+        assert_eq!(vec![(0, 2.), (1, 1.), (0, 2.), (1, 1.)], departures);
+    }
 
-        CountAnalysis {
-            theory,
-            sample_lambda,
-            sample_mu,
-            sample_w_q: self.queue_wait_sum / self.n_served as f64,
-            sample_w: self.system_wait_sum / self.n_served as f64,
-            proportions,
+    #[test]
+    fn finite_population_arrives_less_as_the_system_fills() {
+        // The machine repair model with five machines and two repairers.
+        let mut queue = Queue::seeded(Exp::new(0.2).unwrap(), Exp::new(0.5).unwrap(), 2, 11).with_population(5);
+        let theory = theory::MMCN::new(0.2, 0.5, 2, 5);
+
+        let mut time_in_n = [0.; 6];
+        let mut arrivals = 0;
+        for _ in 0..400_000 {
+            let n = queue.in_system();
+            let time = queue.time();
+            let event = *queue.next_event();
+            time_in_n[n] += event.time - time;
+            if event.event_type() == EventType::Arrival {
+                arrivals += 1;
+            }
         }
+        assert!(queue.in_system() <= 5);
+        for (n, time) in time_in_n.iter().enumerate() {
+            assert_approx_eq!(theory.proportion(n as u32), time / queue.time(), 0.01);
+        }
+        let arrival_rate = arrivals as f64 / queue.time();
+        assert_approx_eq!(theory.effective_arrival_rate(), arrival_rate, 0.02 * arrival_rate);
     }
-}
 
-#[derive(Copy, Clone)]
-pub struct QueueEvent {
-    time: f64,
-    arrivals: u64,
-    departures: u64,
-    in_system: u64,
-    served_customer: Option<Customer>,
-}
+    #[test]
+    fn batch_members_share_an_arrival_time() {
+        // Batches of three every 10 seconds, each customer taking 1 second at the single server.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(10.), deterministic(1.), 1, 0)
+            .with_batch_arrivals(BatchSize::Fixed { size: 3 });
+        let mut arrivals = Vec::new();
+        let mut waits = Vec::new();
+        while waits.len() < 6 {
+            let event = *queue.next_event();
+            match event.served_customer() {
+                Some(customer) => waits.push(customer.wait_in_queue()),
+                None if event.event_type() == EventType::Arrival => arrivals.push((event.time(), event.batch())),
+                None => {}
+            }
+        }
+        assert_eq!(vec![(0., 0), (0., 0), (0., 0), (10., 1), (10., 1), (10., 1)], arrivals);
+        assert_eq!(vec![0., 1., 2., 0., 1., 2.], waits);
+    }
 
-impl TryFrom<String> for QueueEvent {
-    type Error = QueueError;
+    #[test]
+    fn bulk_service_takes_several_customers_at_once() {
+        // The same batches of three, but the server takes two at a time, taking 4 seconds for both.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(10.), deterministic(4.), 1, 0)
+            .with_batch_arrivals(BatchSize::Fixed { size: 3 })
+            .with_bulk_service(2);
+        let mut departures = Vec::new();
+        while departures.len() < 6 {
+            let event = *queue.next_event();
+            assert!(queue.in_system() <= 3);
+            if let Some(customer) = event.served_customer() {
+                departures.push((event.time(), customer.wait_in_queue()));
+            }
+        }
+        assert_eq!(vec![(4., 0.), (4., 0.), (8., 4.), (14., 0.), (14., 0.), (18., 4.)], departures);
+    }
 
-    fn try_from(line: String) -> Result<Self, Self::Error> {
-        let tokens: Vec<&str> = line.split_ascii_whitespace().into_iter().collect();
+    #[test]
+    fn arrivals_follow_the_rate_profile() {
+        // Open for the first 10 seconds of every 20 at one customer a second, then closed.
+        let profile = RateProfile::new(vec![0., 10.], vec![1., 0.], Interpolation::Step).unwrap().with_period(20.).unwrap();
+        let mut queue = Queue::seeded(Exp::new(1.).unwrap(), Exp::new(5.).unwrap(), 1, 3).with_arrival_profile(profile);
+        let mut arrivals = 0;
+        while queue.time() < 20_000. {
+            let event = *queue.next_event();
+            if event.event_type() == EventType::Arrival {
+                assert!(event.time() % 20. < 10., "No one arrives while closed, but someone did at {}", event.time());
+                arrivals += 1;
+            }
+        }
+        assert_approx_eq!(10_000., arrivals as f64, 300.);
+    }
 
-        let served_customer = if tokens[I_TYPE] == "A" {
-            None
-        } else {
-            Some(Customer {
-                interarrival_time: parse(&tokens, I_INTERARRIVAL_TIME)?,
-                time_of_arrival: parse(&tokens, I_TIME_OF_ARRIVAL)?,
-                service_time: parse(&tokens, I_SERVICE_TIME)?,
-                time_of_service_start: parse(&tokens, I_TIME_OF_SERVICE_START)?,
-                time_of_departure: parse(&tokens, I_TIME_OF_DEPARTURE)?,
-                wait_in_queue: parse(&tokens, I_WAIT_IN_QUEUE)?,
-                wait_in_system: parse(&tokens, I_WAIT_IN_SYSTEM)?,
-            })
+    #[test]
+    fn shifts_end_by_finishing_or_requeueing() {
+        // One server on duty except from 12 to 30 seconds of every 40, customers arriving every 10 seconds, each
+        // taking 8 seconds to serve. The second customer is still being served when the shift ends at 12.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let schedule = StaffingSchedule::new(vec![0., 12., 30.], vec![1, 0, 1]).unwrap().with_period(40.).unwrap();
+        let events = |shift_end| {
+            let mut queue = Queue::seeded(deterministic(10.), deterministic(8.), 1, 0)
+                .with_staffing(schedule.clone().with_shift_end(shift_end));
+            let mut events = Vec::new();
+            while queue.time() < 53. {
+                events.push(*queue.next_event());
+            }
+            events
         };
+        let departures = |events: &[QueueEvent]| events.iter()
+            .filter_map(|event| event.served_customer().map(|customer| (event.time(), customer.wait_in_system())))
+            .collect::<Vec<_>>();
 
-        Ok(QueueEvent {
-            time: parse(&tokens, I_TIME)?,
-            arrivals: parse(&tokens, I_ARRIVALS)?,
-            departures: parse(&tokens, I_DEPARTURES)?,
-            in_system: parse(&tokens, I_IN_SYSTEM)?,
-            served_customer,
-        })
+        let finished = events(ShiftEnd::Finish);
+        assert_eq!(vec![(8., 8.), (18., 8.), (38., 18.), (46., 16.), (54., 14.)], departures(&finished));
+        assert!(finished.iter().all(|event| event.event_type() != EventType::Preempted));
+
+        // Handed back at 12 with 6 seconds to go, resumed when the server comes back on duty.
+        let requeued = events(ShiftEnd::Requeue);
+        assert_eq!(vec![(8., 8.), (36., 26.), (44., 24.), (52., 22.)], departures(&requeued));
+        assert!(requeued.iter().any(|event| event.event_type() == EventType::Preempted && event.time() == 12.));
+        for event in finished.iter().chain(&requeued).filter(|event| event.event_type() == EventType::ServiceStart) {
+            assert!(!(12. ..30.).contains(&(event.time() % 40.)), "Service started off duty at {}", event.time());
+        }
     }
-}
 
-fn parse<T: std::str::FromStr>(tokens: &Vec<&str>, index: usize) -> Result<T, QueueError> {
-    let maybe_token = tokens.get(index);
-    if maybe_token.is_none() {
-        let msg = format!("Not enough tokens to read {}.", COLUMNS[index]);
-        return Err(QueueError::LineParsing(msg));
+    #[test]
+    fn servers_go_on_vacation_when_no_one_is_waiting() {
+        // Customers arrive every 10 seconds and take 4 to serve, and vacations last 4 seconds.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let events = |policy| {
+            let mut queue = Queue::seeded(deterministic(10.), deterministic(4.), 1, 0)
+                .with_vacations(policy, deterministic(4.));
+            (0..12).map(|_| {
+                let event = *queue.next_event();
+                (event.event_type().code(), event.time())
+            }).collect::<Vec<_>>()
+        };
+        // After a single vacation the server waits for the next customer.
+        assert_eq!(vec![("A", 0.), ("U", 0.), ("D", 4.), ("V", 4.), ("O", 8.), ("A", 10.), ("U", 10.), ("D", 14.),
+                        ("V", 14.), ("O", 18.), ("A", 20.), ("U", 20.)], events(VacationPolicy::Single));
+        // With multiple vacations it keeps going until it comes back to someone waiting.
+        assert_eq!(vec![("A", 0.), ("U", 0.), ("D", 4.), ("V", 4.), ("V", 8.), ("A", 10.), ("O", 12.), ("U", 12.),
+                        ("D", 16.), ("V", 16.), ("A", 20.), ("O", 20.)], events(VacationPolicy::Multiple));
     }
 
-    let value_or_parse_error = maybe_token.unwrap().parse::<T>();
-    if let Ok(value) = value_or_parse_error {
-        return Ok(value);
+    #[test]
+    fn full_systems_block_arrivals() {
+        // M/M/2/4 at an offered load of 1.5 per server, so arrivals are often blocked.
+        let mut queue = Queue::seeded(exponential(3.), exponential(1.), 2, 3).with_capacity(4);
+        let events = simulate(&mut queue, 200000);
+        let mut blocked = 0;
+        for (i, event) in events.iter().enumerate().skip(1) {
+            if event.event_type() == EventType::Blocked {
+                // Turned away from a full system, without being counted as an arrival.
+                assert_eq!((4, 4), (events[i - 1].in_system, event.in_system));
+                assert_eq!(events[i - 1].arrivals, event.arrivals);
+                blocked += 1;
+            }
+            assert!(event.in_system <= 4);
+        }
+        assert!(blocked > 0);
+
+        let analysis = analyse(&Parameters::new(exponential(3.), exponential(1.), 2).with_capacity(4), &events);
+        let theory = theory::MMCK::new(3., 1., 2, 2);
+        assert_approx_eq!(theory.blocking_probability(), analysis.sample_blocking_probability, 0.01);
+        let l = (0..=analysis.max_n()).map(|n| n as f64 * analysis.measured_p(n)).sum::<f64>();
+        assert_approx_eq!(theory.number_in_system(), l, 0.05);
+        assert_approx_eq!(theory.blocking_probability(), analysis.theory.blocking_probability(), 1.0e-9);
     }
 
-    let msg = format!("Couldn't parse {} as {}", maybe_token.unwrap(), COLUMNS[index]);
-    return Err(QueueError::LineParsing(msg));
-}
+    #[test]
+    fn blocked_customers_retry_from_the_orbit() {
+        // Customers arrive every 10 seconds and take 25 to serve, and those who find the server busy retry every 4.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(10.), deterministic(25.), 1, 0)
+            .with_retrials(deterministic(4.));
+        let events = (0..12).map(|_| {
+            let event = *queue.next_event();
+            (event.event_type().code(), event.time(), event.customer(), event.attempts())
+        }).collect::<Vec<_>>();
+        assert_eq!(vec![("A", 0., 0, 1), ("U", 0., 0, 1), ("A", 10., 1, 1), ("T", 14., 1, 2), ("T", 18., 1, 3),
+                        ("A", 20., 2, 1), ("T", 22., 1, 4), ("T", 24., 2, 2), ("D", 25., 0, 1), ("T", 26., 1, 5),
+                        ("U", 26., 1, 5), ("T", 28., 2, 3)], events);
+        // No one waits in the queue, whoever isn't being served is in orbit.
+        assert_eq!(2, queue.in_system());
+        assert_eq!(1, queue.in_orbit());
 
-impl QueueEvent {
-    pub fn dump_line_header<OUT: Write>(out: &mut OUT) -> Result<(), std::io::Error> {
-        writeln!(out, "# time(s) arrivals departures in_system type \
-        interarrival_time time_of_arrival service_time \
-        time_of_service_start time_of_departure \
-        wait_in_queue wait_in_system")?;
+        // The time in orbit is the wait in the queue.
+        let served = queue.find(|event| event.event_type() == EventType::Departure).unwrap();
+        let customer = served.served_customer().unwrap();
+        assert_eq!((51., 1, 5, 16.), (served.time(), customer.id(), customer.attempts(), customer.wait_in_queue()));
+    }
 
-        Ok(())
+    #[test]
+    fn reworked_customers_go_round_again() {
+        // Customers arrive every 100 seconds and take 2 to serve, so no one waits, and half of them need rework.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(100.), deterministic(2.), 1, 7).with_feedback(vec![0.5]);
+        let events = simulate(&mut queue, 30000);
+        for (i, event) in events.iter().enumerate() {
+            match event.event_type() {
+                EventType::Feedback => {
+                    // Still in the system, and straight back into service for the next pass.
+                    assert_eq!(events[i - 1].in_system, event.in_system);
+                    if let Some(next) = events.get(i + 1) {
+                        assert_eq!((EventType::ServiceStart, event.customer(), event.visit() + 1),
+                                   (next.event_type(), next.customer(), next.visit()));
+                    }
+                }
+                EventType::Departure => {
+                    let customer = event.served_customer().unwrap();
+                    assert_approx_eq!(2. * customer.visit() as f64, customer.sojourn_time(), 1.0e-9);
+                }
+                _ => {}
+            }
+        }
+        let analysis = analyse(&Parameters::new(deterministic(100.), deterministic(2.), 1).with_feedback(vec![0.5]), &events);
+        assert_approx_eq!(2., analysis.sample_visits, 0.05);
+        assert_approx_eq!(0.02, analysis.sample_effective_lambda, 0.001);
     }
 
-    pub fn dump_line<OUT: Write>(&self, out: &mut OUT) -> Result<(), std::io::Error> {
-        if self.served_customer.is_none() {
-            writeln!(out, "{} {} {} {} A - - - - - - -", self.time, self.arrivals, self.departures, self.arrivals - self.departures)?;
-        } else {
-            let customer = self.served_customer.as_ref().unwrap();
-            writeln!(out, "{} {} {} {} D {} {} {} {} {} {} {}", self.time, self.arrivals, self.departures, self.arrivals - self.departures,
-                     customer.interarrival_time, customer.time_of_arrival, customer.service_time,
-                     customer.time_of_service_start, customer.time_of_departure,
-                     customer.wait_in_queue, customer.wait_in_system,
-            )?;
+    #[test]
+    fn processor_sharing_slows_everyone_down() {
+        // Class 0 arrives every 10 seconds and takes 4 to serve, class 1 arrives at 11 and takes 2. Sharing from 11, the
+        // class 1 customer is done at 15, by when the other has had 3 of their 4 seconds, and finishes alone at 16.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(10.), deterministic(4.), 1, 0)
+            .with_class(deterministic(11.), deterministic(2.))
+            .with_sharing(Sharing::ProcessorSharing);
+        let mut events = Vec::new();
+        while queue.time() < 16. {
+            events.push(*queue.next_event());
         }
 
-        Ok(())
+        let departures = events.iter()
+            .filter_map(|event| event.served_customer().map(|customer| (event.time(), customer.id(), customer.wait_in_system())))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(4., 0, 4.), (15., 2, 4.), (16., 1, 6.)], departures);
+        // Everyone starts service as they arrive.
+        let starts = events.iter().filter(|event| event.event_type() == EventType::ServiceStart)
+            .map(|event| (event.time(), event.customer()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(0., 0), (10., 1), (11., 2)], starts);
     }
 
-    pub fn new() -> QueueEvent {
-        QueueEvent {
-            time: 0.0,
-            arrivals: 0,
-            departures: 0,
-            in_system: 0,
-            served_customer: None,
-        }
+    #[test]
+    fn processor_sharing_is_insensitive_to_the_service_times() {
+        // M/D/1-PS has the mean sojourn of M/M/1, 1 / (mu - lambda) = 4, where first come first served would be 2.5.
+        let deterministic = TimeDistribution::Deterministic { value: 1. };
+        let queue = Queue::seeded(exponential(0.75), deterministic.clone(), 1, 11).with_sharing(Sharing::ProcessorSharing);
+        let parameters = Parameters::new(exponential(0.75), deterministic, 1).with_sharing(Sharing::ProcessorSharing);
+        let analysis = simulate_and_analyse(queue, &parameters, 400000);
+        assert!(analysis.markovian);
+        assert_approx_eq!(4., analysis.theory.wait_in_system(), 1.0e-9);
+        assert_approx_eq!(analysis.theory.wait_in_system(), analysis.sample_w, 0.25);
+        assert_approx_eq!(0.75, analysis.server_utilizations[0], 0.01);
     }
 
-    fn arrival(self, time: f64, _arrival: &ArrivingCustomer) -> QueueEvent {
-        QueueEvent {
-            time,
-            arrivals: self.arrivals + 1,
-            departures: self.departures,
-            in_system: self.in_system + 1,
-            served_customer: None,
+    #[test]
+    fn round_robin_takes_turns() {
+        // One second turns. Customer 0 is alone and keeps getting the server back, customers 1 (3 s) and 2 (2 s)
+        // arrive at 100 and 100.5 and take turns.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(100.), deterministic(3.), 1, 0)
+            .with_class(deterministic(100.5), deterministic(2.))
+            .with_sharing(Sharing::RoundRobin { quantum: 1. });
+        let mut events = Vec::new();
+        while queue.time() < 105. {
+            events.push(*queue.next_event());
         }
+
+        let preemptions = events.iter().filter(|event| event.event_type() == EventType::Preempted)
+            .map(|event| (event.time(), event.customer()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(1., 0), (2., 0), (101., 1), (102., 2), (103., 1)], preemptions);
+        let departures = events.iter()
+            .filter_map(|event| event.served_customer().map(|customer| (event.time(), customer.id(), customer.wait_in_system())))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(3., 0, 3.), (104., 2, 3.5), (105., 1, 5.)], departures);
     }
 
-    fn departure(self, time: f64, served_customer: &Customer) -> QueueEvent {
-        QueueEvent {
-            time,
-            arrivals: self.arrivals,
-            departures: self.departures + 1,
-            in_system: self.in_system - 1,
-            served_customer: Some(*served_customer),
+    #[test]
+    fn srpt_preempts_longer_jobs() {
+        // Class 0 needs 10 s every 100 s, class 1 needs 2 s every 103 s. At 103 and 206 the class 0 customer in
+        // service has 7 and 4 s left and is preempted, at 309 they only have 1 s left and the arrival waits.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let mut queue = Queue::seeded(deterministic(100.), deterministic(10.), 1, 0)
+            .with_class(deterministic(103.), deterministic(2.))
+            .with_srpt();
+        let mut events = Vec::new();
+        while queue.time() < 312. {
+            events.push(*queue.next_event());
         }
-    }
 
-    pub fn served_customer(&self) -> &Option<Customer> {
-        &self.served_customer
+        let preemptions = events.iter().filter(|event| event.event_type() == EventType::Preempted)
+            .map(|event| (event.time(), event.customer()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(103., 1), (206., 3)], preemptions);
+        let departures = events.iter()
+            .filter_map(|event| event.served_customer().map(|customer| (event.time(), customer.id(), customer.wait_in_system())))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(10., 0, 10.), (105., 2, 2.), (112., 1, 12.), (208., 4, 2.), (212., 3, 12.), (310., 5, 10.), (312., 6, 3.)],
+                   departures);
     }
-}
-
-pub struct CountAnalysis {
-    theory: theory::MMC,
-    sample_lambda: f64,
-    sample_mu: f64,
-    sample_w_q: f64,
-    sample_w: f64,
-    proportions: HashMap<u64, f64>,
-}
 
-impl CountAnalysis {
-    /// The expected values are only valid for M/M/1...
-    pub fn dump_proportions(&self) {
-        println!("n measured_p_n p_n");
-        for n in 0u64..self.proportions.len() as u64 {
-            println!("{} {} {}", n, self.proportions.get(&n).unwrap(), self.theory.p(n as u32));
-        }
+    #[test]
+    fn srpt_response_times_grow_with_job_size() {
+        let queue = Queue::seeded(exponential(0.7), exponential(1.), 1, 5).with_srpt();
+        let parameters = Parameters::new(exponential(0.7), exponential(1.), 1)
+            .with_discipline(Discipline::Srpt)
+            .with_preemption(Preemption::Resume);
+        let analysis = simulate_and_analyse(queue, &parameters, 400000);
+        let theory = analysis.srpt_theory.unwrap();
+        assert_approx_eq!(theory.wait_in_system(), analysis.sample_w, 0.1);
+        // Far below the 1 / (mu - lambda) = 3.33 of first come first served.
+        assert!(analysis.sample_w < 2.);
+        let slowdowns = analysis.sizes.range(-3..3).map(|(_, size)| size.slowdown_sum / size.n_served as f64).collect::<Vec<_>>();
+        assert!(slowdowns.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", slowdowns);
+        let (_, size) = analysis.sizes.range(1..2).next().unwrap();
+        assert_approx_eq!(theory.response_time(size.size_sum / size.n_served as f64), size.response_sum / size.n_served as f64, 0.2);
     }
 
-    pub fn dump_cross_sectional_statistics(&self) {
-        println!("lambda: sample = {}, input = {}", self.sample_lambda, self.theory.lambda);
-        println!("mu: sample = {}, input = {}", self.sample_mu, self.theory.mu);
+    #[test]
+    fn broken_down_servers_hold_their_customers() {
+        // The server breaks down after 5 seconds of serving, during the second customer, and takes 2 to repair.
+        let deterministic = |value| TimeDistribution::Deterministic { value };
+        let events = |interruption| {
+            let mut queue = Queue::seeded(deterministic(10.), deterministic(4.), 1, 0)
+                .with_breakdowns(deterministic(5.), deterministic(2.), interruption);
+            let mut events = Vec::new();
+            while queue.time() < 20. {
+                events.push(*queue.next_event());
+            }
+            events
+        };
+        let departures = |events: &[QueueEvent]| events.iter()
+            .filter_map(|event| event.served_customer().map(|customer| (event.time(), customer.wait_in_queue())))
+            .collect::<Vec<_>>();
 
-        // Steady state count of people in queue/system
-        let mut steady_customers_count = 0.;
-        for n in 0u64..self.proportions.len() as u64 {
-            steady_customers_count += n as f64 * self.proportions.get(&n).unwrap();
-        }
-        println!("Average number in system, L: sample = {}, expected = {}", steady_customers_count, self.theory.l());
+        let resumed = events(Interruption::Resume);
+        assert_eq!(vec![(4., 0.), (16., 2.)], departures(&resumed));
+        let changes = resumed.iter().filter(|event| event.event_type().is_server_change())
+            .map(|event| (event.event_type(), event.time(), event.server()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(EventType::Breakdown, 11., Some(0)), (EventType::ServerReturn, 13., Some(0))], changes);
 
-        let mut steady_queue_count = 0.;
-        for n in 1u64..self.proportions.len() as u64 {
-            steady_queue_count += (n - 1) as f64 * self.proportions.get(&n).unwrap();
+        // Starting over, the second customer is served for 4 seconds after the repair.
+        assert_eq!(vec![(4., 0.), (17., 3.)], departures(&events(Interruption::Restart)));
+    }
+
+    #[test]
+    fn departure_calendar_has_one_entry_per_busy_server() {
+        // With 1000 servers, each event only touches the top of a calendar of one departure per busy server, rather
+        // than scanning the servers, so it takes a logarithmic number of comparisons.
+        let servers = 1000;
+        let mut queue = Queue::seeded(Exp::new(0.9 * servers as f64).unwrap(), Exp::new(1.).unwrap(), servers, 0);
+        let mut busy = 0;
+        for _ in 0..50 * servers {
+            queue.next_event();
+            assert_eq!(queue.busy, queue.departures.len());
+            let earliest = queue.in_service.iter().filter_map(|customers| customers.first())
+                .map(|customer| customer.time_of_departure)
+                .fold(f64::INFINITY, f64::min);
+            assert_eq!(earliest, queue.next_departure().1);
+            busy = busy.max(queue.busy);
         }
-        println!("Average number in queue, L_q: sample = {}, expected = {}", steady_queue_count, self.theory.l_q());
+        assert!(busy as f64 > 0.75 * servers as f64, "The queue should have been busy.");
+    }
 
-        // Average waits.
-        println!("Average wait in Queue, W_q: sample = {}, expected = {}", self.sample_w_q, self.theory.wait_in_queue());
-        println!("Average wait in system, W: sample = {}, expected = {}", self.sample_w, self.theory.wait_in_system());
+    #[test]
+    #[ignore = "a timing benchmark, run with `cargo test --release -- --ignored`"]
+    fn event_rate_barely_drops_with_the_servers() {
+        let events_per_second = |servers: u32| {
+            let mut queue = Queue::seeded(Exp::new(0.9 * servers as f64).unwrap(), Exp::new(1.).unwrap(), servers, 0);
+            for _ in 0..100 * servers {
+                queue.next_event();
+            }
+            let events = 2000000;
+            let start = std::time::Instant::now();
+            for _ in 0..events {
+                queue.next_event();
+            }
+            events as f64 / start.elapsed().as_secs_f64()
+        };
 
-        println!();
-        println!("Little's Law:");
-        println!("L = W * lambda = {}, expected = {}", self.sample_w * self.sample_lambda, self.theory.l());
-        println!("L_q = W_q * lambda = {}, expected = {}", self.sample_w_q * self.sample_lambda, self.theory.l_q());
+        let (few, many) = (events_per_second(10), events_per_second(1000));
+        println!("{:.0} events/s with 10 servers, {:.0} events/s with 1000 servers", few, many);
+        // Scanning the servers would make the events 100 times slower; the calendars only add a few comparisons.
+        assert!(many > few / 4., "The events slowed down from {:.0}/s to {:.0}/s.", few, many);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,277 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A server selection policy decides which idle server serves a customer when more than one is idle.
+///
+/// The policy keeps track of the idle servers itself, so that each can use whatever structure makes its choice
+/// cheap. The `Queue` tells it when servers become idle and asks it for one when a customer starts service.
+pub trait ServerSelection {
+    /// The server became idle at `time`.
+    fn release(&mut self, server: usize, time: f64);
+
+    /// Returns an idle server to serve the next customer, which is no longer idle.
+    ///
+    /// # Arguments
+    /// * `rng` The random number generator of the queue, for policies that need one.
+    fn acquire(&mut self, rng: &mut dyn RngCore) -> usize;
+
+    /// The idle server went off duty, so it can't be acquired until it is released again.
+    fn withdraw(&mut self, server: usize);
+}
+
+impl<S: ServerSelection + ?Sized> ServerSelection for Box<S> {
+    fn release(&mut self, server: usize, time: f64) {
+        (**self).release(server, time)
+    }
+
+    fn acquire(&mut self, rng: &mut dyn RngCore) -> usize {
+        (**self).acquire(rng)
+    }
+
+    fn withdraw(&mut self, server: usize) {
+        (**self).withdraw(server)
+    }
+}
+
+/// The idle server with the lowest index, so the low numbered servers do most of the work.
+#[derive(Default)]
+pub struct LowestIndexFirst {
+    idle: BinaryHeap<Reverse<usize>>,
+}
+
+impl ServerSelection for LowestIndexFirst {
+    fn release(&mut self, server: usize, _time: f64) {
+        self.idle.push(Reverse(server));
+    }
+
+    fn acquire(&mut self, _rng: &mut dyn RngCore) -> usize {
+        self.idle.pop().expect("A server is only acquired when one is idle.").0
+    }
+
+    fn withdraw(&mut self, server: usize) {
+        self.idle.retain(|idle| idle.0 != server);
+    }
+}
+
+/// Any idle server, each as likely as the others.
+#[derive(Default)]
+pub struct RandomServer {
+    idle: Vec<usize>,
+}
+
+impl ServerSelection for RandomServer {
+    fn release(&mut self, server: usize, _time: f64) {
+        self.idle.push(server);
+    }
+
+    fn acquire(&mut self, rng: &mut dyn RngCore) -> usize {
+        assert!(!self.idle.is_empty(), "A server is only acquired when one is idle.");
+        let index = rng.gen_range(0..self.idle.len());
+        self.idle.swap_remove(index)
+    }
+
+    fn withdraw(&mut self, server: usize) {
+        self.idle.retain(|idle| *idle != server);
+    }
+}
+
+/// The server that has been idle the longest, which spreads the work evenly.
+///
+/// Time only moves forward, so servers become idle in order and the longest idle is the first in line.
+#[derive(Default)]
+pub struct LongestIdleFirst {
+    idle: VecDeque<usize>,
+}
+
+impl ServerSelection for LongestIdleFirst {
+    fn release(&mut self, server: usize, _time: f64) {
+        self.idle.push_back(server);
+    }
+
+    fn acquire(&mut self, _rng: &mut dyn RngCore) -> usize {
+        self.idle.pop_front().expect("A server is only acquired when one is idle.")
+    }
+
+    fn withdraw(&mut self, server: usize) {
+        self.idle.retain(|idle| *idle != server);
+    }
+}
+
+/// The fastest idle server, the one with the shortest mean service time, ties going to the lowest index.
+pub struct FastestFirst {
+    /// The rank of each server by speed, the fastest is 0.
+    ranks: Vec<usize>,
+    /// The idle servers by rank.
+    idle: BinaryHeap<Reverse<(usize, usize)>>,
+}
+
+impl FastestFirst {
+    /// # Arguments
+    /// * `mean_service_times` The mean service time of each server.
+    pub fn new(mean_service_times: &[f64]) -> FastestFirst {
+        let mut by_speed: Vec<usize> = (0..mean_service_times.len()).collect();
+        by_speed.sort_by(|a, b| mean_service_times[*a].total_cmp(&mean_service_times[*b]));
+        let mut ranks = vec![0; mean_service_times.len()];
+        for (rank, server) in by_speed.into_iter().enumerate() {
+            ranks[server] = rank;
+        }
+        FastestFirst { ranks, idle: BinaryHeap::new() }
+    }
+}
+
+impl ServerSelection for FastestFirst {
+    fn release(&mut self, server: usize, _time: f64) {
+        self.idle.push(Reverse((self.ranks[server], server)));
+    }
+
+    fn acquire(&mut self, _rng: &mut dyn RngCore) -> usize {
+        (self.idle.pop().expect("A server is only acquired when one is idle.").0).1
+    }
+
+    fn withdraw(&mut self, server: usize) {
+        self.idle.retain(|idle| (idle.0).1 != server);
+    }
+}
+
+/// Takes the servers in turn: the first idle server after the one that was chosen last, wrapping around.
+#[derive(Default)]
+pub struct RoundRobin {
+    idle: BTreeSet<usize>,
+    next: usize,
+}
+
+impl ServerSelection for RoundRobin {
+    fn release(&mut self, server: usize, _time: f64) {
+        self.idle.insert(server);
+    }
+
+    fn acquire(&mut self, _rng: &mut dyn RngCore) -> usize {
+        let server = *self.idle.range(self.next..).next()
+            .or_else(|| self.idle.iter().next())
+            .expect("A server is only acquired when one is idle.");
+        self.idle.remove(&server);
+        self.next = server + 1;
+        server
+    }
+
+    fn withdraw(&mut self, server: usize) {
+        self.idle.remove(&server);
+    }
+}
+
+/// The server selection policies that ship with the crate, so they can be chosen on the command line and recorded
+/// in the header of an event file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleServerPolicy {
+    #[default]
+    Lowest,
+    Random,
+    LongestIdle,
+    Fastest,
+    RoundRobin,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown server selection '{0}', expected one of lowest, random, longest-idle, fastest, round-robin")]
+pub struct UnknownServerSelection(String);
+
+impl IdleServerPolicy {
+    /// A new selection following this policy, with no idle servers yet.
+    ///
+    /// # Arguments
+    /// * `mean_service_times` The mean service time of each server, which only fastest first needs.
+    pub fn selection(self, mean_service_times: &[f64]) -> Box<dyn ServerSelection> {
+        match self {
+            IdleServerPolicy::Lowest => Box::<LowestIndexFirst>::default(),
+            IdleServerPolicy::Random => Box::<RandomServer>::default(),
+            IdleServerPolicy::LongestIdle => Box::<LongestIdleFirst>::default(),
+            IdleServerPolicy::Fastest => Box::new(FastestFirst::new(mean_service_times)),
+            IdleServerPolicy::RoundRobin => Box::<RoundRobin>::default(),
+        }
+    }
+}
+
+impl fmt::Display for IdleServerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IdleServerPolicy::Lowest => "lowest",
+            IdleServerPolicy::Random => "random",
+            IdleServerPolicy::LongestIdle => "longest-idle",
+            IdleServerPolicy::Fastest => "fastest",
+            IdleServerPolicy::RoundRobin => "round-robin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for IdleServerPolicy {
+    type Err = UnknownServerSelection;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "lowest" => Ok(IdleServerPolicy::Lowest),
+            "random" => Ok(IdleServerPolicy::Random),
+            "longest-idle" => Ok(IdleServerPolicy::LongestIdle),
+            "fastest" => Ok(IdleServerPolicy::Fastest),
+            "round-robin" => Ok(IdleServerPolicy::RoundRobin),
+            _ => Err(UnknownServerSelection(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::servers::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn policies_select_expected_server() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mean_service_times = [2., 1., 3.];
+        let mut lowest = IdleServerPolicy::Lowest.selection(&mean_service_times);
+        let mut longest_idle = IdleServerPolicy::LongestIdle.selection(&mean_service_times);
+        let mut fastest = IdleServerPolicy::Fastest.selection(&mean_service_times);
+        for (server, time) in [(2, 1.), (0, 2.), (1, 3.)] {
+            lowest.release(server, time);
+            longest_idle.release(server, time);
+            fastest.release(server, time);
+        }
+
+        assert_eq!(vec![0, 1, 2], (0..3).map(|_| lowest.acquire(&mut rng)).collect::<Vec<usize>>());
+        assert_eq!(vec![2, 0, 1], (0..3).map(|_| longest_idle.acquire(&mut rng)).collect::<Vec<usize>>());
+        assert_eq!(vec![1, 0, 2], (0..3).map(|_| fastest.acquire(&mut rng)).collect::<Vec<usize>>());
+
+        let mut round_robin = IdleServerPolicy::RoundRobin.selection(&mean_service_times);
+        for server in 0..3 {
+            round_robin.release(server, 0.);
+        }
+        assert_eq!(0, round_robin.acquire(&mut rng));
+        round_robin.release(0, 1.);
+        assert_eq!(vec![1, 2, 0], (0..3).map(|_| round_robin.acquire(&mut rng)).collect::<Vec<usize>>());
+
+        let mut random = IdleServerPolicy::Random.selection(&mean_service_times);
+        for server in 0..5 {
+            random.release(server, 0.);
+        }
+        let mut acquired: Vec<usize> = (0..5).map(|_| random.acquire(&mut rng)).collect();
+        acquired.sort_unstable();
+        assert_eq!(vec![0, 1, 2, 3, 4], acquired);
+    }
+
+    #[test]
+    fn parse_round_trips_through_display() {
+        for policy in [IdleServerPolicy::Lowest, IdleServerPolicy::Random, IdleServerPolicy::LongestIdle,
+                       IdleServerPolicy::Fastest, IdleServerPolicy::RoundRobin] {
+            assert_eq!(policy, policy.to_string().parse().unwrap());
+        }
+        assert!("busiest".parse::<IdleServerPolicy>().is_err());
+    }
+}
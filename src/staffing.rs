@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// What a server whose shift ends does with the customer it is serving.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShiftEnd {
+    /// The server finishes serving the customer, and only then goes off duty.
+    #[default]
+    Finish,
+    /// The server hands the customer back to the front of the queue, to resume their service with the next server.
+    Requeue,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown shift end '{0}', expected one of finish, requeue")]
+pub struct UnknownShiftEnd(String);
+
+impl fmt::Display for ShiftEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShiftEnd::Finish => write!(f, "finish"),
+            ShiftEnd::Requeue => write!(f, "requeue"),
+        }
+    }
+}
+
+impl FromStr for ShiftEnd {
+    type Err = UnknownShiftEnd;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "finish" => Ok(ShiftEnd::Finish),
+            "requeue" => Ok(ShiftEnd::Requeue),
+            _ => Err(UnknownShiftEnd(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StaffingError {
+    #[error("Failed to read the staffing schedule")]
+    Reading(#[from] std::io::Error),
+    #[error("Couldn't parse '{0}', expected a time in seconds and a number of servers")]
+    Parsing(String),
+    #[error("Invalid staffing schedule: {0}")]
+    Invalid(&'static str),
+}
+
+/// The number of servers on duty over time, like the shifts and lunch breaks of a service desk.
+///
+/// The number is given from points in time, the first at zero, and holds until the next point. When the schedule has
+/// a period it repeats, like a daily rota. Otherwise the last number holds for good. The intervals between the points
+/// are what the analysis is broken down by.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StaffingSchedule {
+    /// The times the number of servers changes, in seconds from the start of the schedule.
+    times: Vec<f64>,
+    /// The number of servers on duty from each of those times.
+    servers: Vec<u32>,
+    /// The length of the schedule in seconds when it repeats, like 86400 for a daily rota.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    period: Option<f64>,
+    #[serde(default)]
+    shift_end: ShiftEnd,
+}
+
+impl StaffingSchedule {
+    pub fn new(times: Vec<f64>, servers: Vec<u32>) -> Result<StaffingSchedule, StaffingError> {
+        if times.is_empty() || times.len() != servers.len() {
+            return Err(StaffingError::Invalid("there must be a number of servers for every time, and at least one"));
+        }
+        if times[0] != 0. {
+            return Err(StaffingError::Invalid("the first time must be zero"));
+        }
+        if times.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(StaffingError::Invalid("the times must be increasing"));
+        }
+        Ok(StaffingSchedule { times, servers, period: None, shift_end: ShiftEnd::Finish })
+    }
+
+    /// Repeats the schedule every `period` seconds, which must be after the last point.
+    pub fn with_period(self, period: f64) -> Result<StaffingSchedule, StaffingError> {
+        if !period.is_finite() || period <= *self.times.last().unwrap() {
+            return Err(StaffingError::Invalid("the period must end after the last time"));
+        }
+        Ok(StaffingSchedule { period: Some(period), ..self })
+    }
+
+    pub fn with_shift_end(self, shift_end: ShiftEnd) -> StaffingSchedule {
+        StaffingSchedule { shift_end, ..self }
+    }
+
+    /// Reads a schedule of one point per line, a time in seconds and the number of servers from then, separated by
+    /// whitespace. Blank lines and lines starting with # are skipped.
+    pub fn read<B: BufRead>(reader: B) -> Result<StaffingSchedule, StaffingError> {
+        let mut times = Vec::new();
+        let mut servers = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || StaffingError::Parsing(line.to_string());
+            let mut tokens = line.split_ascii_whitespace();
+            times.push(tokens.next().and_then(|token| token.parse::<f64>().ok()).ok_or_else(error)?);
+            servers.push(tokens.next().and_then(|token| token.parse::<u32>().ok()).ok_or_else(error)?);
+            if tokens.next().is_some() {
+                return Err(error());
+            }
+        }
+        StaffingSchedule::new(times, servers)
+    }
+
+    pub fn period(&self) -> Option<f64> {
+        self.period
+    }
+
+    pub fn shift_end(&self) -> ShiftEnd {
+        self.shift_end
+    }
+
+    /// The most servers ever on duty at once.
+    pub fn max_servers(&self) -> u32 {
+        self.servers.iter().copied().max().unwrap()
+    }
+
+    /// The number of intervals, one starting at each point.
+    pub fn intervals(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Where interval `i` starts and ends within the schedule. The last interval ends with the period, or never.
+    pub fn interval_bounds(&self, i: usize) -> (f64, f64) {
+        let end = self.times.get(i + 1).copied().or(self.period).unwrap_or(f64::INFINITY);
+        (self.times[i], end)
+    }
+
+    /// The number of servers on duty during interval `i`.
+    pub fn interval_servers(&self, i: usize) -> u32 {
+        self.servers[i]
+    }
+
+    /// The time within the schedule, which is `time` itself unless the schedule repeats.
+    fn phase(&self, time: f64) -> f64 {
+        self.period.map_or(time, |period| time.rem_euclid(period))
+    }
+
+    /// The interval that `time` falls in.
+    pub fn interval_at(&self, time: f64) -> usize {
+        let phase = self.phase(time);
+        self.times.partition_point(|&start| start <= phase).saturating_sub(1)
+    }
+
+    /// The number of servers on duty at `time`.
+    pub fn servers_at(&self, time: f64) -> u32 {
+        self.servers[self.interval_at(time)]
+    }
+
+    /// The time of the first change of interval after `time`, infinite if there are no more.
+    pub fn next_change(&self, time: f64) -> f64 {
+        let i = self.interval_at(time);
+        let (_, end) = self.interval_bounds(i);
+        time + (end - self.phase(time))
+    }
+
+    /// Calls `f` with each interval and the time spent in it between `from` and `to`, in order.
+    pub fn for_each_interval_between<F: FnMut(usize, f64)>(&self, from: f64, to: f64, mut f: F) {
+        let mut time = from;
+        while time < to {
+            let until = self.next_change(time).min(to);
+            f(self.interval_at(time), until - time);
+            time = until;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::staffing::*;
+
+    #[test]
+    fn schedule_repeats_and_changes_at_its_points() {
+        let file = "# time servers\n0 1\n30 3\n\n60 2\n";
+        let daily = StaffingSchedule::read(file.as_bytes()).unwrap().with_period(100.).unwrap();
+        assert_eq!(3, daily.max_servers());
+        assert_eq!(3, daily.servers_at(45.));
+        assert_eq!(1, daily.servers_at(110.));
+        assert_eq!(30., daily.next_change(0.));
+        assert_eq!(200., daily.next_change(160.));
+
+        let once = StaffingSchedule::read(file.as_bytes()).unwrap();
+        assert_eq!(2, once.servers_at(1000.));
+        assert_eq!(f64::INFINITY, once.next_change(60.));
+
+        let mut spent = vec![0.; 3];
+        daily.for_each_interval_between(10., 240., |i, duration| spent[i] += duration);
+        assert_eq!(vec![80., 70., 80.], spent);
+
+        assert!(StaffingSchedule::read("0 1\n10 two\n".as_bytes()).is_err());
+        assert!(StaffingSchedule::read("10 1\n".as_bytes()).is_err());
+        assert!("finish".parse::<ShiftEnd>().is_ok());
+        assert!("leave".parse::<ShiftEnd>().is_err());
+    }
+}
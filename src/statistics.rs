@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+
+/// The number of histogram bins per doubling of the wait.
+const BINS_PER_OCTAVE: f64 = 4.;
+
+/// Streaming summary of a distribution of waits (or any non-negative time).
+///
+/// Simulations run to millions of customers, so rather than keeping every sample this keeps the moments and a
+/// histogram with logarithmically spaced bins. Quantiles are then only known to within a bin, about 19%, which
+/// is plenty to compare the shapes of two distributions.
+#[derive(Clone, Debug, Default)]
+pub struct WaitStatistics {
+    n: u64,
+    sum: f64,
+    square_sum: f64,
+    max: f64,
+    /// Waits of exactly zero are common (customers served on arrival) and have no logarithm.
+    zeros: u64,
+    bins: BTreeMap<i32, u64>,
+}
+
+impl WaitStatistics {
+    pub fn add(&mut self, wait: f64) {
+        self.n += 1;
+        self.sum += wait;
+        self.square_sum += wait * wait;
+        if wait > self.max {
+            self.max = wait;
+        }
+
+        if wait <= 0. {
+            self.zeros += 1;
+        } else {
+            let bin = (wait.log2() * BINS_PER_OCTAVE).floor() as i32;
+            *self.bins.entry(bin).or_insert(0) += 1;
+        }
+    }
+
+    /// Adds all the samples of `other`.
+    pub fn merge(&mut self, other: &WaitStatistics) {
+        self.n += other.n;
+        self.sum += other.sum;
+        self.square_sum += other.square_sum;
+        self.max = self.max.max(other.max);
+        self.zeros += other.zeros;
+        for (bin, count) in &other.bins {
+            *self.bins.entry(*bin).or_insert(0) += count;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.n as f64
+    }
+
+    pub fn variance(&self) -> f64 {
+        self.square_sum / self.n as f64 - self.mean() * self.mean()
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// An upper bound on the `q` quantile, the upper edge of the bin it falls in.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let target = (q * self.n as f64).ceil() as u64;
+        let mut seen = self.zeros;
+        if seen >= target {
+            return 0.;
+        }
+        for (bin, count) in &self.bins {
+            seen += count;
+            if seen >= target {
+                return 2f64.powf((bin + 1) as f64 / BINS_PER_OCTAVE).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// A one line summary: count, mean, standard deviation and some quantiles.
+    pub fn summary(&self) -> String {
+        if self.n == 0 {
+            return "n = 0".to_string();
+        }
+        format!("n = {}, mean = {}, std = {}, p50 <= {}, p90 <= {}, p99 <= {}, max = {}",
+                self.n, self.mean(), self.variance().sqrt(),
+                self.quantile(0.5), self.quantile(0.9), self.quantile(0.99), self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::statistics::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn quantiles_bound_the_samples() {
+        let mut statistics = WaitStatistics::default();
+        for i in 0..1000 {
+            statistics.add(i as f64);
+        }
+
+        assert_eq!(1000, statistics.count());
+        assert_approx_eq!(499.5, statistics.mean(), 1.0e-12);
+        assert_eq!(0., statistics.quantile(0.001));
+        for q in [0.1, 0.5, 0.9, 0.99] {
+            let exact = q * 1000. - 1.;
+            let bound = statistics.quantile(q);
+            assert!(bound >= exact && bound <= exact * 2f64.powf(1. / BINS_PER_OCTAVE), "{} {}", q, bound);
+        }
+        assert_eq!(999., statistics.quantile(1.));
+    }
+}
@@ -38,14 +38,23 @@ pub trait QueueTheory {
     fn p(&self, n: u32) -> f64 {
         self.proportion(n)
     }
+
+    /// The fraction of the time each server is busy.
+    fn utilization(&self) -> f64;
+
+    /// The probability that an arriving customer finds the system full and is turned away. Zero unless the
+    /// capacity is finite.
+    fn blocking_probability(&self) -> f64 {
+        0.
+    }
 }
 
-fn fac(n: u8) -> f64 {
-    let mut fac = 1;
+fn fac(n: u32) -> f64 {
+    let mut fac = 1.;
     for i in 1..(n + 1) as u64 {
-        fac *= i;
+        fac *= i as f64;
     }
-    fac as f64
+    fac
 }
 
 fn faci(n: i32) -> f64 {
@@ -66,7 +75,7 @@ pub struct MMC {
 }
 
 impl MMC {
-    pub fn new(lambda: f64, mu: f64, servers: u8) -> MMC {
+    pub fn new(lambda: f64, mu: f64, servers: u32) -> MMC {
         let r = lambda / mu;
         let rho = r / servers as f64;
         let mut sum = 0.;
@@ -105,16 +114,20 @@ impl QueueTheory for MMC {
     }
 
     fn proportion(&self, n: u32) -> f64 {
-        return if (n as i32) < self.servers {
+        if (n as i32) < self.servers {
             self.r.powi(n as i32) / faci(n as i32) * self.p0
         } else {
             let pow = (self.servers as f64).powi(n as i32 - self.servers);
             self.r.powi(n as i32) * self.p0 / pow / faci(self.servers)
-        };
+        }
+    }
+
+    fn utilization(&self) -> f64 {
+        self.rho
     }
 }
 
-struct MMCK {
+pub struct MMCK {
     pub lambda: f64,
     pub mu: f64,
     pub servers: i32,
@@ -126,7 +139,7 @@ struct MMCK {
 }
 
 impl MMCK {
-    pub fn new(lambda: f64, mu: f64, servers: u8, queue_capacity: u32) -> MMCK {
+    pub fn new(lambda: f64, mu: f64, servers: u32, queue_capacity: u32) -> MMCK {
         let r = lambda / mu;
         let rho = r / servers as f64;
         let mut sum = 0.;
@@ -136,15 +149,15 @@ impl MMCK {
         let p0 = if rho != 1. {
             1. / (r.powi(servers as i32) / fac(servers) * (1. - rho.powi((queue_capacity + 1) as i32)) / (1. - rho) + sum)
         } else {
-            // 1. / (r.powi(servers as i32) / fac(servers) * ((queue_capacity + 1) as f64) + sum)
-            todo!("rho = 1 currently not fully supported. Terminating early.")
+            // The geometric sum is just K - c + 1 terms of one.
+            1. / (r.powi(servers as i32) / fac(servers) * ((queue_capacity + 1) as f64) + sum)
         };
         MMCK {
             lambda,
             mu,
             servers: servers as i32,
             queue_capacity,
-            capacity: servers as u32 + queue_capacity,
+            capacity: servers + queue_capacity,
             r,
             rho,
             p0,
@@ -158,12 +171,17 @@ impl QueueTheory for MMCK {
     }
 
     fn wait_in_system(&self) -> f64 {
+        // Little's law, with only the customers that join the system.
         let p_k = self.proportion(self.capacity);
-        (self.number_in_queue() + self.r * p_k) / (self.lambda * (1. - p_k))
+        self.number_in_system() / (self.lambda * (1. - p_k))
     }
 
     fn number_in_queue(&self) -> f64 {
-        // TODO: when rho = 1, we need to apply L'Hopital's rule twice.
+        if self.rho == 1. {
+            // L'Hopital's rule twice on the general expression.
+            let k_c = self.queue_capacity as f64;
+            return self.p0 * self.r.powi(self.servers) / faci(self.servers) * k_c * (k_c + 1.) / 2.;
+        }
         self.p0 * self.r.powi(self.servers) * self.rho / (faci(self.servers) * (1. - self.rho).powi(2)) *
             (
                 1. - self.rho.powi((self.queue_capacity + 1) as i32)
@@ -176,18 +194,797 @@ impl QueueTheory for MMCK {
     }
 
     fn proportion(&self, n: u32) -> f64 {
-        return if (n as i32) < self.servers {
+        if (n as i32) < self.servers {
             self.r.powi(n as i32) / faci(n as i32) * self.p0
         } else if n <= self.capacity {
             let pow = (self.servers as f64).powi(n as i32 - self.servers);
             self.r.powi(n as i32) * self.p0 / pow / faci(self.servers)
         } else {
             0.
+        }
+    }
+
+    fn utilization(&self) -> f64 {
+        // Only the customers that aren't blocked bring work.
+        self.rho * (1. - self.blocking_probability())
+    }
+
+    fn blocking_probability(&self) -> f64 {
+        self.proportion(self.capacity)
+    }
+}
+
+/// The M/M/c//N queue, the machine repair model: a population of N customers, each of which arrives at rate lambda
+/// while outside the system, so the arrival rate is lambda (N - n) with n in the system.
+pub struct MMCN {
+    pub lambda: f64,
+    pub mu: f64,
+    pub servers: u32,
+    pub population: u32,
+    /// p_n for n from 0 to N.
+    proportions: Vec<f64>,
+}
+
+impl MMCN {
+    /// # Arguments
+    /// * `lambda` The arrival rate of each customer outside the system, like the rate a machine breaks down.
+    /// * `mu` The service rate of each server.
+    /// * `servers` The number of servers, c.
+    /// * `population` The number of customers, N.
+    pub fn new(lambda: f64, mu: f64, servers: u32, population: u32) -> MMCN {
+        // A birth and death process, p_n = p_(n-1) lambda (N - n + 1) / (mu min(n, c)). The ratios are multiplied
+        // out rather than using factorials, which overflow for large populations.
+        let mut proportions = vec![1.];
+        for n in 1..=population {
+            let ratio = lambda * (population - n + 1) as f64 / (mu * n.min(servers) as f64);
+            proportions.push(proportions[n as usize - 1] * ratio);
+        }
+        let total: f64 = proportions.iter().sum();
+        for p in &mut proportions {
+            *p /= total;
+        }
+        MMCN { lambda, mu, servers, population, proportions }
+    }
+
+    /// The rate at which customers arrive, lambda (N - L).
+    pub fn effective_arrival_rate(&self) -> f64 {
+        self.lambda * (self.population as f64 - self.number_in_system())
+    }
+}
+
+impl QueueTheory for MMCN {
+    fn number_in_system(&self) -> f64 {
+        self.proportions.iter().enumerate().map(|(n, p)| n as f64 * p).sum()
+    }
+
+    fn wait_in_system(&self) -> f64 {
+        // Little's law, at the effective arrival rate.
+        self.number_in_system() / self.effective_arrival_rate()
+    }
+
+    fn number_in_queue(&self) -> f64 {
+        self.proportions.iter().enumerate().skip(self.servers as usize).map(|(n, p)| (n - self.servers as usize) as f64 * p).sum()
+    }
+
+    fn wait_in_queue(&self) -> f64 {
+        self.number_in_queue() / self.effective_arrival_rate()
+    }
+
+    fn proportion(&self, n: u32) -> f64 {
+        self.proportions.get(n as usize).copied().unwrap_or(0.)
+    }
+
+    fn utilization(&self) -> f64 {
+        self.effective_arrival_rate() / (self.servers as f64 * self.mu)
+    }
+}
+
+/// The M/M/2 queue with heterogeneous servers: server 0 serves at rate `mu_0` and server 1 at rate `mu_1`, first come
+/// first served. A customer arriving to an empty system goes to server 0 with probability `p_0`, and otherwise to
+/// server 1. A customer arriving when one server is busy goes to the other.
+///
+/// With one customer in the system it matters which server is busy, so the states are 0, (1, server 0 busy),
+/// (1, server 1 busy), then n >= 2 where both are busy and the chain is a birth and death process with rate mu_0 + mu_1.
+#[derive(Clone, Copy, Debug)]
+pub struct MM2Heterogeneous {
+    pub lambda: f64,
+    pub mu_0: f64,
+    pub mu_1: f64,
+    /// The probability that a customer arriving to an empty system is served by server 0.
+    pub p_0: f64,
+    rho: f64,
+    p_empty: f64,
+    /// The proportions with one customer in the system, served by server 0 and by server 1.
+    p_1_0: f64,
+    p_1_1: f64,
+    p_2: f64,
+}
+
+impl MM2Heterogeneous {
+    pub fn new(lambda: f64, mu_0: f64, mu_1: f64, p_0: f64) -> MM2Heterogeneous {
+        let rho = lambda / (mu_0 + mu_1);
+        // Solve relative to p_empty = 1. The balance of the states with one customer gives them in terms of p_2:
+        //   (lambda + mu_0) p_1_0 = lambda p_0 + mu_1 p_2
+        //   (lambda + mu_1) p_1_1 = lambda (1 - p_0) + mu_0 p_2
+        // and the cut between one and two customers, lambda (p_1_0 + p_1_1) = (mu_0 + mu_1) p_2, gives p_2.
+        let p_2 = lambda * lambda * (p_0 / (lambda + mu_0) + (1. - p_0) / (lambda + mu_1))
+            / (mu_0 + mu_1 - lambda * mu_1 / (lambda + mu_0) - lambda * mu_0 / (lambda + mu_1));
+        let p_1_0 = (lambda * p_0 + mu_1 * p_2) / (lambda + mu_0);
+        let p_1_1 = (lambda * (1. - p_0) + mu_0 * p_2) / (lambda + mu_1);
+        let total = 1. + p_1_0 + p_1_1 + p_2 / (1. - rho);
+        MM2Heterogeneous {
+            lambda,
+            mu_0,
+            mu_1,
+            p_0,
+            rho,
+            p_empty: 1. / total,
+            p_1_0: p_1_0 / total,
+            p_1_1: p_1_1 / total,
+            p_2: p_2 / total,
+        }
+    }
+
+    /// The fraction of the time each server is busy, which differ unless the servers are equally fast.
+    pub fn server_utilizations(&self) -> [f64; 2] {
+        let both_busy = self.p_2 / (1. - self.rho);
+        [self.p_1_0 + both_busy, self.p_1_1 + both_busy]
+    }
+}
+
+impl QueueTheory for MM2Heterogeneous {
+    fn number_in_system(&self) -> f64 {
+        // The sum of n rho^(n - 2) from n = 2 is (2 - rho) / (1 - rho)^2.
+        self.p_1_0 + self.p_1_1 + self.p_2 * (2. - self.rho) / (1. - self.rho).powi(2)
+    }
+
+    fn wait_in_system(&self) -> f64 {
+        self.number_in_system() / self.lambda
+    }
+
+    fn number_in_queue(&self) -> f64 {
+        self.p_2 * self.rho / (1. - self.rho).powi(2)
+    }
+
+    fn wait_in_queue(&self) -> f64 {
+        self.number_in_queue() / self.lambda
+    }
+
+    fn proportion(&self, n: u32) -> f64 {
+        match n {
+            0 => self.p_empty,
+            1 => self.p_1_0 + self.p_1_1,
+            _ => self.p_2 * self.rho.powi(n as i32 - 2),
+        }
+    }
+
+    fn utilization(&self) -> f64 {
+        let [utilization_0, utilization_1] = self.server_utilizations();
+        (utilization_0 + utilization_1) / 2.
+    }
+}
+
+/// The M^X/M/1 queue: batches arrive at rate `lambda`, each of X customers with `P(X = k)` at index `k - 1` of
+/// `batch_sizes`, and a single exponential server serves them one at a time at rate `mu`.
+///
+/// The means come from the generating function of the proportions, L = rho / (1 - rho) (E[X^2] + E[X]) / (2 E[X])
+/// with rho = lambda E[X] / mu. The proportions themselves have no closed form, they come from the balance across
+/// the cut between n and n + 1 customers: mu p_(n+1) = lambda sum_(k<=n) p_k P(X > n - k).
+#[derive(Clone, Debug)]
+pub struct MXM1 {
+    /// The rate at which batches arrive.
+    pub lambda: f64,
+    pub mu: f64,
+    pub batch_sizes: Vec<f64>,
+    rho: f64,
+    mean_batch_size: f64,
+    second_moment: f64,
+}
+
+impl MXM1 {
+    pub fn new(lambda: f64, mu: f64, batch_sizes: Vec<f64>) -> MXM1 {
+        let mean_batch_size = batch_sizes.iter().enumerate().map(|(i, p)| (i + 1) as f64 * p).sum();
+        let second_moment = batch_sizes.iter().enumerate().map(|(i, p)| ((i + 1) * (i + 1)) as f64 * p).sum();
+        MXM1 { lambda, mu, rho: lambda * mean_batch_size / mu, batch_sizes, mean_batch_size, second_moment }
+    }
+
+    /// The rate at which customers arrive, lambda E[X].
+    pub fn customer_arrival_rate(&self) -> f64 {
+        self.lambda * self.mean_batch_size
+    }
+
+    /// P(X > k), the probability that a batch is bigger than `k`.
+    fn larger_batch(&self, k: usize) -> f64 {
+        1. - self.batch_sizes.iter().take(k).sum::<f64>()
+    }
+}
+
+impl QueueTheory for MXM1 {
+    fn number_in_system(&self) -> f64 {
+        self.rho / (1. - self.rho) * (self.second_moment + self.mean_batch_size) / (2. * self.mean_batch_size)
+    }
+
+    fn wait_in_system(&self) -> f64 {
+        self.number_in_system() / self.customer_arrival_rate()
+    }
+
+    fn number_in_queue(&self) -> f64 {
+        self.number_in_system() - self.rho
+    }
+
+    fn wait_in_queue(&self) -> f64 {
+        self.number_in_queue() / self.customer_arrival_rate()
+    }
+
+    fn proportion(&self, n: u32) -> f64 {
+        let mut proportions = vec![1. - self.rho];
+        for m in 0..n as usize {
+            let crossing: f64 = proportions.iter().enumerate().map(|(k, p)| p * self.larger_batch(m - k)).sum();
+            proportions.push(self.lambda * crossing / self.mu);
+        }
+        proportions[n as usize]
+    }
+
+    fn utilization(&self) -> f64 {
+        self.rho
+    }
+}
+
+/// An open Jackson network: stations of identical exponential servers, Poisson arrivals from outside at any of them,
+/// and after service a customer at station i goes to station j with probability `routing[i][j]`, or leaves with
+/// what is left over.
+///
+/// The total arrival rates come from the traffic equations, lambda_j = gamma_j + sum_i lambda_i routing[i][j]. By
+/// the product form solution every station then behaves as an M/M/c queue on its own at that rate.
+pub struct JacksonNetwork {
+    pub external_arrival_rates: Vec<f64>,
+    pub routing: Vec<Vec<f64>>,
+    /// The total arrival rate at each station, lambda_j, external and routed.
+    pub arrival_rates: Vec<f64>,
+    stations: Vec<MMC>,
+}
+
+impl JacksonNetwork {
+    /// # Arguments
+    /// * `external_arrival_rates` The rate of arrivals from outside at each station, gamma_j. Zero for none.
+    /// * `service_rates` The rate of service at each server of each station, mu_j.
+    /// * `servers` The number of servers at each station, c_j.
+    /// * `routing` The probability of going from each station to each station after service.
+    pub fn new(external_arrival_rates: Vec<f64>, service_rates: Vec<f64>, servers: Vec<u32>, routing: Vec<Vec<f64>>) -> JacksonNetwork {
+        let n = external_arrival_rates.len();
+        assert!(service_rates.len() == n && servers.len() == n && routing.len() == n,
+                "Every station needs an external arrival rate, a service rate, servers and a row of routing.");
+        let arrival_rates = solve_traffic_equations(&external_arrival_rates, &routing);
+        let stations = (0..n).map(|j| MMC::new(arrival_rates[j], service_rates[j], servers[j])).collect();
+        JacksonNetwork { external_arrival_rates, routing, arrival_rates, stations }
+    }
+
+    /// The theory of a station on its own.
+    pub fn station(&self, station: usize) -> &MMC {
+        &self.stations[station]
+    }
+
+    /// Whether every station can keep up with its arrivals, so the network has a steady state.
+    pub fn is_stable(&self) -> bool {
+        self.stations.iter().all(|station| station.utilization() < 1.)
+    }
+
+    /// The average number of visits a customer makes to a station.
+    pub fn visits(&self, station: usize) -> f64 {
+        self.arrival_rates[station] / self.external_arrival_rates.iter().sum::<f64>()
+    }
+
+    /// The number of customers in the whole network, L.
+    pub fn number_in_system(&self) -> f64 {
+        self.stations.iter().map(MMC::number_in_system).sum()
+    }
+
+    /// The average time a customer spends in the network, from arriving at their first station to leaving their last.
+    pub fn wait_in_system(&self) -> f64 {
+        // Little's law over the whole network.
+        self.number_in_system() / self.external_arrival_rates.iter().sum::<f64>()
+    }
+}
+
+/// Solves lambda = gamma + routing^T lambda.
+fn solve_traffic_equations(external_arrival_rates: &[f64], routing: &[Vec<f64>]) -> Vec<f64> {
+    let n = external_arrival_rates.len();
+    // The augmented matrix of (I - routing^T) lambda = gamma.
+    let a = (0..n)
+        .map(|j| {
+            let mut row: Vec<f64> = (0..n).map(|i| if i == j { 1. } else { 0. } - routing[i][j]).collect();
+            row.push(external_arrival_rates[j]);
+            row
+        })
+        .collect();
+    solve_linear_system(a).expect("Customers must be able to leave the network from every station.")
+}
+
+/// Solves the linear system of the augmented matrix `a`, n rows of n coefficients and the right hand side, by
+/// Gaussian elimination with partial pivoting. `None` when the system is singular.
+fn solve_linear_system(mut a: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let n = a.len();
+    for column in 0..n {
+        let pivot = (column..n).max_by(|x, y| a[*x][column].abs().total_cmp(&a[*y][column].abs())).unwrap();
+        a.swap(column, pivot);
+        if a[column][column].abs() <= 1.0e-12 {
+            return None;
+        }
+        let pivot_row = a[column].clone();
+        for row in &mut a[(column + 1)..] {
+            let factor = row[column] / pivot_row[column];
+            for (value, pivot_value) in row.iter_mut().zip(&pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+        }
+    }
+    let mut x = vec![0.; n];
+    for row in (0..n).rev() {
+        let known: f64 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (a[row][n] - known) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Exact Mean Value Analysis of a closed network: a fixed population circulates between the stations forever.
+///
+/// Each station is either a single server queue, first come first served with exponential service, or a delay
+/// station where no one waits (a server for everyone, like machines running or users thinking). The results are
+/// built up one customer at a time from the arrival theorem: a customer arriving at a station sees the mean queue
+/// of the network with one customer fewer.
+///
+/// These aren't `QueueTheory` since the interesting results are per station.
+pub struct MeanValueAnalysis {
+    pub population: u32,
+    /// The mean total service a customer needs from each station per cycle, its visits times the mean service time.
+    pub service_demands: Vec<f64>,
+    pub delay: Vec<bool>,
+    /// The rate at which customers complete a cycle, X.
+    pub throughput: f64,
+    /// The mean time a customer spends at each station per cycle, R_k.
+    pub residence_times: Vec<f64>,
+    /// The mean number of customers at each station, Q_k.
+    pub queue_lengths: Vec<f64>,
+}
+
+impl MeanValueAnalysis {
+    /// # Arguments
+    /// * `visits` The mean number of visits a customer makes to each station per cycle, relative to a reference
+    ///   station that they visit once.
+    /// * `service_times` The mean service time at each station.
+    /// * `delay` Whether each station is a delay station, otherwise it is a single server queue.
+    /// * `population` The number of customers, N.
+    pub fn new(visits: Vec<f64>, service_times: Vec<f64>, delay: Vec<bool>, population: u32) -> MeanValueAnalysis {
+        assert!(visits.len() == service_times.len() && delay.len() == visits.len(),
+                "Every station needs visits, a service time and whether it is a delay.");
+        let service_demands: Vec<f64> = visits.iter().zip(&service_times).map(|(v, s)| v * s).collect();
+        let mut throughput = 0.;
+        let mut residence_times = vec![0.; visits.len()];
+        let mut queue_lengths = vec![0.; visits.len()];
+        for n in 1..=population {
+            for k in 0..visits.len() {
+                residence_times[k] = if delay[k] { service_demands[k] } else { service_demands[k] * (1. + queue_lengths[k]) };
+            }
+            throughput = n as f64 / residence_times.iter().sum::<f64>();
+            for (queue_length, residence_time) in queue_lengths.iter_mut().zip(&residence_times) {
+                *queue_length = throughput * residence_time;
+            }
+        }
+        MeanValueAnalysis { population, service_demands, delay, throughput, residence_times, queue_lengths }
+    }
+
+    /// The time for a customer to go round the whole network once.
+    pub fn cycle_time(&self) -> f64 {
+        self.residence_times.iter().sum()
+    }
+
+    /// The fraction of the time the server of a queueing station is busy, or the mean number in service at a delay.
+    pub fn utilization(&self, station: usize) -> f64 {
+        self.throughput * self.service_demands[station]
+    }
+}
+
+/// The M/M/1 queue with priority classes, class 0 having the highest priority, and first come first served within
+/// each class. Each class has its own arrival and service rate.
+///
+/// These aren't `QueueTheory` since the interesting results are per class.
+pub struct MM1Priority {
+    pub lambdas: Vec<f64>,
+    pub mus: Vec<f64>,
+    /// Whether higher priority customers preempt lower priority ones (preemptive-resume), or wait for the server.
+    pub preemptive: bool,
+}
+
+impl MM1Priority {
+    pub fn new(lambdas: Vec<f64>, mus: Vec<f64>, preemptive: bool) -> MM1Priority {
+        assert_eq!(lambdas.len(), mus.len(), "Every class needs an arrival and a service rate.");
+        MM1Priority { lambdas, mus, preemptive }
+    }
+
+    /// sigma_k, the utilization due to classes 0 to k inclusive. sigma_(-1) is zero.
+    fn sigma(&self, class: i64) -> f64 {
+        (0..=class).map(|i| self.lambdas[i as usize] / self.mus[i as usize]).sum()
+    }
+
+    /// The average wait in the queue of a class, W_q^(k).
+    pub fn wait_in_queue(&self, class: usize) -> f64 {
+        self.wait_in_system(class) - 1. / self.mus[class]
+    }
+
+    /// The average wait in the system of a class, W^(k).
+    pub fn wait_in_system(&self, class: usize) -> f64 {
+        let k = class as i64;
+        let denominator = (1. - self.sigma(k - 1)) * (1. - self.sigma(k));
+        if self.preemptive {
+            // Only the classes with at least this priority matter, lower ones are invisible.
+            let residual: f64 = (0..=class).map(|i| self.lambdas[i] / (self.mus[i] * self.mus[i])).sum();
+            1. / self.mus[class] / (1. - self.sigma(k - 1)) + residual / denominator
+        } else {
+            // The residual service of whoever is in service, of any class, since they can't be interrupted.
+            let residual: f64 = self.lambdas.iter().zip(&self.mus).map(|(lambda, mu)| lambda / (mu * mu)).sum();
+            residual / denominator + 1. / self.mus[class]
+        }
+    }
+}
+
+/// M/G/1 with server vacations and breakdowns, which only has closed forms for the means.
+///
+/// Breakdowns happen at `failure_rate` while serving, and the service resumes after the repair. A customer then
+/// holds the server for a completion time C, with E[C] = E[S] (1 + xi E[R]) and
+/// E[C^2] = E[S^2] (1 + xi E[R])^2 + xi E[S] E[R^2] for failure rate xi and repair time R, and the queue is M/G/1
+/// with C as its service time.
+///
+/// By the decomposition of vacation models, the wait in the queue is then that of M/G/1, lambda E[C^2] / (2 (1 - rho)),
+/// plus the wait due to the vacations: E[V^2] / (2 E[V]) with multiple vacations, the residual of the vacation an
+/// arrival finds, and lambda E[V^2] / (2 (V*(lambda) + lambda E[V])) with single vacations, where V*(lambda) is the
+/// probability that no one arrives during a vacation.
+///
+/// As in the simulation, the wait in the queue is all the time a customer isn't being served, so it includes the
+/// repairs of their server while they hold it: W_q = W - E[S].
+///
+/// These aren't `QueueTheory` since there is no closed form for the proportions.
+#[derive(Clone, Copy, Debug)]
+pub struct MG1Interruptions {
+    pub lambda: f64,
+    service_mean: f64,
+    /// The mean and second moment of the completion time, which is the service time without breakdowns.
+    completion_mean: f64,
+    completion_second_moment: f64,
+    /// The mean wait due to the vacations.
+    vacation_wait: f64,
+}
+
+impl MG1Interruptions {
+    /// M/G/1 with service times of the given mean and second moment, and no interruptions yet.
+    pub fn new(lambda: f64, service_mean: f64, service_second_moment: f64) -> MG1Interruptions {
+        MG1Interruptions {
+            lambda,
+            service_mean,
+            completion_mean: service_mean,
+            completion_second_moment: service_second_moment,
+            vacation_wait: 0.,
+        }
+    }
+
+    /// The server takes multiple vacations of the given mean and second moment whenever the system empties.
+    pub fn with_multiple_vacations(self, vacation_mean: f64, vacation_second_moment: f64) -> MG1Interruptions {
+        MG1Interruptions { vacation_wait: vacation_second_moment / (2. * vacation_mean), ..self }
+    }
+
+    /// The server takes a single vacation of the given mean and second moment whenever the system empties, and
+    /// `no_arrival` is the probability that no one arrives during one, V*(lambda).
+    pub fn with_single_vacations(self, vacation_mean: f64, vacation_second_moment: f64, no_arrival: f64) -> MG1Interruptions {
+        let vacation_wait = self.lambda * vacation_second_moment / (2. * (no_arrival + self.lambda * vacation_mean));
+        MG1Interruptions { vacation_wait, ..self }
+    }
+
+    /// The server breaks down at `failure_rate` while serving, and is repaired in a time of the given mean and second
+    /// moment, after which the service resumes.
+    pub fn with_breakdowns(self, failure_rate: f64, repair_mean: f64, repair_second_moment: f64) -> MG1Interruptions {
+        let stretch = 1. + failure_rate * repair_mean;
+        MG1Interruptions {
+            completion_mean: self.completion_mean * stretch,
+            completion_second_moment: self.completion_second_moment * stretch * stretch
+                + failure_rate * self.completion_mean * repair_second_moment,
+            ..self
+        }
+    }
+
+    /// The fraction of the time the server holds a customer, serving them or being repaired.
+    pub fn utilization(&self) -> f64 {
+        self.lambda * self.completion_mean
+    }
+
+    pub fn wait_in_queue(&self) -> f64 {
+        self.wait_in_system() - self.service_mean
+    }
+
+    pub fn wait_in_system(&self) -> f64 {
+        let delay = self.lambda * self.completion_second_moment / (2. * (1. - self.utilization()));
+        delay + self.vacation_wait + self.completion_mean
+    }
+
+    pub fn number_in_queue(&self) -> f64 {
+        self.lambda * self.wait_in_queue()
+    }
+
+    pub fn number_in_system(&self) -> f64 {
+        self.lambda * self.wait_in_system()
+    }
+}
+
+/// The M/M/1 queue under shortest remaining processing time first (SRPT): the customer with the least service left is
+/// served, preempting the one in service when they arrive needing less.
+///
+/// A job of size x sees only the work of smaller jobs, so with rho_x = lambda E[S; S <= x] the load of jobs up to x,
+/// its mean response time is (Schrage and Miller)
+///
+/// T(x) = lambda (E[S^2; S <= x] + x^2 P(S > x)) / (2 (1 - rho_x)^2) + integral from 0 to x of dt / (1 - rho_t),
+///
+/// the wait for the work ahead of it when it arrives plus its residence time, its own service slowed down by the small
+/// jobs that arrive while it is being served. For exponential service the truncated moments have closed forms, and the
+/// integrals are taken numerically. Small jobs barely notice the queue, while the slowdown T(x) / x of the largest
+/// goes up to 1 / (1 - rho), as under processor sharing.
+///
+/// These aren't `QueueTheory` since the interesting results are by job size.
+#[derive(Clone, Copy, Debug)]
+pub struct MM1Srpt {
+    pub lambda: f64,
+    pub mu: f64,
+}
+
+impl MM1Srpt {
+    pub fn new(lambda: f64, mu: f64) -> MM1Srpt {
+        assert!(lambda < mu, "SRPT is only stable while rho is below one.");
+        MM1Srpt { lambda, mu }
+    }
+
+    /// rho_x, the load of the jobs of size at most x.
+    fn load_up_to(&self, x: f64) -> f64 {
+        let mu_x = self.mu * x;
+        self.lambda / self.mu * (1. - (-mu_x).exp() * (1. + mu_x))
+    }
+
+    /// The mean response time, from arrival to departure, of a job of size `x`.
+    pub fn response_time(&self, x: f64) -> f64 {
+        let mu_x = self.mu * x;
+        let tail = (-mu_x).exp();
+        let truncated_second_moment = 2. / (self.mu * self.mu) * (1. - tail * (1. + mu_x + mu_x * mu_x / 2.));
+        let rho_x = self.load_up_to(x);
+        let waiting = self.lambda * (truncated_second_moment + x * x * tail) / (2. * (1. - rho_x) * (1. - rho_x));
+        let residence = simpson(|t| 1. / (1. - self.load_up_to(t)), 0., x, 512);
+        waiting + residence
+    }
+
+    /// The mean slowdown of a job of size `x`, its response time over its size.
+    pub fn slowdown(&self, x: f64) -> f64 {
+        self.response_time(x) / x
+    }
+
+    /// The mean response time over all jobs, W, integrating T(x) over the exponential sizes up to where what is left
+    /// is negligible.
+    pub fn wait_in_system(&self) -> f64 {
+        simpson(|x| self.response_time(x) * self.mu * (-self.mu * x).exp(), 0., 40. / self.mu, 2048)
+    }
+}
+
+/// The integral of `f` from `a` to `b` by Simpson's rule over `n` intervals, n even.
+fn simpson<F: Fn(f64) -> f64>(f: F, a: f64, b: f64, n: usize) -> f64 {
+    let h = (b - a) / n as f64;
+    let inner: f64 = (1..n).map(|i| if i % 2 == 1 { 4. } else { 2. } * f(a + i as f64 * h)).sum();
+    (f(a) + inner + f(b)) * h / 3.
+}
+
+/// The M/M/1 retrial queue: a customer who finds the server busy doesn't wait, but joins an orbit and tries again
+/// after an exponential time of rate `retrial_rate`, theta, for as long as it takes. There is no queue, the customers
+/// in the system that aren't being served are in the orbit, so L_q is the mean orbit size and W_q the time spent in it.
+///
+/// With a = lambda / theta, the server is idle with j in orbit with probability (1 - rho)^(a + 1) (a)_j rho^j / j!
+/// and busy with (1 - rho)^(a + 1) (a + 1)_j rho^(j + 1) / j!, where (a)_j is the rising factorial. The mean orbit
+/// size is then rho (rho + a) / (1 - rho): that of M/M/1 plus what the customers lose by not being there when the
+/// server frees up, which vanishes as theta grows.
+#[derive(Clone, Copy, Debug)]
+pub struct MM1Retrial {
+    pub lambda: f64,
+    pub mu: f64,
+    pub retrial_rate: f64,
+    rho: f64,
+}
+
+impl MM1Retrial {
+    pub fn new(lambda: f64, mu: f64, retrial_rate: f64) -> MM1Retrial {
+        MM1Retrial { lambda, mu, retrial_rate, rho: lambda / mu }
+    }
+
+    /// The mean number of times a customer tries for the server, counting their arrival: one plus the retrials they
+    /// make at rate theta while in the orbit.
+    pub fn attempts(&self) -> f64 {
+        1. + self.retrial_rate * self.wait_in_queue()
+    }
+}
+
+impl QueueTheory for MM1Retrial {
+    fn number_in_system(&self) -> f64 {
+        self.number_in_queue() + self.rho
+    }
+
+    fn wait_in_system(&self) -> f64 {
+        self.number_in_system() / self.lambda
+    }
+
+    fn number_in_queue(&self) -> f64 {
+        self.rho * (self.rho + self.lambda / self.retrial_rate) / (1. - self.rho)
+    }
+
+    fn wait_in_queue(&self) -> f64 {
+        self.number_in_queue() / self.lambda
+    }
+
+    fn proportion(&self, n: u32) -> f64 {
+        // n in orbit with the server idle, plus n - 1 in orbit with it busy, built up a term at a time.
+        let a = self.lambda / self.retrial_rate;
+        let mut idle = (1. - self.rho).powf(a + 1.);
+        let mut busy = idle * self.rho;
+        if n == 0 {
+            return idle;
+        }
+        for j in 1..n {
+            idle *= (a + (j - 1) as f64) * self.rho / j as f64;
+            busy *= (a + j as f64) * self.rho / j as f64;
+        }
+        idle *= (a + (n - 1) as f64) * self.rho / n as f64;
+        idle + busy
+    }
+
+    fn utilization(&self) -> f64 {
+        self.rho
+    }
+}
+
+/// The M/M/c retrial queue, like `MM1Retrial` but with `servers` servers. It has no closed form, so it is solved
+/// numerically as a Markov chain of the busy servers i and the orbit size j, with the orbit cut off where it is too
+/// unlikely to matter. The truncated chain turns away arrivals that would make the orbit any bigger.
+///
+/// The chain is solved a level j at a time by linear level reduction. Only arrivals to busy servers go up a level,
+/// so p_(j+1) = lambda p_(c, j) r_(j+1), where r_(j+1) is the last row of the inverse of minus the generator of the
+/// levels above j, censored on level j + 1. These come from the top level down, and p_0 from its balance equations.
+#[derive(Clone, Debug)]
+pub struct MMCRetrial {
+    pub lambda: f64,
+    pub mu: f64,
+    pub servers: u32,
+    pub retrial_rate: f64,
+    /// The stationary probability of each state, indexed by the orbit size and then the number of busy servers.
+    probabilities: Vec<Vec<f64>>,
+}
+
+impl MMCRetrial {
+    pub fn new(lambda: f64, mu: f64, servers: u32, retrial_rate: f64) -> MMCRetrial {
+        assert!(lambda < servers as f64 * mu, "The retrial queue only has a steady state when lambda < c mu.");
+        // Rates relative to mu, so that the probabilities don't depend on the unit of time.
+        let (a, theta) = (lambda / mu, retrial_rate / mu);
+        let mut orbit_limit = 64;
+        let probabilities = loop {
+            let probabilities = MMCRetrial::solve(a, theta, servers as usize, orbit_limit);
+            if probabilities[orbit_limit].iter().sum::<f64>() < 1.0e-14 {
+                break probabilities;
+            }
+            orbit_limit *= 2;
+        };
+        MMCRetrial { lambda, mu, servers, retrial_rate, probabilities }
+    }
+
+    /// The stationary probabilities of the chain with at most `orbit_limit` in orbit, and rates relative to mu.
+    fn solve(a: f64, theta: f64, c: usize, orbit_limit: usize) -> Vec<Vec<f64>> {
+        // The generator within level j, including in the diagonal the rates of leaving it.
+        let within = |j: usize| {
+            let mut q = vec![vec![0.; c + 1]; c + 1];
+            for i in 0..=c {
+                if i < c {
+                    q[i][i + 1] = a;
+                    q[i][i] -= a + j as f64 * theta;
+                } else if j < orbit_limit {
+                    q[i][i] -= a;
+                }
+                if i > 0 {
+                    q[i][i - 1] = i as f64;
+                    q[i][i] -= i as f64;
+                }
+            }
+            q
+        };
+        // The generator of level j censored on it, given r_(j+1): the retrials to level j + 1 that came back to level j
+        // by way of an arrival to busy servers, in the last row.
+        let censored = |j: usize, above: Option<&Vec<f64>>| {
+            let mut q = within(j);
+            if let Some(r) = above {
+                for k in 1..=c {
+                    q[c][k] += a * r[k - 1] * (j + 1) as f64 * theta;
+                }
+            }
+            q
         };
+
+        // r_j solves x (-Q_j) = e_c, the last row of the inverse, from the top level down.
+        let mut rows: Vec<Vec<f64>> = vec![Vec::new(); orbit_limit + 1];
+        for j in (1..=orbit_limit).rev() {
+            let q = censored(j, rows.get(j + 1).filter(|r| !r.is_empty()));
+            let system = (0..=c)
+                .map(|k| (0..=c).map(|i| -q[i][k]).chain(std::iter::once(if k == c { 1. } else { 0. })).collect())
+                .collect();
+            rows[j] = solve_linear_system(system).expect("The levels above the first are transient, so invertible.");
+        }
+
+        // p_0 Q_0 = 0, with one of the balance equations replaced by p_0 summing to one, to be normalized later.
+        let q = censored(0, rows.get(1));
+        let system = (0..=c)
+            .map(|k| {
+                if k == c {
+                    vec![1.; c + 2]
+                } else {
+                    (0..=c).map(|i| q[i][k]).chain(std::iter::once(0.)).collect()
+                }
+            })
+            .collect();
+        let mut probabilities = vec![solve_linear_system(system).expect("The chain is irreducible.")];
+        for row in rows.iter().skip(1) {
+            let busy = probabilities.last().unwrap()[c];
+            probabilities.push(row.iter().map(|r| a * busy * r).collect());
+        }
+        let total: f64 = probabilities.iter().flatten().sum();
+        for level in &mut probabilities {
+            for p in level.iter_mut() {
+                *p /= total;
+            }
+        }
+        probabilities
+    }
+
+    /// The mean number of times a customer tries for a server, counting their arrival: one plus the retrials they
+    /// make at rate theta while in the orbit.
+    pub fn attempts(&self) -> f64 {
+        1. + self.retrial_rate * self.wait_in_queue()
+    }
+
+    /// The mean of `f(i, j)` over the stationary distribution, for i busy servers and j in orbit.
+    fn mean<F: Fn(usize, usize) -> f64>(&self, f: F) -> f64 {
+        self.probabilities.iter().enumerate()
+            .flat_map(|(j, level)| level.iter().enumerate().map(move |(i, p)| (i, j, p)))
+            .map(|(i, j, p)| f(i, j) * p)
+            .sum()
+    }
+}
+
+impl QueueTheory for MMCRetrial {
+    fn number_in_system(&self) -> f64 {
+        self.mean(|i, j| (i + j) as f64)
+    }
+
+    fn wait_in_system(&self) -> f64 {
+        self.number_in_system() / self.lambda
+    }
+
+    fn number_in_queue(&self) -> f64 {
+        self.mean(|_, j| j as f64)
+    }
+
+    fn wait_in_queue(&self) -> f64 {
+        self.number_in_queue() / self.lambda
+    }
+
+    fn proportion(&self, n: u32) -> f64 {
+        self.probabilities.iter().enumerate()
+            .filter_map(|(j, level)| (n as usize).checked_sub(j).and_then(|i| level.get(i)))
+            .sum()
+    }
+
+    fn utilization(&self) -> f64 {
+        self.mean(|i, _| i as f64) / self.servers as f64
     }
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)] // Tests are named after the examples in *Fundamentals*.
 mod tests {
     use crate::theory::*;
     use assert_approx_eq::assert_approx_eq;
@@ -212,4 +1009,188 @@ mod tests {
         // I think the additional error here comes from using L (number in system), which is already rounded.
         assert_approx_eq!(12.3, mm37.wait_in_system(), 6.2e-2);
     }
+
+    #[test]
+    fn priorities_conserve_work() {
+        // With equal service rates, the average wait over all classes is the same as without priorities.
+        let mm1 = MMC::new(0.9, 1., 1);
+        let lambdas = vec![0.2, 0.3, 0.4];
+        let priority = MM1Priority::new(lambdas.clone(), vec![1.; 3], false);
+        let average: f64 = lambdas.iter().enumerate().map(|(k, lambda)| lambda * priority.wait_in_queue(k)).sum::<f64>() / 0.9;
+        assert_approx_eq!(mm1.wait_in_queue(), average, 1.0e-12);
+
+        // With preemption the highest class doesn't see anyone else: it's M/M/1 on its own.
+        let preemptive = MM1Priority::new(lambdas, vec![1.; 3], true);
+        assert_approx_eq!(MMC::new(0.2, 1., 1).wait_in_system(), preemptive.wait_in_system(0), 1.0e-12);
+        // And the average over classes is still M/M/1, since the service is memoryless.
+        let average: f64 = [0.2, 0.3, 0.4].iter().enumerate().map(|(k, lambda)| lambda * preemptive.wait_in_system(k)).sum::<f64>() / 0.9;
+        assert_approx_eq!(mm1.wait_in_system(), average, 1.0e-12);
+    }
+
+    #[test]
+    fn heterogeneous_mm2_with_equal_servers_is_mm2() {
+        let mm2 = MMC::new(1.5, 1., 2);
+        let heterogeneous = MM2Heterogeneous::new(1.5, 1., 1., 1.);
+        for n in 0..5 {
+            assert_approx_eq!(mm2.proportion(n), heterogeneous.proportion(n), 1.0e-15);
+        }
+        assert_approx_eq!(mm2.number_in_system(), heterogeneous.number_in_system(), 1.0e-14);
+        assert_approx_eq!(mm2.wait_in_queue(), heterogeneous.wait_in_queue(), 1.0e-14);
+        assert_approx_eq!(mm2.utilization(), heterogeneous.utilization(), 1.0e-15);
+    }
+
+    #[test]
+    fn heterogeneous_mm2_favours_the_fast_server() {
+        let fastest_first = MM2Heterogeneous::new(1., 2., 1., 1.);
+        let slowest_first = MM2Heterogeneous::new(1., 2., 1., 0.);
+        let total: f64 = (0..200).map(|n| fastest_first.proportion(n)).sum();
+        assert_approx_eq!(1., total, 1.0e-12);
+        // Either way the servers do all the work: 2 u_0 + u_1 = lambda.
+        for theory in [fastest_first, slowest_first] {
+            let [utilization_0, utilization_1] = theory.server_utilizations();
+            assert_approx_eq!(1., 2. * utilization_0 + utilization_1, 1.0e-12);
+        }
+        assert!(fastest_first.wait_in_system() < slowest_first.wait_in_system());
+    }
+
+    #[test]
+    fn interruptions_add_to_mg1() {
+        // Without interruptions, exponential service is M/M/1.
+        let mm1 = MMC::new(0.5, 1., 1);
+        let plain = MG1Interruptions::new(0.5, 1., 2.);
+        assert_approx_eq!(mm1.wait_in_queue(), plain.wait_in_queue(), 1.0e-15);
+        assert_approx_eq!(mm1.number_in_system(), plain.number_in_system(), 1.0e-15);
+
+        // Deterministic multiple vacations of 4 add half a vacation, the residual an arrival finds.
+        let multiple = MG1Interruptions::new(0.5, 1., 2.).with_multiple_vacations(4., 16.);
+        assert_approx_eq!(mm1.wait_in_queue() + 2., multiple.wait_in_queue(), 1.0e-15);
+        // Single vacations add less, since the server can be found idle.
+        let single = MG1Interruptions::new(0.5, 1., 2.).with_single_vacations(4., 16., (-2f64).exp());
+        assert!(single.wait_in_queue() < multiple.wait_in_queue());
+        // Vacations so short that no one arrives during them make no difference.
+        let instant = MG1Interruptions::new(0.5, 1., 2.).with_single_vacations(1.0e-9, 1.0e-18, 1.);
+        assert_approx_eq!(mm1.wait_in_queue(), instant.wait_in_queue(), 1.0e-9);
+
+        // Breakdowns stretch the service, the server holds a customer for longer.
+        let breakdowns = MG1Interruptions::new(0.5, 1., 2.).with_breakdowns(0.1, 2., 8.);
+        assert_approx_eq!(0.6, breakdowns.utilization(), 1.0e-15);
+        assert!(breakdowns.wait_in_queue() > mm1.wait_in_queue());
+    }
+
+    #[test]
+    fn single_server_retrials_agree_with_the_closed_form() {
+        let closed_form = MM1Retrial::new(0.7, 1., 0.5);
+        let numerical = MMCRetrial::new(0.7, 1., 1, 0.5);
+        assert_approx_eq!(closed_form.number_in_queue(), numerical.number_in_queue(), 1.0e-10);
+        assert_approx_eq!(closed_form.wait_in_system(), numerical.wait_in_system(), 1.0e-10);
+        assert_approx_eq!(0.7, numerical.utilization(), 1.0e-10);
+        for n in 0..20 {
+            assert_approx_eq!(closed_form.proportion(n), numerical.proportion(n), 1.0e-12);
+        }
+        assert_approx_eq!(1., (0..400).map(|n| closed_form.proportion(n)).sum::<f64>(), 1.0e-12);
+        assert_approx_eq!(closed_form.attempts(), numerical.attempts(), 1.0e-10);
+    }
+
+    #[test]
+    fn fast_retrials_are_mmc() {
+        let mm3 = MMC::new(2.4, 1., 3);
+        let retrials = MMCRetrial::new(2.4, 1., 3, 1.0e4);
+        assert_approx_eq!(mm3.number_in_queue(), retrials.number_in_queue(), 1.0e-3);
+        assert_approx_eq!(mm3.utilization(), retrials.utilization(), 1.0e-10);
+        for n in 0..10 {
+            assert_approx_eq!(mm3.proportion(n), retrials.proportion(n), 1.0e-3);
+        }
+        // Slower retrials leave more in the orbit, but trying that often takes many more attempts.
+        let slow = MMCRetrial::new(2.4, 1., 3, 0.5);
+        assert!(slow.number_in_queue() > retrials.number_in_queue());
+        assert!(retrials.attempts() > slow.attempts());
+    }
+
+    #[test]
+    fn srpt_favours_small_jobs_without_starving_large_ones() {
+        let (lambda, mu) = (0.7, 1.);
+        let srpt = MM1Srpt::new(lambda, mu);
+        let mm1 = MMC::new(lambda, mu, 1);
+        // Better than first come first served (or processor sharing, with the same mean) on average.
+        assert!(srpt.wait_in_system() < mm1.wait_in_system());
+        assert!(srpt.wait_in_system() > 1. / mu);
+        // Tiny jobs go straight through, and the largest are slowed down as under processor sharing.
+        assert_approx_eq!(1., srpt.slowdown(1.0e-6), 1.0e-5);
+        assert_approx_eq!(1. / (1. - lambda / mu), srpt.slowdown(1000.), 0.01);
+        assert!(srpt.slowdown(1.) < srpt.slowdown(5.));
+        // Hardly anyone to get in the way.
+        assert_approx_eq!(1. / mu, MM1Srpt::new(1.0e-6, mu).wait_in_system(), 1.0e-5);
+    }
+
+    #[test]
+    fn batches_of_one_are_mm1() {
+        let mm1 = MMC::new(0.8, 1., 1);
+        let batches = MXM1::new(0.8, 1., vec![1.]);
+        for n in 0..10 {
+            assert_approx_eq!(mm1.proportion(n), batches.proportion(n), 1.0e-15);
+        }
+        assert_approx_eq!(mm1.number_in_system(), batches.number_in_system(), 1.0e-14);
+        assert_approx_eq!(mm1.wait_in_queue(), batches.wait_in_queue(), 1.0e-14);
+    }
+
+    #[test]
+    fn batch_proportions_agree_with_the_mean() {
+        // Batches of two or three at 0.3 a time unit, rho = 0.75.
+        let batches = MXM1::new(0.3, 1., vec![0., 0.5, 0.5]);
+        let proportions: Vec<f64> = (0..400).map(|n| batches.proportion(n)).collect();
+        assert_approx_eq!(1., proportions.iter().sum::<f64>(), 1.0e-12);
+        let l: f64 = proportions.iter().enumerate().map(|(n, p)| n as f64 * p).sum();
+        assert_approx_eq!(batches.number_in_system(), l, 1.0e-10);
+        // (E[X^2] + E[X]) / (2 E[X]) = (6.5 + 2.5) / 5.
+        assert_approx_eq!(3. * 9. / 5., batches.number_in_system(), 1.0e-14);
+    }
+
+    #[test]
+    fn traffic_equations_count_every_visit() {
+        // A tandem of two stations where a quarter of the customers leaving the second go back to the first.
+        let network = JacksonNetwork::new(vec![1., 0.], vec![3., 2.], vec![1, 1], vec![vec![0., 1.], vec![0.25, 0.]]);
+        // lambda_0 = 1 + lambda_1 / 4 and lambda_1 = lambda_0, so both are 4 / 3.
+        assert_approx_eq!(4. / 3., network.arrival_rates[0], 1.0e-15);
+        assert_approx_eq!(4. / 3., network.arrival_rates[1], 1.0e-15);
+        assert_approx_eq!(4. / 3., network.visits(1), 1.0e-15);
+        assert!(network.is_stable());
+
+        let l = MMC::new(4. / 3., 3., 1).number_in_system() + MMC::new(4. / 3., 2., 1).number_in_system();
+        assert_approx_eq!(l, network.number_in_system(), 1.0e-15);
+        assert_approx_eq!(l, network.wait_in_system(), 1.0e-15);
+    }
+
+    #[test]
+    fn machine_repair_is_a_closed_network() {
+        // Five machines running for 10 on average between breakdowns, and one repairer taking 2.
+        let mm15 = MMCN::new(0.1, 0.5, 1, 5);
+        let total: f64 = (0..=5).map(|n| mm15.proportion(n)).sum();
+        assert_approx_eq!(1., total, 1.0e-15);
+        assert_eq!(0., mm15.proportion(6));
+
+        // The same as machines cycling between a delay (running) and a queue (repair).
+        let mva = MeanValueAnalysis::new(vec![1., 1.], vec![10., 2.], vec![true, false], 5);
+        assert_approx_eq!(mm15.effective_arrival_rate(), mva.throughput, 1.0e-12);
+        assert_approx_eq!(mm15.number_in_system(), mva.queue_lengths[1], 1.0e-12);
+        assert_approx_eq!(mm15.wait_in_system(), mva.residence_times[1], 1.0e-12);
+        assert_approx_eq!(1. - mm15.proportion(0), mva.utilization(1), 1.0e-12);
+        assert_approx_eq!(5., mva.queue_lengths.iter().sum::<f64>(), 1.0e-12);
+
+        // With as many repairers as machines no one waits.
+        let mm55 = MMCN::new(0.1, 0.5, 5, 5);
+        assert_approx_eq!(0., mm55.number_in_queue(), 1.0e-15);
+        assert_approx_eq!(2., mm55.wait_in_system(), 1.0e-12);
+    }
+
+    #[test]
+    fn mmck_at_rho_one_is_uniform_beyond_the_servers() {
+        // With one server and rho = 1 every state is equally likely.
+        let mm14 = MMCK::new(1., 1., 1, 3);
+        for n in 0..=4 {
+            assert_approx_eq!(0.2, mm14.proportion(n), 1.0e-15);
+        }
+        assert_approx_eq!(0.2, mm14.blocking_probability(), 1.0e-15);
+        // L_q = 1 * 0.2 + 2 * 0.2 + 3 * 0.2
+        assert_approx_eq!(1.2, mm14.number_in_queue(), 1.0e-15);
+    }
 }
\ No newline at end of file